@@ -0,0 +1,205 @@
+use crate::matching::{markdown_files, normalize};
+use crate::note_exports::{content_hash, NoteExport};
+use crate::readwise::Book;
+use crate::transaction::{render_note, VaultTransaction};
+use crate::{targets, Library};
+use anyhow::Context as _;
+use chrono::Utc;
+use inquire::Confirm;
+use itertools::Itertools;
+use obsidian_rust_interface::joining::strategies::TypeAndKey;
+use obsidian_rust_interface::Vault;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, info, warn};
+
+const HIGHLIGHTS_BEGIN: &str = "%% HIGHLIGHTS_BEGIN %%";
+
+/// An unmanaged note and the book it most likely holds notes on.
+struct Candidate<'a> {
+    note: PathBuf,
+    book: &'a Book,
+    confidence: f64,
+}
+
+fn similarity(a: &str, b: &str) -> f64 {
+    strsim::normalized_levenshtein(&normalize(a), &normalize(b))
+}
+
+/// How confident we are that a note with this title, and author if its frontmatter has one, is
+/// about the book. Titles count for more than authors, whose formatting varies more.
+fn confidence(title: &str, author: Option<&str>, book: &Book) -> f64 {
+    let title = similarity(title, &book.title);
+    match (author, &book.author) {
+        (Some(author), Some(book_author)) => 0.75 * title + 0.25 * similarity(author, book_author),
+        _ => title,
+    }
+}
+
+/// Read a note as its frontmatter, empty if it has none, and contents.
+pub(crate) fn read_note(note: &Path) -> anyhow::Result<(serde_yml::Mapping, String)> {
+    let contents = std::fs::read_to_string(note)?;
+    let Some((frontmatter, body)) = contents
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.split_once("---\n"))
+    else {
+        return Ok((serde_yml::Mapping::new(), contents));
+    };
+
+    let metadata = serde_yml::from_str::<Option<serde_yml::Mapping>>(frontmatter)
+        .with_context(|| format!("Note {:?} has invalid frontmatter", note))?;
+    Ok((metadata.unwrap_or_default(), body.to_string()))
+}
+
+/// A note's frontmatter and contents with the exporter's frontmatter linking it to a book added.
+pub(crate) fn linked_note(note: &Path, book_id: i32) -> anyhow::Result<(serde_yml::Value, String)> {
+    let (mut metadata, contents) = read_note(note)?;
+    metadata.insert("note-kind".into(), "readwise".into());
+    metadata.insert("__readwise_fk".into(), book_id.into());
+
+    // Without the token, updating the note would replace its contents with the highlights
+    let contents = match contents.contains(HIGHLIGHTS_BEGIN) {
+        true => contents,
+        false => format!("{}\n\n{}\n", contents.trim_end(), HIGHLIGHTS_BEGIN),
+    };
+
+    Ok((serde_yml::Value::Mapping(metadata), contents))
+}
+
+/// The title and author of a note, from its frontmatter if present, or else its filename.
+fn note_title_and_author(note: &Path) -> anyhow::Result<(String, Option<String>)> {
+    let (metadata, _) = read_note(note)?;
+    let field = |key: &str| metadata.get(key).and_then(|v| v.as_str()).map(String::from);
+
+    let title = field("title")
+        .or_else(|| note.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_default();
+
+    Ok((title, field("author")))
+}
+
+/// Link notes in `folder` of the vault, or anywhere in it, which weren't written by the exporter
+/// to the books they match with at least `threshold` confidence, asking before each unless `yes`.
+/// Linked notes get the exporter's frontmatter, and their contents are kept above the highlights
+/// future exports add. Notes which can't be read are left alone.
+pub fn adopt(
+    library: &mut Library,
+    vault_root: &Path,
+    folder: Option<&Path>,
+    threshold: f64,
+    yes: bool,
+) -> anyhow::Result<()> {
+    let managed = obsidian_rust_interface::joining::find_by::<_, i32>(
+        &Vault::open(vault_root),
+        &TypeAndKey {
+            type_key: "note-kind".to_string(),
+            note_type: "readwise".to_string(),
+            id_key: "__readwise_fk".to_string(),
+        },
+    );
+
+    let managed_paths = managed
+        .values()
+        .map(|n| n.to_path_buf())
+        .collect::<HashSet<_>>();
+
+    let mut notes = vec![];
+    markdown_files(
+        &folder.map_or(vault_root.to_path_buf(), |f| vault_root.join(f)),
+        &mut notes,
+    )?;
+    notes.retain(|note| {
+        !managed_paths.contains(note)
+            && !note.strip_prefix(vault_root).is_ok_and(|relative| {
+                relative.components().any(|c| match c {
+                    Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+                    _ => false,
+                })
+            })
+    });
+
+    debug!("Found {} unmanaged notes", notes.len());
+
+    // Books merged into another are exported as part of it, and books with a note keep it
+    let books = library
+        .books
+        .iter()
+        .filter(|book| !library.book_merges.contains_key(&book.id))
+        .filter(|book| !managed.contains_key(&book.id))
+        .collect_vec();
+
+    let mut candidates = vec![];
+    for note in notes {
+        let (title, author) = match note_title_and_author(&note) {
+            Ok(found) => found,
+            Err(err) => {
+                warn!("Skipping {:?}, which couldn't be read: {:#}", note, err);
+                continue;
+            }
+        };
+        let best = books
+            .iter()
+            .map(|book| (book, confidence(&title, author.as_deref(), book)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        if let Some((book, confidence)) = best.filter(|(_, c)| *c >= threshold) {
+            candidates.push(Candidate {
+                note,
+                book,
+                confidence,
+            });
+        }
+    }
+
+    // The most confident match for a book wins it
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let mut transaction = VaultTransaction::begin(
+        vault_root,
+        targets::open_target(vault_root, None, None, None, false, false)?,
+    )?;
+    let mut adopted = HashSet::new();
+    let mut exports = vec![];
+    for candidate in candidates {
+        if adopted.contains(&candidate.book.id) {
+            continue;
+        }
+
+        let prompt = format!(
+            "Link {:?} to '{}' by {} ({:.0}% match)?",
+            candidate
+                .note
+                .strip_prefix(vault_root)
+                .unwrap_or(&candidate.note),
+            candidate.book.title,
+            candidate.book.author.as_deref().unwrap_or("unknown author"),
+            candidate.confidence * 100.0
+        );
+
+        if !yes && !Confirm::new(&prompt).with_default(true).prompt()? {
+            continue;
+        }
+
+        let (metadata, contents) = linked_note(&candidate.note, candidate.book.id)?;
+
+        info!(
+            "Adopting {:?} for book {} ({})",
+            candidate.note, candidate.book.id, candidate.book.title
+        );
+        transaction.stage_note(&candidate.note, &metadata, &contents)?;
+        exports.push((
+            candidate.book.id,
+            NoteExport {
+                path: candidate.note.strip_prefix(vault_root)?.to_path_buf(),
+                hash: content_hash(&render_note(&metadata, &contents)?),
+                exported_at: Utc::now(),
+            },
+        ));
+        adopted.insert(candidate.book.id);
+    }
+
+    transaction.commit()?;
+    library.note_exports.extend(exports);
+    println!("Adopted {} notes", adopted.len());
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::Exporter;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// The names, besides its filename, a note is given as Obsidian aliases.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum AliasKind {
+    /// The title as given by Readwise, before characters not allowed in filenames are removed
+    Title,
+
+    /// The book's `readable_title`, where it differs from its title
+    ReadableTitle,
+
+    /// `Author - Title`
+    AuthorTitle,
+
+    /// No aliases besides the display title
+    None,
+}
+
+/// Add an alias to the `aliases` frontmatter of a note, unless it already has it.
+pub fn add_alias(metadata: &mut serde_yml::Mapping, alias: &str) {
+    let aliases = metadata
+        .entry(serde_yml::Value::from("aliases"))
+        .or_insert_with(|| serde_yml::Value::Sequence(vec![]));
+
+    if let Some(aliases) = aliases.as_sequence_mut() {
+        let alias = serde_yml::Value::from(alias);
+        if !aliases.contains(&alias) {
+            aliases.push(alias);
+        }
+    }
+}
+
+impl Exporter {
+    /// Add the configured aliases for a note with this title, author, and readable title. Aliases
+    /// which are the same as the note's filename are left out, as Obsidian finds it by that anyway.
+    pub(crate) fn add_aliases(
+        &self,
+        metadata: &mut serde_yml::Mapping,
+        filename: &str,
+        title: &str,
+        author: Option<&str>,
+        readable_title: Option<&str>,
+    ) {
+        let candidates = self.aliases.iter().filter_map(|kind| match kind {
+            AliasKind::Title => Some(title.to_string()),
+            AliasKind::ReadableTitle => readable_title.map(str::to_string),
+            AliasKind::AuthorTitle => author.map(|author| format!("{} - {}", author, title)),
+            AliasKind::None => None,
+        });
+
+        for alias in candidates {
+            let alias = alias.trim();
+            if !alias.is_empty() && alias != filename {
+                add_alias(metadata, alias);
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+/// Elements which are never part of an article's body.
+const NOISE_ELEMENTS: &str =
+    "script, style, noscript, template, nav, aside, footer, header, form, iframe, button, svg";
+
+/// Class and id fragments marking page furniture around the article, as readability does.
+const NOISE_HINTS: [&str; 10] = [
+    "comment",
+    "share",
+    "social",
+    "related",
+    "promo",
+    "sidebar",
+    "newsletter",
+    "subscribe",
+    "advert",
+    "cookie",
+];
+
+/// Elements which, if present, hold the article itself.
+const ARTICLE_ELEMENTS: &str = "article, main, [role=main]";
+
+/// Whether an element's class or id suggests it is page furniture rather than content.
+fn looks_like_noise(element: &ElementRef) -> bool {
+    if matches!(element.value().name(), "html" | "body" | "article" | "main") {
+        return false;
+    }
+
+    let hints = element
+        .value()
+        .classes()
+        .chain(element.value().id())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+
+    hints
+        .iter()
+        .any(|hint| NOISE_HINTS.iter().any(|noise| hint.contains(noise)))
+}
+
+/// The element holding the article: an `<article>` or `<main>` if there is one, otherwise the
+/// element with the most paragraph text directly inside it, falling back to the body.
+fn article_root(html: &Html) -> ElementRef<'_> {
+    if let Some(article) = html
+        .select(&Selector::parse(ARTICLE_ELEMENTS).unwrap())
+        .next()
+    {
+        return article;
+    }
+
+    let mut scores: HashMap<_, usize> = HashMap::new();
+    for paragraph in html.select(&Selector::parse("p").unwrap()) {
+        if let Some(parent) = paragraph.parent().and_then(ElementRef::wrap) {
+            *scores.entry(parent.id()).or_default() +=
+                paragraph.text().map(str::len).sum::<usize>();
+        }
+    }
+
+    scores
+        .into_iter()
+        .max_by_key(|(id, score)| (*score, *id))
+        .and_then(|(id, _)| html.tree.get(id))
+        .and_then(ElementRef::wrap)
+        .or_else(|| html.select(&Selector::parse("body").unwrap()).next())
+        .unwrap_or_else(|| html.root_element())
+}
+
+/// Extract the article body from a document's HTML, dropping navigation, scripts, and other page
+/// furniture, and convert it to markdown.
+pub fn readable_markdown(html: &str) -> String {
+    let mut html = Html::parse_document(html);
+
+    let noise = Selector::parse(NOISE_ELEMENTS).unwrap();
+    let everything = Selector::parse("*").unwrap();
+    let removed = html
+        .select(&noise)
+        .chain(html.select(&everything).filter(looks_like_noise))
+        .map(|element| element.id())
+        .collect::<Vec<_>>();
+
+    for id in removed {
+        if let Some(mut node) = html.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+
+    let markdown = html2md::parse_html(&article_root(&html).inner_html());
+
+    // Collapse the runs of blank lines left behind by removed elements
+    markdown
+        .lines()
+        .map(str::trim_end)
+        .fold(Vec::<&str>::new(), |mut lines, line| {
+            if !(line.is_empty() && lines.last().is_none_or(|l| l.is_empty())) {
+                lines.push(line);
+            }
+            lines
+        })
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Quote markdown into a collapsed Obsidian callout of a kind (e.g. `abstract`) with the given
+/// title.
+pub fn collapsed_callout(kind: &str, title: &str, markdown: &str) -> String {
+    let body = markdown
+        .lines()
+        .map(|line| match line {
+            "" => ">".to_string(),
+            line => format!("> {}", line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("> [!{}]- {}\n{}", kind, title, body)
+}
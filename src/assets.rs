@@ -0,0 +1,228 @@
+use crate::note_exports::content_hash;
+use crate::readwise::{self, Document};
+use crate::Exporter;
+use regex::{Captures, Regex};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tracing::{info, instrument, warn};
+
+static MARKDOWN_IMAGE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"!\[([^\]]*)\]\((https?://[^)\s]+)\)").unwrap());
+
+#[derive(Deserialize)]
+struct AppConfig {
+    #[serde(rename = "attachmentFolderPath")]
+    attachment_folder_path: Option<String>,
+}
+
+/// Where downloaded images are cached by default, `assets` beside the library cache, so they are
+/// only downloaded once whichever vault they are exported to.
+pub fn default_cache_dir(library: &Path) -> PathBuf {
+    library.with_file_name("assets")
+}
+
+/// The vault's "Default location for new attachments" from `.obsidian/app.json`, the vault root
+/// if unset. A path starting `./` is relative to the folder of the note.
+pub fn vault_attachment_folder(vault_root: &Path) -> String {
+    std::fs::File::open(vault_root.join(".obsidian").join("app.json"))
+        .ok()
+        .and_then(|file| serde_json::from_reader::<_, AppConfig>(file).ok())
+        .and_then(|config| config.attachment_folder_path)
+        .unwrap_or_else(|| "/".to_string())
+}
+
+/// The remote images referenced by a document: its thumbnail, those in its stored HTML, and those
+/// in the highlights and notes made on it.
+fn document_images(documents: &[Document], document: &Document) -> Vec<String> {
+    let mut images = document.image_url.iter().cloned().collect::<Vec<_>>();
+
+    if let Some(html) = &document.html_content {
+        let html = Html::parse_document(html);
+        let selector = Selector::parse("img[src]").unwrap();
+        images.extend(
+            html.select(&selector)
+                .filter_map(|img| img.value().attr("src"))
+                .map(str::to_string),
+        );
+    }
+
+    let children = documents
+        .iter()
+        .filter(|child| child.parent_id.as_deref() == Some(document.id.as_str()));
+    for child in children {
+        for text in [&child.content, &child.notes].into_iter().flatten() {
+            images.extend(MARKDOWN_IMAGE.captures_iter(text).map(|c| c[2].to_string()));
+        }
+    }
+
+    images.retain(|url| url.starts_with("http://") || url.starts_with("https://"));
+    images
+}
+
+/// The extension of a downloaded image, from its url if it has a plausible one, otherwise from
+/// the content type it was served with.
+fn image_extension(url: &str, content_type: Option<&str>) -> String {
+    let from_url = Url::parse(url).ok().and_then(|url| {
+        Path::new(url.path())
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .filter(|e| e.len() <= 5 && e.chars().all(|c| c.is_ascii_alphanumeric()))
+    });
+
+    from_url
+        .or_else(|| {
+            content_type
+                .and_then(|t| t.strip_prefix("image/"))
+                .map(|t| t.split([';', '+']).next().unwrap_or(t).trim().to_string())
+        })
+        .unwrap_or_else(|| "img".to_string())
+}
+
+impl Exporter {
+    /// Download the images referenced by Reader documents into the asset cache, skipping those
+    /// already cached. Images which fail to download are left as remote references.
+    #[instrument(skip_all)]
+    pub(crate) async fn download_images(&mut self, cache: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(cache)?;
+
+        let urls = self
+            .library
+            .documents
+            .iter()
+            .filter(|document| document.parent_id.is_none())
+            .flat_map(|document| document_images(&self.library.documents, document))
+            .filter(|url| {
+                self.library
+                    .assets
+                    .get(url)
+                    .is_none_or(|file| !cache.join(file).is_file())
+            })
+            .collect::<HashSet<_>>();
+
+        info!("Downloading {} images into {:?}", urls.len(), cache);
+
+        let client = readwise::http_client();
+        for url in urls {
+            let response = match readwise::send(client.get(&url))
+                .await
+                .and_then(|r| r.error_for_status())
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    warn!("Failed to download image {}: {}", url, err);
+                    continue;
+                }
+            };
+
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|t| t.to_str().ok())
+                .map(str::to_string);
+            let bytes = match response.bytes().await {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    warn!("Failed to download image {}: {}", url, err);
+                    continue;
+                }
+            };
+
+            let file = format!(
+                "{}.{}",
+                content_hash(&url),
+                image_extension(&url, content_type.as_deref())
+            );
+            std::fs::write(cache.join(&file), &bytes)?;
+            self.library.assets.insert(url, file);
+        }
+
+        self.use_cached_images(cache);
+        Ok(())
+    }
+
+    /// Embed the images already downloaded into `cache`, without downloading any more.
+    pub(crate) fn use_cached_images(&mut self, cache: &Path) {
+        self.asset_cache = Some(cache.to_path_buf());
+    }
+
+    /// The folder attachments for a note at `note_path` are placed in.
+    fn attachment_root(&self, note_path: &Path) -> PathBuf {
+        match self.attachments_folder.strip_prefix("./") {
+            Some(relative) => note_path
+                .parent()
+                .unwrap_or(&self.vault_root)
+                .join(relative),
+            None if self.attachments_folder == "." => {
+                note_path.parent().unwrap_or(&self.vault_root).to_path_buf()
+            }
+            None => self
+                .vault_root
+                .join(self.attachments_folder.trim_start_matches('/')),
+        }
+    }
+
+    /// The vault path of a cached image, staging it into the attachments folder for a note at
+    /// `note_path`, or `None` if it wasn't downloaded.
+    fn attachment(&mut self, note_path: &Path, url: &str) -> anyhow::Result<Option<String>> {
+        let (Some(cache), Some(file)) = (&self.asset_cache, self.library.assets.get(url)) else {
+            return Ok(None);
+        };
+
+        let Ok(contents) = std::fs::read(cache.join(file)) else {
+            return Ok(None);
+        };
+
+        let path = self.attachment_root(note_path).join(file);
+        self.transaction.stage_attachment(&path, contents)?;
+
+        Ok(Some(
+            path.strip_prefix(&self.vault_root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/"),
+        ))
+    }
+
+    /// Point the images in a document note, and its `image_url`, at their downloaded copies,
+    /// staging them into the vault's attachments folder.
+    pub(crate) fn localize_images(
+        &mut self,
+        note_path: &Path,
+        metadata: &mut serde_yml::Value,
+        contents: &str,
+    ) -> anyhow::Result<String> {
+        if self.asset_cache.is_none() {
+            return Ok(contents.to_string());
+        }
+
+        let image_url = metadata
+            .get("image_url")
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
+        if let Some(image_url) = image_url {
+            if let Some(local) = self.attachment(note_path, &image_url)? {
+                metadata["image_url"] = serde_yml::Value::from(format!("[[{}]]", local));
+            }
+        }
+
+        let mut embeds = vec![];
+        for captures in MARKDOWN_IMAGE.captures_iter(contents) {
+            embeds.push(self.attachment(note_path, &captures[2])?);
+        }
+
+        let mut embeds = embeds.into_iter();
+        Ok(MARKDOWN_IMAGE
+            .replace_all(contents, |captures: &Captures| {
+                match embeds.next().flatten() {
+                    Some(local) => format!("![[{}]]", local),
+                    None => captures[0].to_string(),
+                }
+            })
+            .to_string())
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Context;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// An author normalization file, for example:
+///
+/// ```yaml
+/// rules:
+///   - pattern: " on Twitter$"
+///     replace: ""
+///   - pattern: "^([^,]+), (.+)$"
+///     replace: "$2 $1"
+/// aliases:
+///   Nassim Taleb: Nassim Nicholas Taleb
+///   nntaleb: Nassim Nicholas Taleb
+/// ```
+#[derive(Debug, Default, Deserialize)]
+struct AuthorMapFile {
+    #[serde(default)]
+    rules: Vec<AuthorRule>,
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthorRule {
+    pattern: String,
+    replace: String,
+}
+
+/// Rewrites the author strings from Readwise to one canonical name per author, so that notes and
+/// links for the same person converge.
+#[derive(Default)]
+pub struct AuthorNormalizer {
+    rules: Vec<(Regex, String)>,
+    aliases: HashMap<String, String>,
+}
+
+impl AuthorNormalizer {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file: AuthorMapFile = serde_yml::from_reader(std::fs::File::open(path)?)
+            .with_context(|| format!("Failed to parse author map {:?}", path))?;
+
+        let rules = file
+            .rules
+            .into_iter()
+            .map(|rule| Ok((Regex::new(&rule.pattern)?, rule.replace)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(AuthorNormalizer {
+            rules,
+            aliases: file.aliases,
+        })
+    }
+
+    /// The canonical name for an author. An alias matching the raw string wins outright, otherwise
+    /// the rules are applied in order and the result looked up in the aliases.
+    pub fn normalize(&self, author: &str) -> String {
+        let author = author.trim();
+        if let Some(canonical) = self.aliases.get(author) {
+            return canonical.clone();
+        }
+
+        let rewritten = self
+            .rules
+            .iter()
+            .fold(author.to_string(), |author, (pattern, replace)| {
+                pattern
+                    .replace_all(&author, replace.as_str())
+                    .trim()
+                    .to_string()
+            });
+
+        self.aliases.get(&rewritten).cloned().unwrap_or(rewritten)
+    }
+}
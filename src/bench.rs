@@ -0,0 +1,104 @@
+use crate::transaction::render_note;
+use crate::Exporter;
+use itertools::Itertools;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+/// Time spent in each phase of an export, in the order they are reported.
+#[derive(Default)]
+pub struct Timings {
+    pub library_load: Duration,
+    pub setup: Duration,
+    context: Duration,
+    rendering: Duration,
+    script: Duration,
+    writes: Duration,
+}
+
+/// Time `f`, adding the time taken to `total`.
+fn timed<T>(total: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    *total += started.elapsed();
+    result
+}
+
+impl Exporter {
+    /// Run each phase of exporting every book which would be exported, timing them. Notes are
+    /// written to a temporary directory rather than the vault, which is left untouched.
+    pub(crate) fn bench(&self, mut timings: Timings) -> anyhow::Result<String> {
+        let scratch = std::env::temp_dir().join(format!("readwise-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&scratch)?;
+
+        let books = self
+            .library
+            .books
+            .iter()
+            .filter(|book| self.skip_reason(book).is_none())
+            .cloned()
+            .map(|book| self.as_rendered(book))
+            .collect_vec();
+
+        let mut highlight_count = 0;
+        for book in &books {
+            let highlights = self.highlights_for(book);
+            highlight_count += highlights.len();
+
+            let context = timed(&mut timings.context, || {
+                self.create_template_context(&book, &highlights)
+            })?;
+
+            let contents = timed(&mut timings.rendering, || {
+                self.render_templates(&book, &highlights, &context, None)
+            })?;
+
+            let metadata = timed(&mut timings.script, || -> anyhow::Result<_> {
+                match &self.metadata_script {
+                    None => Ok(serde_yml::to_value(book)?),
+                    Some(script) => Ok(script.execute(book, &highlights)?.metadata),
+                }
+            })?;
+
+            timed(&mut timings.writes, || {
+                std::fs::write(
+                    scratch.join(format!("{}.md", book.id)),
+                    render_note(&metadata, &contents)?,
+                )
+                .map_err(anyhow::Error::from)
+            })?;
+        }
+
+        std::fs::remove_dir_all(&scratch)?;
+
+        let phases = [
+            ("Library load", timings.library_load),
+            ("Setup (templates, existing notes)", timings.setup),
+            ("Context building", timings.context),
+            ("Template rendering", timings.rendering),
+            ("Metadata script", timings.script),
+            ("File writes", timings.writes),
+        ];
+        let total = phases.iter().map(|(_, time)| *time).sum::<Duration>();
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Exporting {} books with {} highlights took {:.1} ms",
+            books.len(),
+            highlight_count,
+            total.as_secs_f64() * 1000.0
+        )?;
+
+        for (phase, time) in phases {
+            writeln!(
+                out,
+                "  {:<34} {:>10.1} ms {:>5.1}%",
+                phase,
+                time.as_secs_f64() * 1000.0,
+                100.0 * time.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON)
+            )?;
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,246 @@
+use crate::readwise::Book;
+use crate::Exporter;
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const CARD_WIDTH: i64 = 400;
+const CARD_HEIGHT: i64 = 240;
+const GAP: i64 = 80;
+
+/// How many cards are placed in each row beneath the central card.
+const COLUMNS: usize = 4;
+
+/// A card in an Obsidian `.canvas` file, per the JSON Canvas format.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Node {
+    File {
+        id: String,
+        file: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        subpath: Option<String>,
+        x: i64,
+        y: i64,
+        width: i64,
+        height: i64,
+    },
+    Text {
+        id: String,
+        text: String,
+        x: i64,
+        y: i64,
+        width: i64,
+        height: i64,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Edge {
+    id: String,
+    from_node: String,
+    from_side: &'static str,
+    to_node: String,
+    to_side: &'static str,
+}
+
+#[derive(Serialize)]
+struct Canvas {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+/// The top left corner of the `index`th of `count` cards in the grid beneath the central card,
+/// with rows centred under it.
+fn grid_position(index: usize, count: usize) -> (i64, i64) {
+    let columns = count.clamp(1, COLUMNS) as i64;
+    let row = (index / COLUMNS) as i64;
+    let column = (index % COLUMNS) as i64;
+    let row_width = columns * CARD_WIDTH + (columns - 1) * GAP;
+
+    (
+        column * (CARD_WIDTH + GAP) - row_width / 2 + CARD_WIDTH / 2,
+        (row + 1) * (CARD_HEIGHT + GAP * 2),
+    )
+}
+
+/// Connect the central card to each card of the grid, from its bottom edge to their tops.
+fn edges(center: &str, ids: &[String]) -> Vec<Edge> {
+    ids.iter()
+        .map(|id| Edge {
+            id: format!("{}-{}", center, id),
+            from_node: center.to_string(),
+            from_side: "bottom",
+            to_node: id.clone(),
+            to_side: "top",
+        })
+        .collect()
+}
+
+impl Exporter {
+    /// Where the note for a book is, or would be written by an export.
+    pub(crate) fn note_path(&self, book: &Book) -> anyhow::Result<PathBuf> {
+        if let Some(existing) = self.remaining_existing.get(&book.id) {
+            return Ok(existing.to_path_buf());
+        }
+
+        Ok(self
+            .category_root(&book.category)?
+            .join(self.sanitize_title(&book.title, &format!("book-{}", book.id)))
+            .with_extension("md"))
+    }
+
+    fn vault_relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.vault_root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// A canvas with the note for a book in the centre, connected to a card embedding each of its
+    /// highlights from the note.
+    fn book_canvas(&self, book_id: i32) -> anyhow::Result<(String, Canvas)> {
+        let book = self
+            .library
+            .books
+            .iter()
+            .find(|b| b.id == book_id)
+            .cloned()
+            .map(|book| self.as_rendered(book))
+            .ok_or_else(|| anyhow!("No book with id {} in the library", book_id))?;
+
+        let file = self.vault_relative(&self.note_path(&book)?);
+        let center = format!("book-{}", book.id);
+        let mut nodes = vec![Node::File {
+            id: center.clone(),
+            file: file.clone(),
+            subpath: None,
+            x: -CARD_WIDTH / 2,
+            y: 0,
+            width: CARD_WIDTH,
+            height: CARD_HEIGHT,
+        }];
+
+        let highlights = self
+            .highlights_for(&book)
+            .into_iter()
+            .sorted_by_key(|h| h.location)
+            .collect_vec();
+
+        let mut ids = vec![];
+        for (index, highlight) in highlights.iter().enumerate() {
+            let id = format!("highlight-{}", highlight.id);
+            let (x, y) = grid_position(index, highlights.len());
+            nodes.push(Node::File {
+                id: id.clone(),
+                file: file.clone(),
+                subpath: Some(format!("#^{}", highlight.id)),
+                x,
+                y,
+                width: CARD_WIDTH,
+                height: CARD_HEIGHT,
+            });
+            ids.push(id);
+        }
+
+        let edges = edges(&center, &ids);
+        Ok((book.title, Canvas { nodes, edges }))
+    }
+
+    /// A canvas with a tag in the centre, connected to the note of each book which has the tag or
+    /// has highlights with it.
+    fn tag_canvas(&self, tag: &str) -> anyhow::Result<(String, Canvas)> {
+        let tagged_highlights = self
+            .library
+            .highlights
+            .iter()
+            .filter(|h| h.tags.iter().any(|t| t.name.eq_ignore_ascii_case(tag)))
+            .map(|h| self.library.canonical_book_id(h.book_id))
+            .collect::<std::collections::HashSet<_>>();
+
+        let books = self
+            .library
+            .books
+            .iter()
+            .filter(|b| {
+                b.tags.iter().any(|t| t.name.eq_ignore_ascii_case(tag))
+                    || tagged_highlights.contains(&b.id)
+            })
+            .filter(|b| self.skip_reason(b).is_none())
+            .cloned()
+            .map(|book| self.as_rendered(book))
+            .sorted_by(|a, b| a.title.cmp(&b.title))
+            .collect_vec();
+
+        if books.is_empty() {
+            return Err(anyhow!("No exported books are tagged '{}'", tag));
+        }
+
+        let center = "tag".to_string();
+        let mut nodes = vec![Node::Text {
+            id: center.clone(),
+            text: format!("# {}", tag),
+            x: -CARD_WIDTH / 2,
+            y: CARD_HEIGHT / 2,
+            width: CARD_WIDTH,
+            height: CARD_HEIGHT / 2,
+        }];
+
+        let mut ids = vec![];
+        for (index, book) in books.iter().enumerate() {
+            let id = format!("book-{}", book.id);
+            let (x, y) = grid_position(index, books.len());
+            nodes.push(Node::File {
+                id: id.clone(),
+                file: self.vault_relative(&self.note_path(book)?),
+                subpath: None,
+                x,
+                y,
+                width: CARD_WIDTH,
+                height: CARD_HEIGHT,
+            });
+            ids.push(id);
+        }
+
+        let edges = edges(&center, &ids);
+        Ok((tag.to_string(), Canvas { nodes, edges }))
+    }
+
+    /// Write a canvas mapping a book and its highlights, or a tag and its books, to `output`
+    /// within the vault, or by default to `Canvases/<title>.canvas` within the base folder.
+    pub(crate) fn export_canvas(
+        &mut self,
+        book_id: Option<i32>,
+        tag: Option<&str>,
+        output: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        let (title, canvas) = match (book_id, tag) {
+            (Some(book_id), _) => self.book_canvas(book_id)?,
+            (None, Some(tag)) => self.tag_canvas(tag)?,
+            (None, None) => return Err(anyhow!("Either a book or a tag is required")),
+        };
+
+        let path = match output {
+            Some(output) => self.vault_root.join(output),
+            None => {
+                let path = self
+                    .export_root
+                    .join("Canvases")
+                    .join(format!("{}.canvas", self.sanitize_title(&title, "canvas")));
+                self.confine(&path, &format!("canvas '{}'", title))?;
+                path
+            }
+        };
+
+        info!(
+            "Writing canvas with {} cards to {:?}",
+            canvas.nodes.len(),
+            path
+        );
+        self.transaction
+            .stage_file(&path, serde_json::to_string_pretty(&canvas)?)
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Split a `<category>=<value>` argument, lowercasing the category.
+fn parse_pair(s: &str, example: &str) -> anyhow::Result<(String, String)> {
+    let (category, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected <category>=<value>, e.g. {}, got '{}'", example, s))?;
+
+    let (category, value) = (category.trim(), value.trim());
+    if category.is_empty() || value.is_empty() {
+        return Err(anyhow!("Empty category or value in mapping '{}'", s));
+    }
+
+    Ok((category.to_lowercase(), value.to_string()))
+}
+
+/// A renaming of a Readwise category to the user's own label, e.g. `supplementals=Books`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CategoryMapping {
+    pub category: String,
+    pub label: String,
+}
+
+impl FromStr for CategoryMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (category, label) = parse_pair(s, "supplementals=Books")?;
+        Ok(CategoryMapping { category, label })
+    }
+}
+
+impl TryFrom<String> for CategoryMapping {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// The vault-relative folder notes for a category are written to, e.g. `articles=Sources/Articles`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CategoryFolder {
+    pub category: String,
+    pub folder: PathBuf,
+}
+
+impl FromStr for CategoryFolder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (category, folder) = parse_pair(s, "articles=Sources/Articles")?;
+        let folder = PathBuf::from(folder.trim_matches('/'));
+        if folder.is_absolute() || folder.components().any(|c| c.as_os_str() == "..") {
+            return Err(anyhow!(
+                "Category folder '{}' must be within the vault",
+                folder.display()
+            ));
+        }
+
+        Ok(CategoryFolder { category, folder })
+    }
+}
+
+impl TryFrom<String> for CategoryFolder {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
@@ -0,0 +1,192 @@
+use crate::readwise::{parse_date_arg, parse_timestamp};
+use crate::Library;
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+/// A record of an export run, kept in the library cache so later commands can tell what changed
+/// since the vault was last written.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportRun {
+    pub exported_at: DateTime<Utc>,
+
+    /// Notes left without a corresponding Readwise book after the run, cleared once the run is
+    /// older than the last [`DETAILED_EXPORT_RUNS`]
+    #[serde(default)]
+    pub stranded: Vec<PathBuf>,
+
+    /// How many notes were stranded, kept after the paths themselves are cleared
+    #[serde(default)]
+    pub stranded_count: usize,
+
+    /// Highlights left out of the vault by an exclusion tag
+    #[serde(default)]
+    pub excluded_highlights: usize,
+}
+
+/// How many of the most recent export runs keep the paths of the notes they stranded, so the
+/// library cache doesn't grow with the paths of every run ever made.
+pub const DETAILED_EXPORT_RUNS: usize = 50;
+
+impl Library {
+    /// Record an export run, clearing the stranded paths of the runs before the most recent
+    /// [`DETAILED_EXPORT_RUNS`].
+    pub fn record_export_run(&mut self, run: ExportRun) {
+        self.export_runs.push(run);
+
+        let older = self.export_runs.len().saturating_sub(DETAILED_EXPORT_RUNS);
+        for run in &mut self.export_runs[..older] {
+            run.stranded_count = run.stranded_count.max(run.stranded.len());
+            run.stranded = vec![];
+        }
+    }
+}
+
+/// Resolve a `--since` argument: `last-run` for the most recent export, a date, or a timestamp.
+pub fn resolve_since(library: &Library, since: &str) -> anyhow::Result<DateTime<Utc>> {
+    if since == "last-run" {
+        return library
+            .export_runs
+            .last()
+            .map(|run| run.exported_at)
+            .ok_or_else(|| anyhow!("No export has been recorded in the library yet"));
+    }
+
+    parse_date_arg(since).map_err(|_| {
+        anyhow!(
+            "Invalid --since '{}', expected last-run, a date, or a timestamp",
+            since
+        )
+    })
+}
+
+fn after(timestamp: Option<&str>, since: DateTime<Utc>) -> bool {
+    timestamp
+        .and_then(parse_timestamp)
+        .is_some_and(|t| t > since)
+}
+
+/// Render the changes to the library since the given time as markdown, suitable for pasting
+/// into a review note.
+pub fn changelog(library: &Library, since: DateTime<Utc>) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "## Readwise changes since {}",
+        since.format("%Y-%m-%d %H:%M")
+    )
+    .unwrap();
+
+    let changed_highlights = library
+        .highlights
+        .iter()
+        .filter(|h| after(Some(&h.updated), since))
+        .collect_vec();
+
+    // A book is new if every one of its highlights was made after the cutoff
+    let new_books = library
+        .books
+        .iter()
+        .filter(|book| {
+            let mut highlights = library.highlights_for(book).into_iter().peekable();
+            highlights.peek().is_some()
+                && highlights
+                    .all(|h| after(h.highlighted_at.as_deref().or(Some(&h.updated)), since))
+        })
+        .collect_vec();
+
+    writeln!(out, "\n### New books\n").unwrap();
+    if new_books.is_empty() {
+        writeln!(out, "None").unwrap();
+    }
+
+    for book in &new_books {
+        writeln!(
+            out,
+            "- {}{} ({} highlights)",
+            book.title,
+            book.author
+                .as_ref()
+                .map(|a| format!(" — {}", a))
+                .unwrap_or_default(),
+            library.highlights_for(book).len()
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\n### Changed highlights\n").unwrap();
+    if changed_highlights.is_empty() {
+        writeln!(out, "None").unwrap();
+    }
+
+    let by_book = changed_highlights
+        .iter()
+        .into_group_map_by(|h| library.canonical_book_id(h.book_id));
+
+    for book in library.books.iter().filter(|b| by_book.contains_key(&b.id)) {
+        writeln!(out, "#### {}\n", book.title).unwrap();
+        for highlight in &by_book[&book.id] {
+            writeln!(out, "> {}\n", highlight.text.trim().replace('\n', "\n> ")).unwrap();
+        }
+    }
+
+    // Stranded before the cutoff means not newly stranded
+    let previously_stranded = library
+        .export_runs
+        .iter()
+        .filter(|run| run.exported_at <= since)
+        .flat_map(|run| run.stranded.iter())
+        .collect::<HashSet<_>>();
+
+    let newly_stranded = library
+        .export_runs
+        .iter()
+        .filter(|run| run.exported_at > since)
+        .flat_map(|run| run.stranded.iter())
+        .filter(|path| !previously_stranded.contains(path))
+        .unique()
+        .collect_vec();
+
+    writeln!(out, "\n### Newly stranded notes\n").unwrap();
+    if newly_stranded.is_empty() {
+        writeln!(out, "None").unwrap();
+    }
+
+    for path in newly_stranded {
+        writeln!(out, "- {}", path.display()).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExportRun, DETAILED_EXPORT_RUNS};
+    use crate::testing::library;
+    use chrono::{DateTime, Duration};
+    use std::path::PathBuf;
+
+    #[test]
+    fn older_runs_keep_only_their_stranded_count() {
+        let mut library = library(vec![], vec![]);
+        for day in 0..DETAILED_EXPORT_RUNS + 2 {
+            library.record_export_run(ExportRun {
+                exported_at: DateTime::UNIX_EPOCH + Duration::days(day as i64),
+                stranded: vec![PathBuf::from("Readwise/Stranded.md")],
+                stranded_count: 1,
+                excluded_highlights: 0,
+            });
+        }
+
+        let (older, detailed) = library.export_runs.split_at(2);
+        assert!(older
+            .iter()
+            .all(|run| run.stranded.is_empty() && run.stranded_count == 1));
+        assert_eq!(detailed.len(), DETAILED_EXPORT_RUNS);
+        assert!(detailed.iter().all(|run| run.stranded.len() == 1));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::errors::RecordError;
+use crate::Exporter;
+use anyhow::anyhow;
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+/// `path` with its `.` and `..` components resolved, without touching the filesystem, as the note
+/// it names may not exist yet.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+impl Exporter {
+    /// Check a note path computed from a record's title, category, or other fields is within the
+    /// base folder, or a category folder, so a malicious or unusual record can't have a note
+    /// written elsewhere in the vault or outside it. `record` names the record in the error.
+    pub(crate) fn confine(&self, path: &Path, record: &str) -> anyhow::Result<()> {
+        let normalized = normalize(path);
+        let within = std::iter::once(self.export_root.clone())
+            .chain(
+                self.category_folders
+                    .values()
+                    .map(|folder| self.vault_root.join(folder)),
+            )
+            .map(|root| normalize(&root))
+            .any(|root| normalized.starts_with(&root) && normalized != root);
+
+        match within {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "Refusing to write the note for {} to {:?}, outside the base folder {:?}",
+                record,
+                path,
+                self.export_root
+            )),
+        }
+    }
+
+    /// Confine the note for a record, warning and recording the record as failed if it is outside
+    /// the base folder, so the rest of the run carries on without it.
+    pub(crate) fn confine_record(
+        &mut self,
+        path: &Path,
+        kind: &str,
+        id: &str,
+        title: Option<&str>,
+    ) -> bool {
+        let record = match title {
+            Some(title) => format!("{} {} '{}'", kind, id, title),
+            None => format!("{} {}", kind, id),
+        };
+
+        let Err(err) = self.confine(path, &record) else {
+            return true;
+        };
+
+        warn!("{:#}", err);
+        self.failures.push(RecordError {
+            kind: kind.to_string(),
+            id: id.to_string(),
+            title: title.map(str::to_string),
+            reason: format!("{:#}", err),
+        });
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+    use crate::testing::{book, exporter, library, vault};
+    use std::path::Path;
+
+    #[test]
+    fn normalize_resolves_dots_without_the_filesystem() {
+        assert_eq!(
+            normalize(Path::new("/vault/Readwise/./Books/../../Secrets/Note.md")),
+            Path::new("/vault/Secrets/Note.md")
+        );
+        assert_eq!(
+            normalize(Path::new("/vault/../../../etc/passwd")),
+            Path::new("/etc/passwd")
+        );
+    }
+
+    #[test]
+    fn notes_escaping_the_base_folder_are_refused() {
+        let root = vault("confinement");
+        let exporter = exporter(&root, library(vec![book(1, "Book")], vec![]), &[]);
+
+        let inside = root.join("Readwise/Books/Book.md");
+        let escaping = root.join("Readwise/Books/../../.obsidian/app.json");
+        assert!(exporter.confine(&inside, "book 1").is_ok());
+        assert!(exporter.confine(&escaping, "book 1").is_err());
+        assert!(exporter.confine(&root.join("Readwise"), "book 1").is_err());
+
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -0,0 +1,150 @@
+use crate::note_exports::{content_hash, NoteExport};
+use crate::readwise::Book;
+use crate::Exporter;
+use anyhow::anyhow;
+use chrono::Utc;
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::Deserialize;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// What happens to a book's note which was changed since it was last exported, by the user or
+/// another device.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Export the note as usual, replacing the changes below the highlights marker
+    Overwrite,
+
+    /// Leave the note as it is, with a warning
+    Skip,
+
+    /// Leave the note as it is, only appending the blocks of highlights it doesn't have yet
+    MergeHighlights,
+}
+
+impl Exporter {
+    fn read_note(&self, path: &Path) -> anyhow::Result<String> {
+        self.transaction
+            .read_existing(path)?
+            .ok_or_else(|| anyhow!("The note {:?} no longer exists", path))
+    }
+
+    /// Whether the note at `path` differs from the one last exported for a book, by the hash
+    /// recorded at the time. Notes exported before hashes were recorded are never in conflict.
+    fn changed_since_export(&self, book: &Book, path: &Path) -> anyhow::Result<bool> {
+        let Some(export) = self.library.note_exports.get(&book.id) else {
+            return Ok(false);
+        };
+
+        Ok(content_hash(&self.read_note(path)?) != export.hash)
+    }
+
+    /// Apply the conflict policy to a book's existing note if it was changed since it was last
+    /// exported, returning whether the note was dealt with and shouldn't be exported as usual.
+    pub(crate) fn resolve_conflict(&mut self, book: &Book, path: &Path) -> anyhow::Result<bool> {
+        if self.conflict_policy == ConflictPolicy::Overwrite
+            || !self.changed_since_export(book, path)?
+        {
+            return Ok(false);
+        }
+
+        match self.conflict_policy {
+            ConflictPolicy::Overwrite => Ok(false),
+
+            ConflictPolicy::Skip => {
+                warn!(
+                    "Leaving the note for '{}' at {:?} as it was changed since it was last exported",
+                    book.title, path
+                );
+                self.exported_paths.insert(book.id, path.to_path_buf());
+                Ok(true)
+            }
+
+            ConflictPolicy::MergeHighlights => {
+                self.merge_highlights(book, path)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Append the blocks of a book's highlights which aren't in its note yet, found by their
+    /// `^<id>` block references, leaving the rest of the note as it is.
+    fn merge_highlights(&mut self, book: &Book, path: &Path) -> anyhow::Result<()> {
+        self.exported_paths.insert(book.id, path.to_path_buf());
+
+        let existing = self.read_note(path)?;
+        let highlights = self.highlights_for(book);
+        let new = highlights
+            .iter()
+            .copied()
+            .filter(|highlight| {
+                let reference = format!("^{}", highlight.id);
+                !existing
+                    .lines()
+                    .any(|line| line.trim_end().ends_with(&reference))
+            })
+            .collect_vec();
+
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Adding {} highlights to the changed note for '{}'",
+            new.len(),
+            book.title
+        );
+
+        let context = self.create_template_context(&book, &highlights)?;
+        let blocks = self.render_highlights(book, &new, &context)?;
+        let contents = format!("{}\n\n{}\n", existing.trim_end(), blocks);
+
+        self.library.note_exports.insert(
+            book.id,
+            NoteExport {
+                path: path.strip_prefix(&self.vault_root)?.to_path_buf(),
+                hash: content_hash(&contents),
+                exported_at: Utc::now(),
+            },
+        );
+        self.transaction.stage_file(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{book, exporter, highlight, library, vault};
+
+    #[test]
+    fn merging_keeps_changes_and_appends_only_new_highlights() {
+        let root = vault("merge-highlights");
+        let mut first = exporter(
+            &root,
+            library(vec![book(1, "Book")], vec![highlight(10, 1, "First")]),
+            &[],
+        );
+        first.export().unwrap();
+        first.transaction.commit().unwrap();
+
+        let note = root.join("Readwise/Books/Book.md");
+        let edited = format!(
+            "{}\nMy own thoughts\n",
+            std::fs::read_to_string(&note).unwrap()
+        );
+        std::fs::write(&note, &edited).unwrap();
+
+        let mut library = first.library;
+        library.highlights.push(highlight(11, 1, "Second"));
+        let mut second = exporter(&root, library, &["--conflict-policy", "merge-highlights"]);
+        second.export().unwrap();
+        second.transaction.commit().unwrap();
+
+        let merged = std::fs::read_to_string(&note).unwrap();
+        assert!(merged.starts_with(edited.trim_end()));
+        assert_eq!(merged.matches("^10").count(), 1);
+        assert_eq!(merged.matches("^11").count(), 1);
+
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -0,0 +1,110 @@
+use crate::readwise::parse_timestamp;
+use crate::Exporter;
+use anyhow::anyhow;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Utc};
+use itertools::Itertools;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tera::Context;
+use tracing::info;
+
+const DEFAULT_DAILY_TEMPLATE: &str = r#"## Highlights captured today
+{% for highlight in highlights %}
+- [[{{ highlight.link }}#^{{ highlight.id }}|{{ highlight.book }}]]: {{ highlight.text | truncate(length=200) }}{% endfor %}
+"#;
+
+/// Check a daily note path format given on the command line is a valid strftime format.
+pub fn parse_path_format(value: &str) -> Result<String, String> {
+    match StrftimeItems::new(value).any(|item| matches!(item, Item::Error)) {
+        true => Err(format!("'{}' is not a valid strftime format", value)),
+        false => Ok(value.to_string()),
+    }
+}
+
+impl Exporter {
+    /// Append a section listing the highlights made since `since` to the daily note of the day
+    /// each was made, creating daily notes which don't exist yet. Daily notes are found with
+    /// `path_format`, a strftime format relative to the vault root such as `Daily/%Y-%m-%d.md`.
+    /// Highlights already linked from a daily note are left out, so reruns don't repeat them.
+    pub(crate) fn append_to_daily_notes(
+        &mut self,
+        path_format: &str,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        if !self.templates.get_template_names().any(|n| n == "daily") {
+            self.templates
+                .add_raw_template("daily", DEFAULT_DAILY_TEMPLATE)?;
+        }
+
+        // The exported highlights of the books exported this run, as they are in their notes
+        let highlights = self
+            .library
+            .books
+            .iter()
+            .filter_map(|book| Some((book, self.exported_paths.get(&book.id)?)))
+            .flat_map(|(book, path)| {
+                self.highlights_for(book)
+                    .into_iter()
+                    .map(move |highlight| (book, path, highlight))
+            })
+            .sorted_by(|(_, _, a), (_, _, b)| a.highlighted_at.cmp(&b.highlighted_at));
+
+        let mut by_day: BTreeMap<String, Vec<serde_json::Value>> = BTreeMap::new();
+        for (book, path, highlight) in highlights {
+            let Some(at) = highlight
+                .highlighted_at
+                .as_deref()
+                .and_then(parse_timestamp)
+                .filter(|at| *at > since)
+            else {
+                continue;
+            };
+
+            let mut note = String::new();
+            write!(note, "{}", at.with_timezone(&Local).format(path_format))
+                .map_err(|_| anyhow!("'{}' is not a valid strftime format", path_format))?;
+            by_day.entry(note).or_default().push(json!({
+                "id": highlight.id,
+                "text": highlight.text.trim().replace('\n', " "),
+                "note": highlight.note,
+                "book": book.title,
+                "link": self.wikilink_target(path),
+            }));
+        }
+
+        for (note, highlights) in by_day {
+            let path = self.vault_root.join(&note);
+            let existing = self.transaction.read_existing(&path)?.unwrap_or_default();
+
+            let highlights = highlights
+                .into_iter()
+                .filter(|h| !existing.contains(&format!("#^{}|", h["id"])))
+                .collect_vec();
+
+            if highlights.is_empty() {
+                continue;
+            }
+
+            info!(
+                "Adding {} highlights to daily note {}",
+                highlights.len(),
+                note
+            );
+
+            let mut context = Context::new();
+            context.insert("highlights", &highlights);
+            let section = self.templates.render("daily", &context)?;
+
+            let contents = match existing.trim_end() {
+                "" => section,
+                existing => format!("{}\n\n{}", existing, section.trim_start()),
+            };
+
+            self.transaction.stage_file(&path, contents)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use crate::readwise::parse_timestamp;
+use crate::stats::WORDS_PER_MINUTE;
+use crate::Exporter;
+use itertools::Itertools;
+use std::fmt::Write as _;
+use tracing::info;
+
+impl Exporter {
+    /// Write a "currently reading" note in the base folder listing the Reader documents which have
+    /// been started but not finished, most recently opened first. Regenerated on each export.
+    pub(crate) fn write_dashboard(&mut self, file_name: &str) -> anyhow::Result<()> {
+        let in_progress = self
+            .library
+            .documents
+            .iter()
+            .filter(|d| d.parent_id.is_none())
+            .filter(|d| d.reading_progress > 0.0 && d.reading_progress < 1.0)
+            .sorted_by(|a, b| b.last_opened_at.cmp(&a.last_opened_at))
+            .collect_vec();
+
+        let mut contents = String::new();
+        writeln!(contents, "# Currently Reading\n")?;
+
+        if in_progress.is_empty() {
+            writeln!(contents, "Nothing in progress.")?;
+        } else {
+            writeln!(
+                contents,
+                "| Title | Author | Progress | Words | Time left | Saved | Last opened |"
+            )?;
+            writeln!(contents, "| --- | --- | ---: | ---: | ---: | --- | --- |")?;
+        }
+
+        for document in &in_progress {
+            let date = |timestamp: Option<&str>| {
+                timestamp
+                    .and_then(parse_timestamp)
+                    .map(|t| t.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default()
+            };
+
+            let time_left = document
+                .word_count
+                .map(|words| {
+                    let minutes = (words as f64 * (1.0 - document.reading_progress)
+                        / WORDS_PER_MINUTE)
+                        .ceil();
+                    format!("{} min", minutes)
+                })
+                .unwrap_or_default();
+
+            writeln!(
+                contents,
+                "| [{}](https://read.readwise.io/read/{}) | {} | {:.0}% | {} | {} | {} | {} |",
+                document
+                    .title
+                    .as_deref()
+                    .unwrap_or(&document.url)
+                    .replace('|', "\\|"),
+                document.id,
+                document.author.as_deref().unwrap_or("").replace('|', "\\|"),
+                document.reading_progress * 100.0,
+                document
+                    .word_count
+                    .map(|w| w.to_string())
+                    .unwrap_or_default(),
+                time_left,
+                date(Some(&document.saved_at)),
+                date(document.last_opened_at.as_deref()),
+            )?;
+        }
+
+        let path = self.export_root.join(file_name);
+        info!(
+            "Writing reading dashboard {:?} with {} documents",
+            path,
+            in_progress.len()
+        );
+        self.transaction.stage_file(&path, contents)
+    }
+}
@@ -0,0 +1,138 @@
+use crate::readwise::{Book, Highlight};
+use crate::{Exporter, Library, ReadwiseObjectKind};
+use chrono::Utc;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashSet;
+use tracing::info;
+
+/// What happens to the notes of books deleted in Readwise.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum DeletedBookPolicy {
+    /// Treat their notes as stranded, so `--mark-stranded` and `--delete-stranded` apply to them
+    Strand,
+
+    /// Leave their notes as they were last exported
+    Keep,
+}
+
+impl Library {
+    /// Keep the books and highlights in `previous_books` and `previous_highlights` which a refetch
+    /// of `kinds` no longer found, marking them deleted in Readwise, so they are no longer exported
+    /// but their notes can be dealt with. Imported records, with their negative synthetic ids, were
+    /// never in Readwise to be found, so are carried over as they were.
+    pub(crate) fn record_deletions(
+        &mut self,
+        kinds: &[ReadwiseObjectKind],
+        previous_books: Vec<Book>,
+        previous_highlights: Vec<Highlight>,
+    ) {
+        let now = Utc::now();
+
+        let (imported_books, previous_books): (Vec<_>, Vec<_>) =
+            previous_books.into_iter().partition(|b| b.id < 0);
+        let (imported_highlights, previous_highlights): (Vec<_>, Vec<_>) =
+            previous_highlights.into_iter().partition(|h| h.id < 0);
+        self.books.extend(imported_books);
+        self.highlights.extend(imported_highlights);
+
+        if kinds.contains(&ReadwiseObjectKind::Book) {
+            let fetched = self.books.iter().map(|b| b.id).collect::<HashSet<_>>();
+            let deleted = previous_books
+                .into_iter()
+                .filter(|b| !fetched.contains(&b.id))
+                .collect::<Vec<_>>();
+
+            info!("Found {} books deleted in Readwise", deleted.len());
+            for book in &deleted {
+                self.deleted_books.entry(book.id).or_insert(now);
+            }
+            self.books.extend(deleted);
+        }
+
+        if kinds.contains(&ReadwiseObjectKind::Highlight) {
+            let fetched = self.highlights.iter().map(|h| h.id).collect::<HashSet<_>>();
+            let deleted = previous_highlights
+                .into_iter()
+                .filter(|h| !fetched.contains(&h.id))
+                .collect::<Vec<_>>();
+
+            info!("Found {} highlights deleted in Readwise", deleted.len());
+            for highlight in &deleted {
+                self.deleted_highlights.entry(highlight.id).or_insert(now);
+            }
+            self.highlights.extend(deleted);
+        }
+    }
+}
+
+impl Exporter {
+    /// Leave the notes of books deleted in Readwise as they are, if they are kept, so they aren't
+    /// treated as stranded.
+    pub(crate) fn keep_deleted_notes(&mut self) {
+        if self.deleted_books != DeletedBookPolicy::Keep {
+            return;
+        }
+
+        for book_id in self.library.deleted_books.keys() {
+            if let Some(note) = self.remaining_existing.remove(book_id) {
+                self.exported_paths.insert(*book_id, note.to_path_buf());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{book, highlight, library};
+    use crate::ReadwiseObjectKind;
+
+    #[test]
+    fn refetch_tombstones_only_readwise_records() {
+        // The refetch found book 1 and its first highlight again, but not book 2 or highlight 11
+        let mut refetched = library(vec![book(1, "Kept")], vec![highlight(10, 1, "Kept")]);
+
+        refetched.record_deletions(
+            &[ReadwiseObjectKind::Book, ReadwiseObjectKind::Highlight],
+            vec![book(1, "Kept"), book(2, "Deleted"), book(-5, "Imported")],
+            vec![
+                highlight(10, 1, "Kept"),
+                highlight(11, 1, "Deleted"),
+                highlight(-50, -5, "Imported"),
+            ],
+        );
+
+        let mut book_ids = refetched.books.iter().map(|b| b.id).collect::<Vec<_>>();
+        book_ids.sort();
+        assert_eq!(book_ids, vec![-5, 1, 2]);
+        assert_eq!(refetched.deleted_books.keys().collect::<Vec<_>>(), vec![&2]);
+
+        let mut highlight_ids = refetched
+            .highlights
+            .iter()
+            .map(|h| h.id)
+            .collect::<Vec<_>>();
+        highlight_ids.sort();
+        assert_eq!(highlight_ids, vec![-50, 10, 11]);
+        assert_eq!(
+            refetched.deleted_highlights.keys().collect::<Vec<_>>(),
+            vec![&11]
+        );
+    }
+
+    #[test]
+    fn refetch_of_other_kinds_keeps_imported_records() {
+        let mut refetched = library(vec![], vec![]);
+
+        refetched.record_deletions(
+            &[ReadwiseObjectKind::ReaderDocument],
+            vec![book(-5, "Imported")],
+            vec![highlight(-50, -5, "Imported")],
+        );
+
+        assert_eq!(refetched.books.len(), 1);
+        assert_eq!(refetched.highlights.len(), 1);
+        assert!(refetched.deleted_books.is_empty());
+        assert!(refetched.deleted_highlights.is_empty());
+    }
+}
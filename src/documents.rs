@@ -0,0 +1,603 @@
+use crate::articles;
+use crate::language;
+use crate::readwise::{Book, Document};
+use crate::text_cleanup;
+use crate::{Exporter, ReplacementStrategy};
+use clap::ValueEnum;
+use itertools::Itertools;
+use obsidian_rust_interface::joining::JoinedNote;
+use obsidian_rust_interface::NoteReference;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tera::Context;
+use tracing::{debug, info, instrument, warn};
+
+/// Frontmatter key recording the Reader `last_moved_at` a document note was last placed for.
+const LAST_MOVED_KEY: &str = "__readwise_last_moved_at";
+
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum DocumentFolderStrategy {
+    /// Place every document note directly in the Documents folder
+    Flat,
+
+    /// Group document notes into a folder per Reader location (Inbox, Later, Archive, Feed), moving
+    /// notes between folders as documents are moved in Reader
+    Location,
+}
+
+/// How Reader documents with a parent document (other than highlights and notes) are exported.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum DocumentHierarchy {
+    /// Write a note for each child document alongside the others, linking to its parent's note
+    Flat,
+
+    /// Write a note for each child document in a folder named after its parent's note
+    Nested,
+
+    /// Write each child document as a section of its parent's note, with its own highlights
+    Sections,
+}
+
+/// Whether a document is a highlight or note made in Reader, which arrive as child documents.
+fn is_annotation(document: &Document) -> bool {
+    document.parent_id.is_some()
+        && matches!(document.category.as_deref(), Some("highlight" | "note"))
+}
+
+/// What happens to Reader documents which are the same source as a book in the classic library.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum LinkedDocumentPolicy {
+    /// Write a note for both the document and the book
+    Keep,
+
+    /// Only write the book's note, leaving out the document's
+    Skip,
+
+    /// Only write the book's note, with the document's reading progress and Reader location in its
+    /// frontmatter
+    MergeProgress,
+}
+
+/// The folder name for a Reader location, using the names shown in Reader's UI.
+fn location_folder(location: &str) -> String {
+    match location {
+        "new" => "Inbox".to_string(),
+        "later" => "Later".to_string(),
+        "shortlist" => "Shortlist".to_string(),
+        "archive" => "Archive".to_string(),
+        "feed" => "Feed".to_string(),
+        other => {
+            let mut c = other.chars();
+            c.next()
+                .map(|f| f.to_uppercase().collect::<String>() + c.as_str())
+                .unwrap_or_else(|| "Unknown".to_string())
+        }
+    }
+}
+
+impl Exporter {
+    /// The Reader document which is the same source as a book, or as any book merged into it.
+    pub(crate) fn linked_document(&self, book: &Book) -> Option<&Document> {
+        let document_id = self.library.document_links.get(&book.id).or_else(|| {
+            self.library
+                .book_merges
+                .iter()
+                .filter(|(_, target)| **target == book.id)
+                .find_map(|(merged, _)| self.library.document_links.get(merged))
+        })?;
+
+        self.library.documents.iter().find(|d| d.id == *document_id)
+    }
+
+    /// Whether a document is left out because its book's note is written in its place.
+    fn replaced_by_book(&self, document: &Document) -> bool {
+        if self.linked_document_policy == LinkedDocumentPolicy::Keep {
+            return false;
+        }
+
+        self.library
+            .document_links
+            .iter()
+            .filter(|(_, linked)| **linked == document.id)
+            .filter_map(|(book_id, _)| {
+                let book_id = self.library.canonical_book_id(*book_id);
+                self.library.books.iter().find(|b| b.id == book_id)
+            })
+            .any(|book| self.skip_reason(book).is_none())
+    }
+
+    #[instrument(skip_all)]
+    pub(crate) fn export_documents(&mut self) -> anyhow::Result<()> {
+        let documents_root = self.export_root.join("Documents");
+        let documents = self
+            .library
+            .documents
+            .iter()
+            .filter(|document| !is_annotation(document))
+            .filter(|document| {
+                document.parent_id.is_none()
+                    || self.document_hierarchy != DocumentHierarchy::Sections
+            })
+            .filter(|document| !self.replaced_by_book(document))
+            .cloned()
+            .map(|mut document| {
+                document.author = document.author.map(|a| self.authors.normalize(&a));
+                document.category = document.category.map(|c| self.category_label(&c));
+                document
+            })
+            // Parents first, so their notes are placed before their children's
+            .sorted_by_key(|document| document.parent_id.is_some())
+            .collect::<Vec<_>>();
+
+        let mut note_paths: HashMap<String, PathBuf> = HashMap::new();
+
+        info!("Exporting {} Reader documents", documents.len());
+
+        for document in &documents {
+            let parent_path = document
+                .parent_id
+                .as_ref()
+                .and_then(|parent| note_paths.get(parent))
+                .cloned();
+
+            let root = match (self.document_hierarchy, &parent_path) {
+                (DocumentHierarchy::Nested, Some(parent_path)) => parent_path.with_extension(""),
+                _ => match self.document_folder_strategy {
+                    DocumentFolderStrategy::Flat => documents_root.clone(),
+                    DocumentFolderStrategy::Location => documents_root.join(location_folder(
+                        document.location.as_deref().unwrap_or("new"),
+                    )),
+                },
+            };
+
+            let existing_note = self.remaining_existing_documents.remove(&document.id);
+            let existing_file = existing_note.as_ref().map(|n| n.to_path_buf());
+
+            // Only follow moves made in Reader, so notes the user has filed elsewhere stay put
+            let moved = match &existing_note {
+                Some(note) if self.document_folder_strategy == DocumentFolderStrategy::Location => {
+                    let recorded = note
+                        .parse::<serde_yml::Value>()?
+                        .metadata
+                        .get(LAST_MOVED_KEY)
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string);
+
+                    recorded.as_deref() != Some(document.last_moved_at.as_str())
+                }
+
+                _ => false,
+            };
+
+            let mut note = match self.replacement_strategy {
+                ReplacementStrategy::Update => {
+                    self.export_document(&root, document, existing_note.as_ref())?
+                }
+                ReplacementStrategy::Replace | ReplacementStrategy::IgnoreExisting => {
+                    self.export_document(&root, document, None)?
+                }
+            };
+
+            if !self.confine_record(
+                &note.default_path,
+                "document",
+                &document.id,
+                document.title.as_deref(),
+            ) {
+                continue;
+            }
+
+            if let Some(parent_path) = &parent_path {
+                note.metadata["parent"] =
+                    serde_yml::Value::from(format!("[[{}]]", self.wikilink_target(parent_path)));
+            }
+
+            let note_path = existing_file.clone().unwrap_or(note.default_path.clone());
+            note_paths.insert(document.id.clone(), note_path);
+
+            note.contents = self.localize_images(
+                existing_file.as_ref().unwrap_or(&note.default_path),
+                &mut note.metadata,
+                &note.contents,
+            )?;
+
+            match (&existing_file, &self.replacement_strategy) {
+                (Some(existing_file), ReplacementStrategy::IgnoreExisting) => {
+                    debug!(
+                        "Ignoring existing file '{:?}' for document '{}'",
+                        existing_file, document.id
+                    );
+                    self.transaction.stage_note(
+                        &note.default_path,
+                        &note.metadata,
+                        &note.contents,
+                    )?;
+                }
+
+                (Some(existing_file), _) if moved && *existing_file != note.default_path => {
+                    info!(
+                        "Moving note for document '{}' from {:?} to {:?}",
+                        document.id, existing_file, note.default_path
+                    );
+                    self.transaction.stage_note(
+                        &note.default_path,
+                        &note.metadata,
+                        &note.contents,
+                    )?;
+                    self.transaction.stage_trash(existing_file)?;
+                }
+
+                (Some(existing_file), ReplacementStrategy::Replace) => {
+                    self.transaction.stage_trash(existing_file)?;
+                    self.transaction
+                        .stage_note(existing_file, &note.metadata, &note.contents)?;
+                }
+
+                (existing_file, _) => self.transaction.stage_note(
+                    existing_file.as_ref().unwrap_or(&note.default_path),
+                    &note.metadata,
+                    &note.contents,
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Child documents of the given category, or of any category, oldest first.
+    fn document_children(&self, document: &Document, category: Option<&str>) -> Vec<&Document> {
+        let mut children = self
+            .library
+            .documents
+            .iter()
+            .filter(|child| child.parent_id.as_deref() == Some(document.id.as_str()))
+            .filter(|child| category.is_none() || child.category.as_deref() == category)
+            .collect::<Vec<_>>();
+
+        children.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        children
+    }
+
+    /// A highlight made in Reader, shaped like a Readwise highlight so the same highlight template
+    /// can render both.
+    fn document_highlight(
+        &self,
+        document: &Document,
+        child: &Document,
+        index: usize,
+    ) -> serde_json::Value {
+        let category = document.category.as_deref().unwrap_or("");
+        // Reader tags are an object keyed by tag name
+        let tags = document
+            .tags
+            .as_ref()
+            .and_then(|t| t.as_object())
+            .map(|t| t.keys().map(String::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let clean = |text: Option<&str>| {
+            text_cleanup::clean(&self.text_cleanup, category, &tags, text.unwrap_or(""))
+        };
+
+        json!({
+            "id": child.id,
+            "text": clean(child.content.as_deref()),
+            "note": clean(child.notes.as_deref()),
+            "location": index,
+            "location_type": "order",
+            "highlighted_at": child.created_at,
+            "updated": child.updated_at,
+            "url": child.url,
+            "color": "",
+            "tags": child.tags,
+        })
+    }
+
+    /// The language of a document, from its content or summary and title, if languages are detected
+    /// and it could be.
+    fn document_language(&self, document: &Document) -> Option<String> {
+        self.languages.as_ref()?;
+
+        let text = [
+            document.title.as_deref(),
+            document.summary.as_deref(),
+            document.content.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        language::detect(&text)
+    }
+
+    /// A document as it is given to templates, with the highlights made on it in Reader as
+    /// `highlights`, its notes as `annotations`, and every child document as `children`.
+    pub(crate) fn document_value(&self, document: &Document) -> serde_json::Result<Value> {
+        let highlights = self
+            .document_children(document, Some("highlight"))
+            .into_iter()
+            .enumerate()
+            .map(|(index, child)| self.document_highlight(document, child, index))
+            .collect::<Vec<_>>();
+
+        let annotations = self.document_children(document, Some("note"));
+        let children = self.document_children(document, None);
+
+        let mut value = serde_json::to_value(document)?;
+        if let Some(object) = value.as_object_mut() {
+            object.insert("highlights".to_string(), json!(highlights));
+            object.insert("annotations".to_string(), json!(annotations));
+            object.insert("children".to_string(), json!(children));
+            object.insert(
+                "first_synced_at".to_string(),
+                json!(self.library.first_synced.documents.get(&document.id)),
+            );
+        }
+
+        Ok(value)
+    }
+
+    /// A document shaped like a Readwise book, so the highlight template can use `book` whether it
+    /// renders a book's highlights or a document's.
+    fn document_book_value(&self, document: &Document, num_highlights: usize) -> Value {
+        let tags = document
+            .tags
+            .as_ref()
+            .and_then(|t| t.as_object())
+            .map(|t| {
+                t.keys()
+                    .map(|name| json!({ "name": name }))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let reader_url = format!("https://read.readwise.io/read/{}", document.id);
+
+        json!({
+            "id": document.id,
+            "title": document.title.as_deref().unwrap_or(&document.url),
+            "readable_title": document.title,
+            "author": document.author,
+            "category": document.category,
+            "num_highlights": num_highlights,
+            "updated": document.updated_at,
+            "cover_image_url": document.image_url,
+            "highlights_url": reader_url,
+            "readwise_url": reader_url,
+            "source_url": document.source_url,
+            "unique_url": document.url,
+            "tags": tags,
+            "document_note": document.notes,
+            "source": document.source,
+        })
+    }
+
+    fn create_document_context(&self, document: &Document) -> anyhow::Result<Context> {
+        let document_value = self.document_value(document)?;
+        let num_highlights = document_value["highlights"]
+            .as_array()
+            .map_or(0, |highlights| highlights.len());
+        let book_value = self.document_book_value(document, num_highlights);
+
+        let mut context = Context::from_value(document_value.clone())?;
+        context.insert("document", &document_value);
+        context.insert("display_title", &book_value["title"]);
+        context.insert("book", &book_value);
+        context.insert("language", &self.document_language(document));
+        Ok(context)
+    }
+
+    fn render_document(
+        &self,
+        document: &Document,
+        existing_note: Option<&NoteReference>,
+    ) -> anyhow::Result<String> {
+        let context = self.create_document_context(document)?;
+        let highlights_begin_token = "%% HIGHLIGHTS_BEGIN %%";
+
+        let contents = if let Some(existing_note) = existing_note {
+            let existing_file_contents = existing_note.parts::<serde_yml::Mapping>()?.1;
+            match existing_file_contents.find(highlights_begin_token) {
+                Some(index) => existing_file_contents.split_at(index).0.to_string(),
+                None => {
+                    warn!(
+                        "Existing note for document '{}' did not contain highlights begin token",
+                        document.id
+                    );
+                    existing_file_contents
+                }
+            }
+        } else {
+            self.templates.render("document", &context)?
+        };
+
+        let highlight_contents = self.render_document_highlights(&context)?;
+
+        let article = document
+            .html_content
+            .as_deref()
+            .filter(|_| self.embed_article)
+            .map(articles::readable_markdown)
+            .filter(|markdown| !markdown.is_empty())
+            .map(|markdown| {
+                format!(
+                    "\n{}\n",
+                    articles::collapsed_callout("abstract", "Article", &markdown)
+                )
+            })
+            .unwrap_or_default();
+
+        let sections = match self.document_hierarchy {
+            DocumentHierarchy::Sections => self.child_sections(document)?,
+            _ => vec![],
+        };
+
+        let body = std::iter::once(highlight_contents)
+            .chain(sections)
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        Ok(format!(
+            "{}\n\n{}\n\n{}\n{}",
+            contents.trim(),
+            highlights_begin_token,
+            body,
+            article
+        ))
+    }
+
+    /// Render each of the highlights in a document's context with the highlight template.
+    fn render_document_highlights(&self, context: &Context) -> anyhow::Result<String> {
+        let highlight_contents = context
+            .get("highlights")
+            .and_then(|h| h.as_array())
+            .into_iter()
+            .flatten()
+            .map(|highlight| {
+                let mut highlight_context = context.clone();
+                highlight_context.insert("highlight", highlight);
+
+                self.templates.render("highlight", &highlight_context)
+            })
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(highlight_contents.join("\n\n").trim().to_string())
+    }
+
+    /// A section for each child document of a document, oldest first, headed by its title and
+    /// followed by its own highlights.
+    fn child_sections(&self, document: &Document) -> anyhow::Result<Vec<String>> {
+        let mut children = self
+            .library
+            .documents
+            .iter()
+            .filter(|child| child.parent_id.as_deref() == Some(document.id.as_str()))
+            .filter(|child| !is_annotation(child))
+            .collect::<Vec<_>>();
+        children.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+        children
+            .into_iter()
+            .map(|child| {
+                let context = self.create_document_context(child)?;
+                let highlights = self.render_document_highlights(&context)?;
+                let heading = format!(
+                    "## {}\n\n[Open in Reader](https://read.readwise.io/read/{})",
+                    child.title.as_deref().unwrap_or(&child.url),
+                    child.id
+                );
+
+                Ok(match highlights.is_empty() {
+                    true => heading,
+                    false => format!("{}\n\n{}", heading, highlights),
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(skip_all, fields(document = %document.id))]
+    fn export_document(
+        &self,
+        root: &Path,
+        document: &Document,
+        existing_note: Option<&NoteReference>,
+    ) -> anyhow::Result<JoinedNote<String, serde_yml::Value>> {
+        debug!(
+            "Starting export of document '{}' into '{:?}'",
+            document.id, root
+        );
+
+        let title = self.sanitize_title(
+            document.title.as_deref().unwrap_or(&document.url),
+            &format!("document-{}", document.id),
+        );
+
+        let contents = self.render_document(document, existing_note)?;
+
+        let mut metadata = serde_yml::to_value(document)?;
+        {
+            let metadata = metadata.as_mapping_mut().ok_or_else(|| {
+                anyhow::anyhow!("Metadata for document {} was not a mapping", document.id)
+            })?;
+
+            // The stored HTML is far too large for frontmatter
+            metadata.remove("html_content");
+
+            metadata.insert(
+                serde_yml::Value::from("note-kind"),
+                serde_yml::Value::from("readwise-document"),
+            );
+
+            metadata.insert(
+                serde_yml::Value::from("__readwise_document_fk"),
+                serde_yml::Value::from(document.id.as_str()),
+            );
+
+            if let Some(language) = self.document_language(document) {
+                metadata.insert(
+                    serde_yml::Value::from("lang"),
+                    serde_yml::Value::from(language),
+                );
+            }
+
+            if let Some(document_title) = &document.title {
+                self.add_aliases(
+                    metadata,
+                    &title,
+                    document_title,
+                    document.author.as_deref(),
+                    None,
+                );
+            }
+
+            self.add_document_provenance(metadata);
+
+            if let Some(property_types) = &self.property_types {
+                property_types.apply(metadata);
+            }
+
+            metadata.insert(
+                serde_yml::Value::from(LAST_MOVED_KEY),
+                serde_yml::Value::from(document.last_moved_at.as_str()),
+            );
+        }
+
+        Ok(JoinedNote {
+            note_id: document.id.clone(),
+            default_path: PathBuf::from(root).join(title).with_extension("md"),
+            contents,
+            metadata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{document, exporter, library, vault};
+    use crate::Library;
+
+    #[test]
+    fn document_highlights_are_given_a_book() {
+        let vault = vault("document-book");
+        let library = Library {
+            documents: vec![
+                document("doc", "An Article", "article", None),
+                document("hl", "A highlight", "highlight", Some("doc")),
+            ],
+            ..library(vec![], vec![])
+        };
+        let exporter = exporter(&vault, library, &[]);
+
+        let context = exporter
+            .create_document_context(&exporter.library.documents[0])
+            .unwrap();
+        let book = context.get("book").unwrap();
+        assert_eq!(book["title"], "An Article");
+        assert_eq!(book["num_highlights"], 1);
+        assert_eq!(context.get("display_title").unwrap(), "An Article");
+
+        std::fs::remove_dir_all(vault).ok();
+    }
+}
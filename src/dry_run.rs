@@ -0,0 +1,67 @@
+use crate::Library;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::hash::Hash;
+
+/// How the records of one kind would change.
+#[derive(Default)]
+struct Changes {
+    new: usize,
+    updated: usize,
+    removed: usize,
+}
+
+fn changes<T: Serialize, K: Eq + Hash>(
+    before: &[T],
+    after: &[T],
+    key: impl Fn(&T) -> K,
+) -> Changes {
+    let before = before
+        .iter()
+        .map(|record| (key(record), serde_json::to_value(record).ok()))
+        .collect::<HashMap<_, _>>();
+
+    let mut changes = Changes::default();
+    let mut kept = 0;
+    for record in after {
+        match before.get(&key(record)) {
+            None => changes.new += 1,
+            Some(value) => {
+                kept += 1;
+                if *value != serde_json::to_value(record).ok() {
+                    changes.updated += 1;
+                }
+            }
+        }
+    }
+
+    changes.removed = before.len() - kept;
+    changes
+}
+
+/// Describe how a fetch would change the library, comparing it before and after the fetch.
+pub fn summarize(before: &Library, after: &Library) -> anyhow::Result<String> {
+    let mut out = String::new();
+    let kinds = [
+        ("Books", changes(&before.books, &after.books, |b| b.id)),
+        (
+            "Highlights",
+            changes(&before.highlights, &after.highlights, |h| h.id),
+        ),
+        (
+            "Documents",
+            changes(&before.documents, &after.documents, |d| d.id.clone()),
+        ),
+    ];
+
+    for (name, changes) in kinds {
+        writeln!(
+            out,
+            "{}: {} new, {} updated, {} removed",
+            name, changes.new, changes.updated, changes.removed
+        )?;
+    }
+
+    Ok(out)
+}
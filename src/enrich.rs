@@ -0,0 +1,172 @@
+use crate::readwise::{http_client, send, Book};
+use crate::Library;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+/// Bibliographic metadata looked up from an external catalogue, Readwise's own metadata for books
+/// is often sparse.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BookMetadata {
+    pub isbn: Option<String>,
+    pub publication_year: Option<i32>,
+    pub page_count: Option<i32>,
+    pub cover_url: Option<String>,
+
+    /// When the lookup was made, recorded even if nothing was found so that books aren't looked up
+    /// again on every run.
+    pub looked_up_at: DateTime<Utc>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum MetadataProvider {
+    /// https://openlibrary.org
+    OpenLibrary,
+
+    /// https://books.google.com
+    GoogleBooks,
+}
+
+/// An ASIN which is also an ISBN-10, as is the case for most print books sold on Amazon.
+fn isbn_from_asin(book: &Book) -> Option<&str> {
+    book.asin
+        .as_deref()
+        .filter(|asin| asin.len() == 10)
+        .filter(|asin| {
+            asin.chars()
+                .enumerate()
+                .all(|(i, c)| c.is_ascii_digit() || (i == 9 && c == 'X'))
+        })
+}
+
+pub struct Enricher {
+    provider: MetadataProvider,
+    client: reqwest::Client,
+}
+
+impl Enricher {
+    pub fn new(provider: MetadataProvider) -> Self {
+        Self {
+            provider,
+            client: http_client(),
+        }
+    }
+
+    /// Look up metadata for every book in the given category which hasn't already been enriched,
+    /// or for every book if `refresh` is set.
+    pub async fn enrich_library(
+        &self,
+        library: &mut Library,
+        category: &str,
+        refresh: bool,
+    ) -> anyhow::Result<()> {
+        let pending = library
+            .books
+            .iter()
+            .filter(|book| book.category == category)
+            .filter(|book| refresh || !library.book_metadata.contains_key(&book.id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        info!("Looking up metadata for {} books", pending.len());
+
+        for book in pending {
+            match self.lookup(&book).await {
+                Ok(metadata) => {
+                    debug!("Metadata for book '{}': {:?}", book.title, metadata);
+                    library.book_metadata.insert(book.id, metadata);
+                }
+
+                Err(err) => warn!("Failed to look up metadata for '{}': {:?}", book.title, err),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_json(&self, url: Url) -> anyhow::Result<Value> {
+        debug!("Metadata lookup url: {}", url);
+        let response = send(self.client.get(url)).await?.error_for_status()?;
+        Ok(response.json::<Value>().await?)
+    }
+
+    async fn lookup(&self, book: &Book) -> anyhow::Result<BookMetadata> {
+        let mut metadata = BookMetadata {
+            looked_up_at: Utc::now(),
+            ..Default::default()
+        };
+
+        match self.provider {
+            MetadataProvider::OpenLibrary => {
+                let mut url: Url = "https://openlibrary.org/search.json".parse().unwrap();
+                {
+                    let mut query = url.query_pairs_mut();
+                    if let Some(isbn) = isbn_from_asin(book) {
+                        query.append_pair("isbn", isbn);
+                    } else {
+                        query.append_pair("title", &book.title);
+                        if let Some(author) = &book.author {
+                            query.append_pair("author", author);
+                        }
+                    }
+
+                    query.append_pair("limit", "1");
+                }
+
+                let response = self.get_json(url).await?;
+                if let Some(doc) = response["docs"].get(0) {
+                    metadata.isbn = doc["isbn"][0].as_str().map(str::to_string);
+                    metadata.publication_year =
+                        doc["first_publish_year"].as_i64().map(|y| y as i32);
+                    metadata.page_count = doc["number_of_pages_median"].as_i64().map(|p| p as i32);
+                    metadata.cover_url = doc["cover_i"]
+                        .as_i64()
+                        .map(|id| format!("https://covers.openlibrary.org/b/id/{}-L.jpg", id));
+                }
+            }
+
+            MetadataProvider::GoogleBooks => {
+                let query = match isbn_from_asin(book) {
+                    Some(isbn) => format!("isbn:{}", isbn),
+                    None => match &book.author {
+                        Some(author) => format!("intitle:{} inauthor:{}", book.title, author),
+                        None => format!("intitle:{}", book.title),
+                    },
+                };
+
+                let mut url: Url = "https://www.googleapis.com/books/v1/volumes"
+                    .parse()
+                    .unwrap();
+                url.query_pairs_mut()
+                    .append_pair("q", &query)
+                    .append_pair("maxResults", "1");
+
+                let response = self.get_json(url).await?;
+                let volume = &response["items"][0]["volumeInfo"];
+                if volume.is_object() {
+                    metadata.isbn = volume["industryIdentifiers"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .find(|id| id["type"] == "ISBN_13")
+                        .or_else(|| volume["industryIdentifiers"].get(0))
+                        .and_then(|id| id["identifier"].as_str())
+                        .map(str::to_string);
+                    metadata.publication_year = volume["publishedDate"]
+                        .as_str()
+                        .and_then(|d| d.get(0..4))
+                        .and_then(|y| y.parse().ok());
+                    metadata.page_count = volume["pageCount"].as_i64().map(|p| p as i32);
+                    metadata.cover_url = volume["imageLinks"]["thumbnail"]
+                        .as_str()
+                        .map(|u| u.replace("http://", "https://"));
+                }
+            }
+        }
+
+        Ok(metadata)
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// A record which was skipped or failed during a run, without stopping the rest of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordError {
+    /// What the record is, e.g. `book`, `highlights`, or `document`
+    pub kind: String,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub reason: String,
+}
+
+/// The errors of the latest run of a command.
+#[derive(Serialize, Deserialize)]
+struct CommandErrors {
+    at: DateTime<Utc>,
+    errors: Vec<RecordError>,
+}
+
+/// Where the error report is written by default, `errors.json` beside the library cache.
+pub fn default_report_path(library: &Path) -> PathBuf {
+    library.with_file_name("errors.json")
+}
+
+/// Record what a command skipped or failed on in the report at `path`, so they can be
+/// fixed one by one. Each command's errors replace those of its previous run, and the report is
+/// removed once no command has any, so it never goes stale.
+pub fn write_report(path: &Path, command: &str, errors: &[RecordError]) -> anyhow::Result<()> {
+    let mut report: BTreeMap<String, CommandErrors> = match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        Err(_) => BTreeMap::new(),
+    };
+
+    report.remove(command);
+    if !errors.is_empty() {
+        info!("Reporting {} errors in {:?}", errors.len(), path);
+        report.insert(
+            command.to_string(),
+            CommandErrors {
+                at: Utc::now(),
+                errors: errors.to_vec(),
+            },
+        );
+    }
+
+    if report.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        return Ok(());
+    }
+
+    std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    Ok(())
+}
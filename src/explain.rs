@@ -0,0 +1,81 @@
+use crate::Exporter;
+use anyhow::anyhow;
+use itertools::Itertools;
+use std::fmt::Write as _;
+
+impl Exporter {
+    /// Describe what exporting a single book would do, without writing anything to the vault.
+    pub(crate) fn explain(&mut self, book_id: i32) -> anyhow::Result<String> {
+        let book = self
+            .library
+            .books
+            .iter()
+            .find(|b| b.id == book_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("No book with id {} in the library", book_id))?;
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Book {}: '{}' by {} in '{}'",
+            book.id,
+            book.title,
+            book.author.as_deref().unwrap_or("unknown author"),
+            book.category
+        )?;
+
+        match self.skip_reason(&book) {
+            Some(reason) => writeln!(out, "Would be skipped because {}", reason)?,
+            None => writeln!(out, "Would be exported")?,
+        }
+
+        let book = self.as_rendered(book);
+
+        writeln!(out, "Highlights: {}", self.highlights_for(&book).len())?;
+
+        let category_root = self.category_root(&book.category)?;
+        let existing_note = self.remaining_existing.get(&book.id).cloned().or_else(|| {
+            self.library
+                .book_merges
+                .iter()
+                .filter(|(_, target)| **target == book.id)
+                .find_map(|(merged, _)| self.remaining_existing.get(merged).cloned())
+        });
+
+        let note = self.export_book(&category_root, &book, existing_note.as_ref())?;
+
+        match &existing_note {
+            Some(existing) => writeln!(
+                out,
+                "Joins existing note: {}",
+                existing.to_path_buf().display()
+            )?,
+            None => match self.unmanaged.adopt(&book.title, &note.default_path) {
+                Some(adopted) => writeln!(out, "Adopts unmanaged note: {}", adopted.display())?,
+                None => writeln!(out, "No existing note, a new note would be created")?,
+            },
+        }
+
+        writeln!(out, "Default path: {}", note.default_path.display())?;
+        writeln!(
+            out,
+            "Templates: book = '{}', highlight = '{}'",
+            self.template_for(&book, "book"),
+            self.template_for(&book, "highlight")
+        )?;
+
+        let context = self
+            .create_template_context(&&book, &self.highlights_for(&book))?
+            .into_json();
+        let variables = context
+            .as_object()
+            .into_iter()
+            .flat_map(|o| o.keys())
+            .sorted()
+            .join(", ");
+        writeln!(out, "Template variables: {}", variables)?;
+        writeln!(out, "Metadata:\n{}", serde_yml::to_string(&note.metadata)?)?;
+
+        Ok(out)
+    }
+}
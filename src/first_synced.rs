@@ -0,0 +1,41 @@
+use crate::Library;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// When each record first entered the library cache, apart from Readwise's own timestamps, which
+/// move as records are edited. Records already in the cache when these were first recorded have
+/// the time of that sync.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FirstSynced {
+    #[serde(default)]
+    pub books: HashMap<i32, DateTime<Utc>>,
+    #[serde(default)]
+    pub highlights: HashMap<i32, DateTime<Utc>>,
+    #[serde(default)]
+    pub documents: HashMap<String, DateTime<Utc>>,
+}
+
+impl Library {
+    /// Record `at` as when the records in the library without a first synced time were first
+    /// synced.
+    pub(crate) fn record_first_synced(&mut self, at: DateTime<Utc>) {
+        for book in &self.books {
+            self.first_synced.books.entry(book.id).or_insert(at);
+        }
+
+        for highlight in &self.highlights {
+            self.first_synced
+                .highlights
+                .entry(highlight.id)
+                .or_insert(at);
+        }
+
+        for document in &self.documents {
+            self.first_synced
+                .documents
+                .entry(document.id.clone())
+                .or_insert(at);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::transaction::AppliedChanges;
+use anyhow::{anyhow, Context as _};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tera::{Context, Tera};
+use tracing::{debug, info};
+
+pub const DEFAULT_MESSAGE_TEMPLATE: &str = "Readwise export: {{ created | length }} added, {{ updated | length }} updated{% if trashed %}, {{ trashed | length }} removed{% endif %}
+{% for note in created %}
++ {{ note }}{% endfor %}{% for note in updated %}
+~ {{ note }}{% endfor %}{% for note in trashed %}
+- {{ note }}{% endfor %}
+";
+
+fn git(vault_root: &Path, args: &[&str]) -> anyhow::Result<std::process::Output> {
+    debug!("Running git {}", args.join(" "));
+    Command::new("git")
+        .arg("-C")
+        .arg(vault_root)
+        .args(args)
+        .output()
+        .context("Failed to run git")
+}
+
+fn git_checked(vault_root: &Path, args: &[&str]) -> anyhow::Result<()> {
+    let output = git(vault_root, args)?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+fn note_paths(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .collect()
+}
+
+fn note_name(path: &str) -> String {
+    Path::new(path)
+        .with_extension("")
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// The staged changes to `paths` as the notes created, updated, and trashed, relative to the vault
+/// root. Notes rewritten exactly as they were aren't staged, so aren't among them.
+fn staged_changes(
+    vault_root: &Path,
+    paths: &[String],
+) -> anyhow::Result<(Vec<String>, Vec<String>, Vec<String>)> {
+    let mut diff = vec![
+        "diff",
+        "--cached",
+        "--relative",
+        "--no-renames",
+        "--name-status",
+        "-z",
+        "--",
+    ];
+    diff.extend(paths.iter().map(String::as_str));
+    let output = git(vault_root, &diff)?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git diff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let (mut created, mut updated, mut trashed) = (vec![], vec![], vec![]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.split('\0').filter(|f| !f.is_empty());
+    while let (Some(status), Some(path)) = (fields.next(), fields.next()) {
+        match status {
+            "A" => created.push(note_name(path)),
+            "D" => trashed.push(note_name(path)),
+            _ => updated.push(note_name(path)),
+        }
+    }
+
+    Ok((created, updated, trashed))
+}
+
+/// Commit the notes changed by an export to the git repository containing the vault, leaving any
+/// other changes in the repository alone. The message template is rendered with the `created`,
+/// `updated`, and `trashed` note paths, as git sees them, so notes rewritten unchanged aren't
+/// counted.
+pub fn commit_export(
+    vault_root: &Path,
+    applied: &AppliedChanges,
+    message_template: &str,
+    push: bool,
+) -> anyhow::Result<()> {
+    let inside = git(vault_root, &["rev-parse", "--is-inside-work-tree"])?;
+    if !inside.status.success() {
+        return Err(anyhow!("Vault {:?} is not in a git repository", vault_root));
+    }
+
+    // Trashed notes which were never committed have nothing to record, and would fail the pathspec
+    let mut ls_files = vec!["ls-files", "--"];
+    let trashed = note_paths(&applied.trashed);
+    ls_files.extend(trashed.iter().map(String::as_str));
+    let tracked = if trashed.is_empty() {
+        String::new()
+    } else {
+        String::from_utf8_lossy(&git(vault_root, &ls_files)?.stdout).to_string()
+    };
+
+    let paths = note_paths(&applied.created)
+        .into_iter()
+        .chain(note_paths(&applied.updated))
+        .chain(
+            trashed
+                .into_iter()
+                .filter(|path| tracked.lines().any(|line| line == path)),
+        )
+        .collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        info!("No notes changed, skipping git commit");
+        return Ok(());
+    }
+
+    let mut add = vec!["add", "--all", "--"];
+    add.extend(paths.iter().map(String::as_str));
+    git_checked(vault_root, &add)?;
+
+    let (created, updated, trashed) = staged_changes(vault_root, &paths)?;
+    if created.is_empty() && updated.is_empty() && trashed.is_empty() {
+        info!("Exported notes are unchanged in git, skipping commit");
+        return Ok(());
+    }
+
+    let mut context = Context::new();
+    context.insert("created", &created);
+    context.insert("updated", &updated);
+    context.insert("trashed", &trashed);
+    let message = Tera::one_off(message_template, &context, false)?;
+
+    let mut commit = vec!["commit", "--quiet", "--message", message.trim(), "--"];
+    commit.extend(paths.iter().map(String::as_str));
+    git_checked(vault_root, &commit)?;
+    info!(
+        "Committed {} exported notes",
+        created.len() + updated.len() + trashed.len()
+    );
+
+    if push {
+        git_checked(vault_root, &["push", "--quiet"])?;
+        info!("Pushed export commit");
+    }
+
+    Ok(())
+}
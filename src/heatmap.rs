@@ -0,0 +1,69 @@
+use crate::readwise::parse_timestamp;
+use crate::Exporter;
+use chrono::{Months, Utc};
+use std::collections::BTreeMap;
+use tracing::info;
+
+/// Renders the activity stored in the note's frontmatter with the Heatmap Calendar plugin.
+const HEATMAP_BLOCK: &str = r#"# Highlight Activity
+
+```dataviewjs
+const activity = dv.current().highlight_activity ?? {};
+renderHeatmapCalendar(this.container, {
+    entries: Object.entries(activity).map(([date, count]) => ({ date, intensity: count, content: String(count) })),
+});
+```
+"#;
+
+impl Exporter {
+    /// Write the number of highlights made on each day over the last `months` months, either as a
+    /// JSON object of dates to counts when `file_name` ends in `.json`, or otherwise as the
+    /// `highlight_activity` frontmatter of a note which renders it with the Heatmap Calendar
+    /// plugin. Regenerated on each export.
+    pub(crate) fn write_heatmap(&mut self, file_name: &str, months: u32) -> anyhow::Result<()> {
+        let since = Utc::now()
+            .checked_sub_months(Months::new(months))
+            .unwrap_or_default();
+
+        let mut activity: BTreeMap<String, u32> = BTreeMap::new();
+        for highlight in self
+            .library
+            .highlights
+            .iter()
+            .filter(|h| self.exports_highlight(h))
+        {
+            if let Some(at) = highlight
+                .highlighted_at
+                .as_deref()
+                .and_then(parse_timestamp)
+            {
+                if at >= since {
+                    *activity
+                        .entry(at.format("%Y-%m-%d").to_string())
+                        .or_default() += 1;
+                }
+            }
+        }
+
+        let path = self.export_root.join(file_name);
+        info!(
+            "Writing highlight activity for {} days to {:?}",
+            activity.len(),
+            path
+        );
+
+        if path.extension().is_some_and(|e| e == "json") {
+            self.transaction
+                .stage_file(&path, serde_json::to_string_pretty(&activity)?)
+        } else {
+            let mut metadata = serde_yml::Mapping::new();
+            metadata.insert(
+                serde_yml::Value::from("highlight_activity"),
+                serde_yml::to_value(&activity)?,
+            );
+
+            self.transaction
+                .stage_note(&path, &serde_yml::Value::Mapping(metadata), HEATMAP_BLOCK)
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::import::synthetic_id;
+use crate::readwise::{http_client, send, Book, Highlight, Tag};
+use chrono::{DateTime, Utc};
+use reqwest::header::AUTHORIZATION;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::{debug, info, instrument};
+
+/// A client for the Hypothes.is annotation API, used to pull open-web annotations into the library
+/// alongside Readwise highlights.
+pub struct Hypothesis {
+    username: String,
+    token: String,
+    api_endpoint: Url,
+    api_page_size: i32,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    rows: Vec<Annotation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Annotation {
+    id: String,
+    updated: String,
+    uri: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    target: Vec<Target>,
+    #[serde(default)]
+    document: AnnotationDocument,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AnnotationDocument {
+    #[serde(default)]
+    title: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Target {
+    #[serde(default)]
+    selector: Vec<Selector>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum Selector {
+    #[serde(rename = "TextQuoteSelector")]
+    TextQuote { exact: String },
+    #[serde(rename = "TextPositionSelector")]
+    TextPosition { start: i32 },
+    #[serde(other)]
+    Other,
+}
+
+fn tags(names: &[String]) -> Vec<Tag> {
+    names
+        .iter()
+        .map(|name| Tag {
+            id: synthetic_id("hypothesis-tag", name),
+            name: name.clone(),
+        })
+        .collect()
+}
+
+impl Hypothesis {
+    pub fn new(username: &str, token: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            token: token.to_string(),
+            api_endpoint: "https://api.hypothes.is/api".parse().unwrap(),
+            api_page_size: 200,
+            client: http_client(),
+        }
+    }
+
+    /// Fetch the user's annotations, mapped into highlights on one synthetic article book per
+    /// annotated page. Page notes without a quoted selection are skipped.
+    #[instrument(skip_all)]
+    pub async fn fetch_annotations(
+        &self,
+        last_updated: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Vec<Book>, Vec<Highlight>)> {
+        info!(
+            "Fetching annotations from Hypothes.is, since {}",
+            last_updated
+                .map(|v| v.to_rfc3339())
+                .unwrap_or("[all]".to_string())
+        );
+
+        let mut annotations = vec![];
+        let mut search_after = last_updated.map(|v| v.to_rfc3339());
+
+        loop {
+            let mut url = self.api_endpoint.clone();
+            url.path_segments_mut().unwrap().push("search");
+
+            {
+                let mut query = url.query_pairs_mut();
+                query
+                    .append_pair("user", &format!("acct:{}@hypothes.is", self.username))
+                    .append_pair("sort", "updated")
+                    .append_pair("order", "asc")
+                    .append_pair("limit", &self.api_page_size.to_string());
+
+                if let Some(search_after) = &search_after {
+                    query.append_pair("search_after", search_after);
+                }
+            }
+
+            debug!("Hypothes.is api url: {}", url);
+
+            let response = send(
+                self.client
+                    .get(url)
+                    .header(AUTHORIZATION, format!("Bearer {}", self.token)),
+            )
+            .await?;
+
+            if !response.status().is_success() {
+                return Err(anyhow::anyhow!("Unexpected response: {:?}", response));
+            }
+
+            let response = response.json::<SearchResponse>().await?;
+            debug!("Received {} annotations", response.rows.len());
+
+            let Some(last) = response.rows.last() else {
+                break;
+            };
+
+            search_after = Some(last.updated.clone());
+            let page_len = response.rows.len();
+            annotations.extend(response.rows);
+
+            if page_len < self.api_page_size as usize {
+                break;
+            }
+        }
+
+        let mut books: HashMap<String, Book> = HashMap::new();
+        let mut highlights = vec![];
+
+        for annotation in annotations {
+            let selectors = annotation.target.iter().flat_map(|t| t.selector.iter());
+            let mut quote = None;
+            let mut position = 0;
+            for selector in selectors {
+                match selector {
+                    Selector::TextQuote { exact } => quote = Some(exact.clone()),
+                    Selector::TextPosition { start } => position = *start,
+                    Selector::Other => {}
+                }
+            }
+
+            let Some(quote) = quote else {
+                debug!("Skipping annotation {} without a quote", annotation.id);
+                continue;
+            };
+
+            let book_id = synthetic_id("hypothesis-book", &annotation.uri);
+            let book = books.entry(annotation.uri.clone()).or_insert_with(|| Book {
+                id: book_id,
+                title: annotation
+                    .document
+                    .title
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| annotation.uri.clone()),
+                author: None,
+                category: "articles".to_string(),
+                num_highlights: 0,
+                last_highlight_at: None,
+                updated: None,
+                cover_image_url: None,
+                highlights_url: None,
+                source_url: Some(annotation.uri.clone()),
+                asin: None,
+                tags: vec![],
+                readable_title: None,
+                document_note: None,
+                source: Some("hypothesis".to_string()),
+                unique_url: Some(annotation.uri.clone()),
+            });
+
+            book.num_highlights += 1;
+            book.last_highlight_at = book
+                .last_highlight_at
+                .clone()
+                .max(Some(annotation.updated.clone()));
+            book.updated = book.last_highlight_at.clone();
+
+            highlights.push(Highlight {
+                id: synthetic_id("hypothesis", &annotation.id),
+                text: quote,
+                note: annotation.text,
+                location: position,
+                location_type: "offset".to_string(),
+                highlighted_at: Some(annotation.updated.clone()),
+                url: Some(format!("https://hyp.is/{}", annotation.id)),
+                color: String::new(),
+                updated: annotation.updated,
+                book_id,
+                tags: tags(&annotation.tags),
+                is_favorite: false,
+                is_discard: false,
+                end_location: None,
+                external_id: Some(annotation.id.clone()),
+            });
+        }
+
+        info!(
+            "Fetched {} annotations on {} pages from Hypothes.is",
+            highlights.len(),
+            books.len()
+        );
+
+        Ok((books.into_values().collect(), highlights))
+    }
+}
@@ -0,0 +1,83 @@
+use crate::readwise::Document;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+
+mod instapaper;
+mod pocket;
+mod readwise_csv;
+
+pub use instapaper::import_instapaper;
+pub use pocket::import_pocket;
+pub use readwise_csv::{import_readwise_csv, reconcile_readwise_csv};
+
+/// A stable id for a record which did not come from the Readwise API. These are always negative so
+/// they can never collide with the ids Readwise assigns.
+pub fn synthetic_id(namespace: &str, key: &str) -> i32 {
+    // FNV-1a, chosen over the std hasher as its output is stable between releases
+    let hash = namespace
+        .bytes()
+        .chain(std::iter::once(0))
+        .chain(key.bytes())
+        .fold(0x811c9dc5u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        });
+
+    -1 - (hash % i32::MAX as u32) as i32
+}
+
+/// The time of a Unix timestamp in an export. Missing or unreadable timestamps are given the Unix
+/// epoch rather than the current time, so importing the same export again gives the same documents.
+pub(crate) fn export_timestamp(timestamp: Option<&str>) -> DateTime<Utc> {
+    timestamp
+        .and_then(|t| t.trim().parse::<i64>().ok())
+        .and_then(|t| DateTime::from_timestamp(t, 0))
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+/// A Reader-shaped document for an article read through another service before Readwise Reader.
+/// These are always placed in the archive as the reading history they represent is complete.
+pub(crate) fn archived_document(
+    source: &str,
+    url: String,
+    title: Option<String>,
+    saved_at: DateTime<Utc>,
+    tags: Vec<String>,
+) -> Document {
+    let saved_at = saved_at.to_rfc3339();
+    let tags = tags
+        .into_iter()
+        .map(|name| (name.clone(), json!({ "name": name })))
+        .collect::<Map<String, Value>>();
+
+    Document {
+        id: format!("{}:{}", source, synthetic_id(source, &url)),
+        title: title.filter(|t| !t.trim().is_empty()),
+        author: None,
+        source: Some(source.to_string()),
+        category: Some("articles".to_string()),
+        location: Some("archive".to_string()),
+        tags: if tags.is_empty() {
+            None
+        } else {
+            Some(Value::Object(tags))
+        },
+        site_name: None,
+        word_count: None,
+        created_at: saved_at.clone(),
+        updated_at: saved_at.clone(),
+        published_date: None,
+        summary: None,
+        image_url: None,
+        content: None,
+        html_content: None,
+        source_url: Some(url.clone()),
+        notes: None,
+        parent_id: None,
+        reading_progress: 1.0,
+        first_opened_at: None,
+        last_opened_at: None,
+        saved_at: saved_at.clone(),
+        last_moved_at: saved_at,
+        url,
+    }
+}
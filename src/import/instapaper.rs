@@ -0,0 +1,43 @@
+use crate::import::{archived_document, export_timestamp};
+use crate::Library;
+use anyhow::Context as _;
+use serde::Deserialize;
+use std::path::Path;
+use tracing::info;
+
+/// A row of the CSV export available from Instapaper's settings page.
+#[derive(Debug, Deserialize)]
+struct InstapaperRow {
+    #[serde(rename = "URL")]
+    url: String,
+    #[serde(rename = "Title", default)]
+    title: Option<String>,
+    #[serde(rename = "Folder", default)]
+    folder: Option<String>,
+    #[serde(rename = "Timestamp", default)]
+    timestamp: Option<String>,
+}
+
+/// Import an Instapaper CSV export into the library's documents. Instapaper folders are kept as
+/// document tags.
+pub fn import_instapaper(library: &mut Library, path: &Path) -> anyhow::Result<()> {
+    let documents = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open Instapaper export {:?}", path))?
+        .deserialize::<InstapaperRow>()
+        .map(|row| {
+            let row = row.context("Failed to parse Instapaper CSV export")?;
+            Ok(archived_document(
+                "instapaper",
+                row.url,
+                row.title,
+                export_timestamp(row.timestamp.as_deref()),
+                row.folder.into_iter().filter(|f| !f.is_empty()).collect(),
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    info!("Imported {} documents from {:?}", documents.len(), path);
+    library.upsert_documents(documents);
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use crate::import::{archived_document, export_timestamp};
+use crate::Library;
+use anyhow::{anyhow, Context as _};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::info;
+
+/// An item in the JSON returned by Pocket's retrieve API, which is also what most Pocket backup
+/// tools write out.
+#[derive(Debug, Deserialize)]
+struct PocketItem {
+    given_url: Option<String>,
+    resolved_url: Option<String>,
+    given_title: Option<String>,
+    resolved_title: Option<String>,
+    time_added: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PocketJson {
+    list: HashMap<String, PocketItem>,
+}
+
+/// A row of the CSV export Pocket offers in place of the older HTML export.
+#[derive(Debug, Deserialize)]
+struct PocketCsvRow {
+    title: Option<String>,
+    url: String,
+    time_added: Option<String>,
+    #[serde(default)]
+    tags: Option<String>,
+}
+
+fn split_tags(tags: &str, separator: char) -> Vec<String> {
+    tags.split(separator)
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Import a Pocket export into the library's documents. Pocket has offered its export as HTML,
+/// CSV, and through its API as JSON over the years, the format is chosen by the file extension.
+pub fn import_pocket(library: &mut Library, path: &Path) -> anyhow::Result<()> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let documents = match extension.as_deref() {
+        Some("html") | Some("htm") => {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read Pocket export {:?}", path))?;
+
+            let link = Regex::new(r"(?s)<a\s+([^>]*)>(.*?)</a>").unwrap();
+            let attribute = Regex::new(r#"([\w-]+)="([^"]*)""#).unwrap();
+
+            link.captures_iter(&contents)
+                .filter_map(|link| {
+                    let attributes = attribute
+                        .captures_iter(&link[1])
+                        .map(|a| (a[1].to_string(), unescape_html(&a[2])))
+                        .collect::<HashMap<_, _>>();
+
+                    let url = attributes.get("href")?.clone();
+                    Some(archived_document(
+                        "pocket",
+                        url,
+                        Some(unescape_html(link[2].trim())),
+                        export_timestamp(attributes.get("time_added").map(String::as_str)),
+                        split_tags(attributes.get("tags").map_or("", String::as_str), ','),
+                    ))
+                })
+                .collect::<Vec<_>>()
+        }
+
+        Some("json") => {
+            let export: PocketJson = serde_json::from_reader(std::fs::File::open(path)?)
+                .with_context(|| format!("Failed to parse Pocket export {:?}", path))?;
+
+            export
+                .list
+                .into_values()
+                .filter_map(|item| {
+                    let url = item.resolved_url.or(item.given_url)?;
+                    Some(archived_document(
+                        "pocket",
+                        url,
+                        item.resolved_title.or(item.given_title),
+                        export_timestamp(item.time_added.as_deref()),
+                        item.tags.into_keys().collect(),
+                    ))
+                })
+                .collect::<Vec<_>>()
+        }
+
+        Some("csv") => csv::Reader::from_path(path)?
+            .deserialize::<PocketCsvRow>()
+            .map(|row| {
+                let row = row.context("Failed to parse Pocket CSV export")?;
+                Ok(archived_document(
+                    "pocket",
+                    row.url,
+                    row.title,
+                    export_timestamp(row.time_added.as_deref()),
+                    split_tags(row.tags.as_deref().unwrap_or(""), '|'),
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+
+        _ => {
+            return Err(anyhow!(
+                "Unrecognised Pocket export {:?}, expected a .html, .json, or .csv file",
+                path
+            ))
+        }
+    };
+
+    info!("Imported {} documents from {:?}", documents.len(), path);
+    library.upsert_documents(documents);
+
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use crate::import::synthetic_id;
+use crate::readwise::{Book, Highlight, Tag};
+use crate::Library;
+use anyhow::Context as _;
+use itertools::Itertools;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::{debug, info};
+
+/// A row of the full-account CSV export available from the Readwise website.
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    #[serde(rename = "Highlight")]
+    highlight: String,
+    #[serde(rename = "Book Title")]
+    book_title: String,
+    #[serde(rename = "Book Author", default)]
+    book_author: Option<String>,
+    #[serde(rename = "Amazon Book ID", default)]
+    amazon_book_id: Option<String>,
+    #[serde(rename = "Note", default)]
+    note: Option<String>,
+    #[serde(rename = "Color", default)]
+    color: Option<String>,
+    #[serde(rename = "Tags", default)]
+    tags: Option<String>,
+    #[serde(rename = "Location Type", default)]
+    location_type: Option<String>,
+    #[serde(rename = "Location", default)]
+    location: Option<String>,
+    #[serde(rename = "Highlighted at", default)]
+    highlighted_at: Option<String>,
+    #[serde(rename = "Document tags", default)]
+    document_tags: Option<String>,
+}
+
+fn parse_tags(tags: &Option<String>) -> Vec<Tag> {
+    tags.as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(|name| Tag {
+            id: synthetic_id("readwise-csv-tag", name),
+            name: name.to_string(),
+        })
+        .collect_vec()
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.trim().is_empty())
+}
+
+fn csv_book_id(title: &str, author: &str) -> i32 {
+    synthetic_id("readwise-csv-book", &format!("{}\u{0}{}", title, author))
+}
+
+/// A book's title and author ignoring case and surrounding whitespace, which is all an imported
+/// book has in common with the same book fetched from the API.
+fn book_key(book: &Book) -> String {
+    format!(
+        "{}\u{0}{}",
+        book.title.trim().to_lowercase(),
+        book.author.as_deref().unwrap_or("").trim().to_lowercase()
+    )
+}
+
+/// Move whatever is recorded for the book `from` to the book `to`, unless `to` has its own.
+fn rekey<V>(map: &mut HashMap<i32, V>, from: i32, to: i32) {
+    if let Some(value) = map.remove(&from) {
+        map.entry(to).or_insert(value);
+    }
+}
+
+/// Seed the library from a Readwise CSV export. The export does not include Readwise's own ids so
+/// books and highlights are given synthetic ones; importing the same file twice replaces the
+/// previously imported records rather than duplicating them, and once a book is fetched from the
+/// API its imported records are replaced by it (see [`reconcile_readwise_csv`]).
+pub fn import_readwise_csv(library: &mut Library, path: &Path) -> anyhow::Result<()> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open Readwise CSV export {:?}", path))?;
+
+    let rows = reader
+        .deserialize::<CsvRow>()
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse Readwise CSV export")?;
+
+    debug!("Read {} rows from Readwise CSV export", rows.len());
+
+    let mut books: Vec<Book> = vec![];
+    let mut highlights: Vec<Highlight> = vec![];
+
+    for row in rows {
+        let book_id = csv_book_id(&row.book_title, row.book_author.as_deref().unwrap_or(""));
+
+        // Rows for a book may not be contiguous, so fold into any book we have already created
+        let book = match books.iter_mut().position(|b| b.id == book_id) {
+            Some(index) => &mut books[index],
+            None => {
+                let asin = non_empty(row.amazon_book_id.clone());
+                books.push(Book {
+                    id: book_id,
+                    title: row.book_title.clone(),
+                    author: non_empty(row.book_author.clone()),
+                    // The export doesn't record categories, but only Kindle books carry an ASIN
+                    category: if asin.is_some() { "books" } else { "articles" }.to_string(),
+                    num_highlights: 0,
+                    last_highlight_at: None,
+                    updated: None,
+                    cover_image_url: None,
+                    highlights_url: None,
+                    source_url: None,
+                    asin,
+                    tags: parse_tags(&row.document_tags),
+                    readable_title: None,
+                    document_note: None,
+                    source: None,
+                    unique_url: None,
+                });
+
+                books.last_mut().unwrap()
+            }
+        };
+
+        let highlighted_at = non_empty(row.highlighted_at);
+        let location = row.location.unwrap_or_default();
+
+        book.num_highlights += 1;
+        book.last_highlight_at = book.last_highlight_at.clone().max(highlighted_at.clone());
+        book.updated = book.last_highlight_at.clone();
+
+        highlights.push(Highlight {
+            id: synthetic_id(
+                "readwise-csv-highlight",
+                &format!("{}\u{0}{}\u{0}{}", book_id, location, row.highlight),
+            ),
+            note: row.note.unwrap_or_default(),
+            location: location.parse().unwrap_or(0),
+            location_type: row.location_type.unwrap_or_else(|| "order".to_string()),
+            highlighted_at: highlighted_at.clone(),
+            url: None,
+            color: row.color.unwrap_or_default(),
+            updated: highlighted_at.unwrap_or_default(),
+            book_id,
+            tags: parse_tags(&row.tags),
+            text: row.highlight,
+            is_favorite: false,
+            is_discard: false,
+            end_location: None,
+            external_id: None,
+        });
+    }
+
+    info!(
+        "Imported {} books and {} highlights from {:?}",
+        books.len(),
+        highlights.len(),
+        path
+    );
+
+    library.upsert_books(books);
+    library.upsert_highlights(highlights);
+    reconcile_readwise_csv(library);
+
+    Ok(())
+}
+
+/// Replace the books imported from a Readwise CSV export which have since been fetched from the
+/// API, matched by title and author, with the fetched books. Their imported highlights are matched
+/// to the fetched ones by text and dropped, and any not fetched yet are moved to the fetched book
+/// until they are. The book's exported note, metadata, and other bookkeeping move with it, so the
+/// note is updated in place rather than duplicated.
+pub fn reconcile_readwise_csv(library: &mut Library) {
+    let fetched_books = library
+        .books
+        .iter()
+        .filter(|b| b.id > 0)
+        .map(|b| (book_key(b), b.id))
+        .collect::<HashMap<_, _>>();
+
+    let replaced = library
+        .books
+        .iter()
+        .filter(|b| b.id == csv_book_id(&b.title, b.author.as_deref().unwrap_or("")))
+        .filter_map(|b| Some((b.id, *fetched_books.get(&book_key(b))?)))
+        .collect::<HashMap<_, _>>();
+
+    if replaced.is_empty() {
+        return;
+    }
+
+    info!(
+        "Replacing {} books imported from a Readwise CSV export with those fetched from Readwise",
+        replaced.len()
+    );
+
+    let fetched_highlights = library
+        .highlights
+        .iter()
+        .filter(|h| h.id > 0)
+        .map(|h| (h.book_id, h.text.trim().to_string()))
+        .collect::<HashSet<_>>();
+
+    library.highlights.retain(|h| {
+        replaced
+            .get(&h.book_id)
+            .is_none_or(|id| !fetched_highlights.contains(&(*id, h.text.trim().to_string())))
+    });
+    for highlight in &mut library.highlights {
+        if let Some(id) = replaced.get(&highlight.book_id) {
+            highlight.book_id = *id;
+        }
+    }
+    library.books.retain(|b| !replaced.contains_key(&b.id));
+
+    for (&imported, &fetched) in &replaced {
+        rekey(&mut library.note_exports, imported, fetched);
+        rekey(&mut library.exported_highlights_at, imported, fetched);
+        rekey(&mut library.book_metadata, imported, fetched);
+        rekey(&mut library.excluded_books, imported, fetched);
+        rekey(&mut library.first_synced.books, imported, fetched);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csv_book_id, reconcile_readwise_csv};
+    use crate::readwise::Book;
+    use crate::testing::{book, highlight, library};
+
+    #[test]
+    fn fetched_books_replace_imported_ones() {
+        let imported_id = csv_book_id("Dune", "Frank Herbert");
+        let by_herbert = |id, title| Book {
+            author: Some("Frank Herbert".to_string()),
+            ..book(id, title)
+        };
+
+        let mut library = library(
+            vec![
+                by_herbert(imported_id, "Dune"),
+                by_herbert(7, " dune "),
+                book(-3, "Imported elsewhere"),
+            ],
+            vec![
+                highlight(-10, imported_id, "Fear is the mind-killer."),
+                highlight(-11, imported_id, "Not fetched yet"),
+                highlight(70, 7, "Fear is the mind-killer. "),
+                highlight(-30, -3, "Unrelated"),
+            ],
+        );
+
+        reconcile_readwise_csv(&mut library);
+
+        let mut book_ids = library.books.iter().map(|b| b.id).collect::<Vec<_>>();
+        book_ids.sort();
+        assert_eq!(book_ids, vec![-3, 7]);
+
+        let mut highlights = library
+            .highlights
+            .iter()
+            .map(|h| (h.id, h.book_id))
+            .collect::<Vec<_>>();
+        highlights.sort();
+        assert_eq!(highlights, vec![(-30, -3), (-11, 7), (70, 7)]);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::templates::{DEFAULT_BOOK_TEMPLATE, DEFAULT_HIGHLIGHT_TEMPLATE};
+use anyhow::{anyhow, Context as _};
+use std::path::Path;
+use tracing::{info, warn};
+
+const DOCUMENT_TEMPLATE: &str = include_str!("../templates/document.md.tera");
+const METADATA_SCRIPT: &str = include_str!("../meta.js");
+
+/// Quote a path for a POSIX shell.
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// The script running a fetch then an export into the vault with the starter files in `dir`.
+fn export_script(dir: &Path, library: &Path, vault: &Path, base_folder: &str) -> String {
+    let bin = env!("CARGO_BIN_NAME");
+    format!(
+        r#"#!/bin/sh
+# Fetches your Readwise library and exports it into your vault. Set READWISE_API_TOKEN first, and
+# see `{bin} fetch --help` and `{bin} export --help` for more options.
+set -eu
+
+DIR={dir}
+LIBRARY={library}
+VAULT={vault}
+
+{bin} --library "$LIBRARY" fetch
+
+{bin} --library "$LIBRARY" export \
+  --vault "$VAULT" \
+  --base-folder {base_folder} \
+  --book-template "$DIR/book.md.tera" \
+  --highlight-template "$DIR/highlight.md.tera" \
+  --document-template "$DIR/document.md.tera" \
+  --metadata-script "$DIR/metadata.js"
+"#,
+        bin = bin,
+        dir = shell_quote(dir),
+        library = shell_quote(library),
+        vault = shell_quote(vault),
+        base_folder = shell_quote(Path::new(base_folder)),
+    )
+}
+
+/// Write starter templates, an example metadata script, and an `export.sh` running fetch and
+/// export with them for `vault` into `dir`. Existing files are kept unless `force` is set.
+pub fn init(
+    dir: &Path,
+    library: &Path,
+    vault: &Path,
+    base_folder: &str,
+    force: bool,
+) -> anyhow::Result<()> {
+    let vault = vault
+        .canonicalize()
+        .with_context(|| format!("No vault at {:?}", vault))?;
+    if !vault.join(".obsidian").is_dir() {
+        warn!(
+            "{:?} has no .obsidian folder, is it an Obsidian vault?",
+            vault
+        );
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let dir = dir.canonicalize()?;
+    let library = std::path::absolute(library)?;
+
+    let files = [
+        ("book.md.tera", DEFAULT_BOOK_TEMPLATE.to_string()),
+        ("highlight.md.tera", DEFAULT_HIGHLIGHT_TEMPLATE.to_string()),
+        ("document.md.tera", DOCUMENT_TEMPLATE.to_string()),
+        ("metadata.js", METADATA_SCRIPT.to_string()),
+        (
+            "export.sh",
+            export_script(&dir, &library, &vault, base_folder),
+        ),
+    ];
+
+    let existing = files
+        .iter()
+        .map(|(name, _)| dir.join(name))
+        .filter(|path| path.exists())
+        .collect::<Vec<_>>();
+    if !force && !existing.is_empty() {
+        return Err(anyhow!(
+            "Not overwriting {:?}, pass --force to replace them",
+            existing
+        ));
+    }
+
+    for (name, contents) in files {
+        let path = dir.join(name);
+        info!("Writing {:?}", path);
+        std::fs::write(&path, contents)?;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            dir.join("export.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+    }
+
+    println!(
+        "Wrote starter templates and {:?}. Set READWISE_API_TOKEN and run it to export into {:?}.",
+        dir.join("export.sh"),
+        vault
+    );
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use crate::readwise::{Book, Highlight, Tag};
+use crate::Library;
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde_json::json;
+use std::fmt::Write as _;
+
+fn tag_names(tags: &[Tag]) -> String {
+    tags.iter().map(|t| t.name.as_str()).join(", ")
+}
+
+fn write_highlight(
+    out: &mut String,
+    library: &Library,
+    book: &Book,
+    highlight: &Highlight,
+) -> std::fmt::Result {
+    writeln!(
+        out,
+        "### Highlight {} ({} {})",
+        highlight.id, highlight.location_type, highlight.location
+    )?;
+    writeln!(out)?;
+    for line in highlight.text.trim().lines() {
+        writeln!(out, "> {}", line)?;
+    }
+    writeln!(out)?;
+
+    if !highlight.note.trim().is_empty() {
+        writeln!(out, "- Note: {}", highlight.note.trim())?;
+    }
+    if !highlight.tags.is_empty() {
+        writeln!(out, "- Tags: {}", tag_names(&highlight.tags))?;
+    }
+    if let Some(highlighted_at) = &highlight.highlighted_at {
+        writeln!(out, "- Highlighted: {}", highlighted_at)?;
+    }
+    writeln!(out, "- Updated: {}", highlight.updated)?;
+    if let Some(first_synced) = library.first_synced.highlights.get(&highlight.id) {
+        writeln!(out, "- First synced: {}", first_synced.to_rfc3339())?;
+    }
+    if !highlight.color.is_empty() {
+        writeln!(out, "- Color: {}", highlight.color)?;
+    }
+    if highlight.is_favorite || highlight.is_discard {
+        writeln!(
+            out,
+            "- Favorite: {}, discarded: {}",
+            highlight.is_favorite, highlight.is_discard
+        )?;
+    }
+    if highlight.book_id != book.id {
+        writeln!(out, "- From merged book: {}", highlight.book_id)?;
+    }
+    writeln!(out)
+}
+
+fn write_book(out: &mut String, library: &Library, book: &Book) -> std::fmt::Result {
+    writeln!(out, "# {} (book {})", book.title, book.id)?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "- Author: {}",
+        book.author.as_deref().unwrap_or("unknown")
+    )?;
+    writeln!(out, "- Category: {}", book.category)?;
+    if let Some(source) = &book.source {
+        writeln!(out, "- Source: {}", source)?;
+    }
+    if !book.tags.is_empty() {
+        writeln!(out, "- Tags: {}", tag_names(&book.tags))?;
+    }
+    writeln!(out, "- Highlights (as reported): {}", book.num_highlights)?;
+    if let Some(last_highlight_at) = &book.last_highlight_at {
+        writeln!(out, "- Last highlighted: {}", last_highlight_at)?;
+    }
+    if let Some(updated) = &book.updated {
+        writeln!(out, "- Updated: {}", updated)?;
+    }
+    if let Some(first_synced) = library.first_synced.books.get(&book.id) {
+        writeln!(out, "- First synced: {}", first_synced.to_rfc3339())?;
+    }
+    for (label, url) in [
+        ("Source URL", &book.source_url),
+        ("Unique URL", &book.unique_url),
+        ("Highlights URL", &book.highlights_url),
+        ("Cover", &book.cover_image_url),
+        ("ASIN", &book.asin),
+    ] {
+        if let Some(url) = url {
+            writeln!(out, "- {}: {}", label, url)?;
+        }
+    }
+    if let Some(note) = &book.document_note {
+        writeln!(out, "- Document note: {}", note.trim())?;
+    }
+    writeln!(out)
+}
+
+/// Print a book as it is stored in the library, with its tags and all of its highlights (including
+/// those of books merged into it), as markdown or JSON.
+pub fn show_book(library: &Library, book_id: i32, as_json: bool) -> anyhow::Result<String> {
+    let book = library
+        .books
+        .iter()
+        .find(|b| b.id == book_id)
+        .ok_or_else(|| anyhow!("No book with id {} in the library", book_id))?;
+
+    let highlights = library
+        .highlights_for(book)
+        .into_iter()
+        .sorted_by_key(|h| (h.book_id, h.location))
+        .collect_vec();
+
+    let merged_into = library.book_merges.get(&book.id);
+    let merged = library
+        .book_merges
+        .iter()
+        .filter(|(_, target)| **target == book.id)
+        .map(|(merged, _)| *merged)
+        .sorted()
+        .collect_vec();
+
+    if as_json {
+        return Ok(serde_json::to_string_pretty(&json!({
+            "book": book,
+            "first_synced_at": library.first_synced.books.get(&book.id),
+            "merged_into": merged_into,
+            "merged": merged,
+            "linked_document": library.document_links.get(&book.id),
+            "highlights": highlights,
+        }))?);
+    }
+
+    let mut out = String::new();
+    write_book(&mut out, library, book)?;
+
+    if let Some(target) = merged_into {
+        writeln!(
+            out,
+            "Merged into book {}, exported as part of it.\n",
+            target
+        )?;
+    }
+    if !merged.is_empty() {
+        writeln!(
+            out,
+            "Books merged into this one: {}\n",
+            merged.iter().join(", ")
+        )?;
+    }
+    if let Some(document) = library.document_links.get(&book.id) {
+        writeln!(out, "Same source as Reader document {}\n", document)?;
+    }
+
+    writeln!(out, "## {} highlights in the library", highlights.len())?;
+    writeln!(out)?;
+    for highlight in highlights {
+        write_highlight(&mut out, library, book, highlight)?;
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,113 @@
+use crate::readwise::{Book, Highlight};
+use whatlang::Lang;
+
+/// How much text language detection looks at, which is plenty to be reliable and keeps it quick
+/// for books with thousands of highlights.
+const SAMPLE_CHARS: usize = 4096;
+
+/// The ISO 639-1 code for a language, e.g. `de` for German, rather than the ISO 639-3 code whatlang
+/// uses.
+fn language_code(lang: Lang) -> &'static str {
+    match lang {
+        Lang::Eng => "en",
+        Lang::Rus => "ru",
+        Lang::Cmn => "zh",
+        Lang::Spa => "es",
+        Lang::Por => "pt",
+        Lang::Ita => "it",
+        Lang::Ben => "bn",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Ukr => "uk",
+        Lang::Kat => "ka",
+        Lang::Ara => "ar",
+        Lang::Hin => "hi",
+        Lang::Jpn => "ja",
+        Lang::Heb => "he",
+        Lang::Pol => "pl",
+        Lang::Amh => "am",
+        Lang::Jav => "jv",
+        Lang::Kor => "ko",
+        Lang::Nob => "nb",
+        Lang::Dan => "da",
+        Lang::Swe => "sv",
+        Lang::Fin => "fi",
+        Lang::Tur => "tr",
+        Lang::Nld => "nl",
+        Lang::Hun => "hu",
+        Lang::Ces => "cs",
+        Lang::Ell => "el",
+        Lang::Bul => "bg",
+        Lang::Bel => "be",
+        Lang::Ron => "ro",
+        Lang::Slv => "sl",
+        Lang::Hrv => "hr",
+        Lang::Srp => "sr",
+        Lang::Mkd => "mk",
+        Lang::Lit => "lt",
+        Lang::Lav => "lv",
+        Lang::Est => "et",
+        Lang::Vie => "vi",
+        Lang::Ind => "id",
+        Lang::Pes => "fa",
+        Lang::Afr => "af",
+        Lang::Lat => "la",
+        Lang::Slk => "sk",
+        Lang::Cat => "ca",
+        Lang::Epo => "eo",
+        Lang::Hye => "hy",
+        Lang::Tha => "th",
+        Lang::Urd => "ur",
+        Lang::Tam => "ta",
+        Lang::Tel => "te",
+        Lang::Aze => "az",
+        Lang::Uzb => "uz",
+        Lang::Yid => "yi",
+        Lang::Mar => "mr",
+        Lang::Kan => "kn",
+        Lang::Guj => "gu",
+        Lang::Pan => "pa",
+        Lang::Mal => "ml",
+        Lang::Ori => "or",
+        Lang::Mya => "my",
+        Lang::Nep => "ne",
+        Lang::Sin => "si",
+        Lang::Khm => "km",
+        Lang::Tuk => "tk",
+        Lang::Aka => "ak",
+        Lang::Zul => "zu",
+        Lang::Sna => "sn",
+        Lang::Tgl => "tl",
+    }
+}
+
+/// The language of some text, if it can be reliably detected.
+pub fn detect(text: &str) -> Option<String> {
+    let sample = match text.char_indices().nth(SAMPLE_CHARS) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+
+    whatlang::detect(sample)
+        .filter(|info| info.is_reliable())
+        .map(|info| language_code(info.lang()).to_string())
+}
+
+/// The language of a book, from the text of its highlights, or its title if it has none.
+pub fn detect_book(book: &Book, highlights: &[&Highlight]) -> Option<String> {
+    let mut text = String::new();
+    for highlight in highlights {
+        if text.chars().count() >= SAMPLE_CHARS {
+            break;
+        }
+
+        text.push_str(&highlight.text);
+        text.push('\n');
+    }
+
+    if text.trim().is_empty() {
+        text = book.title.clone();
+    }
+
+    detect(&text)
+}
@@ -1,6 +1,32 @@
-use crate::readwise::{Book, Document, Highlight};
+use crate::aliases::AliasKind;
+use crate::authors::AuthorNormalizer;
+use crate::categories::{CategoryFolder, CategoryMapping};
+use crate::changelog::ExportRun;
+use crate::conflicts::ConflictPolicy;
+use crate::deletions::DeletedBookPolicy;
+use crate::documents::{DocumentFolderStrategy, DocumentHierarchy, LinkedDocumentPolicy};
+use crate::enrich::{BookMetadata, MetadataProvider};
+use crate::errors::RecordError;
+use crate::first_synced::FirstSynced;
+use crate::matching::{NoteMatchStrategy, UnmanagedNotes};
+use crate::merging::MergeKey;
+use crate::note_exports::NoteExport;
+use crate::open_urls::OpenUrlTemplate;
+use crate::properties::PropertyTypes;
+use crate::reading_lists::ReadingList;
+use crate::readwise::{Book, Document, Highlight, ReaderCategory};
+use crate::retention::RetentionRule;
+use crate::series::SeriesPattern;
+use crate::summaries::SummaryPeriod;
+use crate::supplementals::SupplementalsPolicy;
+use crate::tags::{sorted_tags, TagOutput};
+use crate::templates::HighlightTemplateRule;
+use crate::text_cleanup::TextCleanup;
+use crate::transaction::{render_note, VaultTransaction};
+use crate::versions::HighlightVersion;
+use crate::watch::RemovalPolicy;
 use anyhow::{anyhow, Context as _};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Local, Utc};
 use clap::{Parser, ValueEnum};
 use itertools::Itertools;
 use obsidian_rust_interface::joining::strategies::TypeAndKey;
@@ -9,13 +35,76 @@ use obsidian_rust_interface::{NoteReference, Vault};
 use regex::Regex;
 use scripting::ScriptType;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use tera::{Context, Tera};
-use tracing::{debug, info, warn};
-
+use tracing::{debug, info, instrument, warn};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+mod adopt;
+mod aliases;
+mod articles;
+mod assets;
+mod authors;
+mod bench;
+mod canvas;
+mod categories;
+mod changelog;
+mod confinement;
+mod conflicts;
+mod daily;
+mod dashboard;
+mod deletions;
+mod documents;
+mod dry_run;
+mod enrich;
+mod errors;
+mod explain;
+mod first_synced;
+mod git;
+mod heatmap;
+mod hypothesis;
+mod import;
+mod init;
+mod inspect;
+mod language;
+mod matching;
+mod merging;
+mod note_exports;
+mod open_urls;
+mod parts;
+mod picker;
+mod podcasts;
+mod properties;
+mod provenance;
+mod quotes;
+mod raindrop;
+mod reading_lists;
 mod readwise;
+mod retention;
 mod scripting;
+mod search;
+mod series;
+mod stats;
+mod summaries;
+mod supplementals;
+mod sync_log;
+mod sync_state;
+mod tags;
+mod targets;
+mod telemetry;
+mod templates;
+#[cfg(test)]
+mod testing;
+mod text_cleanup;
+mod threads;
+mod transaction;
+mod tweets;
+mod versions;
+mod video;
+mod watch;
 
 #[derive(Debug, Parser, Deserialize)]
 struct Cli {
@@ -23,6 +112,26 @@ struct Cli {
     #[arg(long)]
     library: PathBuf,
 
+    /// How to handle malformed records from the Readwise API
+    #[arg(long, global = true, default_value = "lenient")]
+    parse_mode: ParseMode,
+
+    /// Where records skipped or failed on by fetch and export are reported, as JSON. Defaults to
+    /// `errors.json` beside the library cache.
+    #[arg(long, global = true)]
+    error_report: Option<PathBuf>,
+
+    /// Log the method, URL, status, timing, and rate limit headers of every API request, with
+    /// credentials redacted, e.g. to attach to a bug report
+    #[arg(long, global = true)]
+    trace_http: bool,
+
+    /// Sync the library cache to disk each time it is saved, before it replaces the previous
+    /// cache. Slower, particularly on SD cards and network mounts, but a crash mid-write can't
+    /// leave a truncated cache.
+    #[arg(long, global = true)]
+    durable_writes: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -34,6 +143,55 @@ enum Commands {
 
     /// Export highlights to markdown files
     Export(ExportCommand),
+
+    /// Seed the library cache from an export made outside of the Readwise API
+    Import(ImportCommand),
+
+    /// Look up missing book metadata (ISBN, publication year, page count, cover) from an external
+    /// catalogue
+    Enrich(EnrichCommand),
+
+    /// Print the books, highlights, and stranded notes which changed since a point in time, as
+    /// markdown
+    Changelog(ChangelogCommand),
+
+    /// Apply retention rules to the library cache without fetching
+    Prune(PruneCommand),
+
+    /// Inspect or reset when each kind of record was last synced
+    SyncState(SyncStateCommand),
+
+    /// Inspect records as they are stored in the library cache
+    Show(ShowCommand),
+
+    /// Describe what exporting a single book would do (templates, metadata, target path, joined
+    /// note, and any reason it would be skipped) without writing anything
+    Explain(ExplainCommand),
+
+    /// Time each phase of an export (loading the library, building template contexts, rendering
+    /// templates, running the metadata script, and writing files) and print a breakdown, without
+    /// changing the vault
+    Bench(BenchCommand),
+
+    /// Write an Obsidian canvas mapping a book and its highlights, or a tag and its books, as
+    /// connected cards
+    ExportCanvas(ExportCanvasCommand),
+
+    /// Search the highlights of exported books for some text, printing the matches or writing them
+    /// into a note
+    Search(SearchCommand),
+
+    /// Link notes already in the vault, e.g. written by hand or by other tools, to the books they
+    /// are about, so exports add the books' highlights to them from then on
+    Adopt(AdoptCommand),
+
+    /// Write starter book, highlight, and document templates, an example metadata script, and an
+    /// `export.sh` fetching and exporting with them into a vault
+    Init(InitCommand),
+
+    /// Keep running, watching the base folder for book notes deleted by hand, or whose frontmatter
+    /// linking them to their book was removed, and either recreate them or stop exporting the book
+    Watch(WatchCommand),
 }
 
 #[derive(Debug, Parser, Deserialize)]
@@ -49,9 +207,315 @@ struct FetchCommand {
     /// Only export the listed kind of records from readwise. Allows multiple.
     #[arg(long, short)]
     kind: Vec<ReadwiseObjectKind>,
+
+    /// Fetch the records updated since this date or timestamp rather than since the last fetch,
+    /// for this run only, e.g. to backfill a window after a bad sync. Applies to the update
+    /// strategy and leaves the stored sync state as it was.
+    #[arg(long, value_parser = readwise::parse_date_arg)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only fetch the records updated before this date or timestamp, for this run only
+    #[arg(long, value_parser = readwise::parse_date_arg)]
+    until: Option<DateTime<Utc>>,
+
+    /// Only sync Reader documents in these categories, e.g. `article,pdf,epub` to leave out RSS
+    /// items entirely. Defaults to every category.
+    #[arg(long, value_delimiter = ',')]
+    reader_category: Vec<ReaderCategory>,
+
+    /// Only sync the Reader documents with this tag, rather than every document. The tag keeps its
+    /// own sync state, so updates only fetch the tagged documents changed since it was last synced.
+    #[arg(long)]
+    reader_tag: Option<String>,
+
+    /// Fetch as usual, but only report how many books, highlights, and documents would be added,
+    /// updated, or removed, leaving the library cache as it was
+    #[arg(long)]
+    dry_run: bool,
+
+    /// How many books or highlights to request per page from the Readwise API. Smaller pages are
+    /// slower overall but each request is quicker, which helps on slow or flaky connections.
+    #[arg(long, default_value = "1000", value_parser = clap::value_parser!(i32).range(1..=1000))]
+    fetch_page_size: i32,
+
+    /// Fetch books and highlights together through Readwise's export endpoint, each book with its
+    /// highlights, rather than listing them separately. Far fewer requests for a full refetch.
+    /// Reconciling still lists them separately.
+    #[arg(long)]
+    export_endpoint: bool,
+
+    /// Also fetch the stored HTML content of Reader documents, so their articles can be embedded in
+    /// document notes with `export --embed-article`. Makes the library cache considerably larger.
+    /// Each document's content is a request of its own, made concurrently and kept in the library
+    /// cache as it arrives, so an interrupted fetch resumes where it left off.
+    #[arg(long)]
+    with_html: bool,
+
+    /// How many documents' content is fetched at once
+    #[arg(long, default_value = "4", requires = "with_html")]
+    content_concurrency: usize,
+
+    /// How many content requests are made a minute, across every concurrent fetch
+    #[arg(long, default_value = "20", requires = "with_html")]
+    content_rate: u32,
+
+    /// How many documents' content is fetched between saves of the library cache. Lower loses
+    /// less to an interrupted fetch, higher writes the cache less often on slow storage.
+    #[arg(long, default_value = "25", requires = "with_html")]
+    content_checkpoint: usize,
+
+    /// Merge books which refer to the same work (e.g. the same title highlighted on Kindle and in
+    /// Reader) so they are exported as a single note. Allows multiple, the merge mapping is
+    /// recomputed on each fetch and stored in the library cache.
+    #[arg(long)]
+    merge_by: Vec<MergeKey>,
+
+    /// Also fetch the annotations of this Hypothes.is user, each annotated page becoming an
+    /// article
+    #[arg(long, requires = "hypothesis_token")]
+    hypothesis_user: Option<String>,
+
+    /// Hypothes.is API token, required to fetch private annotations
+    #[arg(long, env = "HYPOTHESIS_API_TOKEN")]
+    hypothesis_token: Option<String>,
+
+    /// Raindrop.io API token. If set, bookmarks are also fetched from Raindrop as documents, with
+    /// any highlights on them exported like article highlights.
+    #[arg(long, env = "RAINDROP_API_TOKEN")]
+    raindrop_token: Option<String>,
+
+    /// Only sync bookmarks from these Raindrop collection ids. Allows multiple, defaults to all
+    /// collections.
+    #[arg(long, requires = "raindrop_token")]
+    raindrop_collection: Vec<i64>,
+
+    /// Drop records older than an age from the library for a category or Reader location, e.g.
+    /// `feed=30d` (units h/d/w/m/y). Allows multiple, categories without a rule are kept forever.
+    #[arg(long)]
+    retain: Vec<RetentionRule>,
 }
 
-#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Parser, Deserialize)]
+struct ImportCommand {
+    #[command(subcommand)]
+    source: ImportSource,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+enum ImportSource {
+    /// Import the full-account CSV export downloaded from the Readwise website. Later fetches
+    /// with the update strategy will only ask the API for changes made after the import. Imported
+    /// books are replaced by the same book, by title and author, once it is fetched from the API.
+    ReadwiseCsv {
+        /// The CSV file to import
+        file: PathBuf,
+    },
+
+    /// Import a Pocket export (HTML, CSV, or API JSON) as archived Reader documents
+    Pocket {
+        /// The export file to import
+        file: PathBuf,
+    },
+
+    /// Import an Instapaper CSV export as archived Reader documents
+    Instapaper {
+        /// The CSV file to import
+        file: PathBuf,
+    },
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct EnrichCommand {
+    /// The catalogue to look books up in
+    #[arg(long, default_value = "open-library")]
+    provider: MetadataProvider,
+
+    /// Only look up books from this category
+    #[arg(long, default_value = "books")]
+    category: String,
+
+    /// Look up books again even if they have already been enriched
+    #[arg(long)]
+    refresh: bool,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct ChangelogCommand {
+    /// Either `last-run` for changes since the most recent export, or a date (YYYY-MM-DD) or
+    /// RFC 3339 timestamp
+    #[arg(long, default_value = "last-run")]
+    since: String,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct PruneCommand {
+    /// Drop records older than an age for a category or Reader location, e.g. `feed=30d`. Allows
+    /// multiple.
+    #[arg(long, required = true)]
+    retain: Vec<RetentionRule>,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct ShowCommand {
+    #[command(subcommand)]
+    record: ShowRecord,
+
+    /// Print the records as JSON
+    #[arg(long, global = true)]
+    json: bool,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+enum ShowRecord {
+    /// Print a book with its tags and all of its highlights
+    Book {
+        /// The id of the book
+        #[arg(allow_negative_numbers = true)]
+        id: i32,
+    },
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct SyncStateCommand {
+    #[command(subcommand)]
+    action: SyncStateAction,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+enum SyncStateAction {
+    /// Print when each kind of record, and the Reader documents with each tag, were last synced
+    Show,
+
+    /// Forget when records were last synced so the next fetch gets them in full. Resets every
+    /// kind and tag unless some are given.
+    Reset {
+        /// The kinds of record to reset. Allows multiple.
+        #[arg(long)]
+        kind: Vec<ReadwiseObjectKind>,
+
+        /// The Reader tags synced with `fetch --reader-tag` to reset. Allows multiple.
+        #[arg(long)]
+        reader_tag: Vec<String>,
+    },
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct ExplainCommand {
+    /// The Readwise id of the book to explain
+    #[arg(allow_negative_numbers = true)]
+    book_id: i32,
+
+    #[command(flatten)]
+    export: ExportCommand,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct AdoptCommand {
+    /// The root of the obsidian vault
+    #[arg(long)]
+    vault: PathBuf,
+
+    /// Only adopt notes in this folder, relative to the vault root, rather than anywhere in the
+    /// vault
+    #[arg(long)]
+    folder: Option<PathBuf>,
+
+    /// How notes are matched to books. Notes are matched by their `title` and `author`
+    /// frontmatter, or their filename if they have no title.
+    #[arg(long, default_value = "title")]
+    match_by: AdoptMatchStrategy,
+
+    /// The confidence (0 to 1) a note must match a book with to be adopted
+    #[arg(long, default_value = "0.85")]
+    match_threshold: f64,
+
+    /// Adopt every match without asking
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize)]
+enum AdoptMatchStrategy {
+    /// By similarity of the note's title and author to the book's
+    Title,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct InitCommand {
+    /// The root of the obsidian vault to export into
+    #[arg(long)]
+    vault: PathBuf,
+
+    /// The directory the templates, metadata script, and export script are written to
+    #[arg(long, default_value = ".")]
+    dir: PathBuf,
+
+    /// The location within the vault the exported notes are written to
+    #[arg(long, default_value = "Readwise")]
+    base_folder: String,
+
+    /// Replace files already in the directory
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct WatchCommand {
+    #[command(flatten)]
+    export: ExportCommand,
+
+    /// What happens to a book whose note is removed by hand
+    #[arg(long, default_value = "recreate")]
+    on_removal: RemovalPolicy,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct BenchCommand {
+    #[command(flatten)]
+    export: ExportCommand,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+#[command(group = clap::ArgGroup::new("subject").required(true))]
+struct ExportCanvasCommand {
+    /// The Readwise id of the book to map with its highlights
+    #[arg(long, group = "subject", allow_negative_numbers = true)]
+    book: Option<i32>,
+
+    /// The tag to map with the books tagged with it, or with highlights tagged with it
+    #[arg(long, group = "subject")]
+    tag: Option<String>,
+
+    /// Where to write the canvas, relative to the vault root. Defaults to
+    /// `Canvases/<title>.canvas` within the base folder.
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    export: ExportCommand,
+}
+
+#[derive(Debug, Parser, Deserialize)]
+struct SearchCommand {
+    /// The text to search for in the text and notes of highlights, ignoring case
+    query: String,
+
+    /// Write the matching highlights into this note, relative to the vault root, linking back to
+    /// the notes they are from, rather than printing them
+    #[arg(long)]
+    to_note: Option<PathBuf>,
+
+    /// The template for the search note, rendered with `query`, `highlights` (each with
+    /// `book_title`, `book_author`, and `link` to its book's note), `highlight_count`,
+    /// `book_count`, and `searched_at`. A built-in template is used if unset.
+    #[arg(long, requires = "to_note")]
+    search_template: Option<PathBuf>,
+
+    #[command(flatten)]
+    export: ExportCommand,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
 enum ReadwiseObjectKind {
     Book,
     Highlight,
@@ -64,6 +528,45 @@ struct ExportCommand {
     #[arg(long)]
     vault: PathBuf,
 
+    /// Push notes to a remote vault instead of writing them into `--vault`, as
+    /// `webdav+https://user@host/path/to/vault` or `sftp://user@host:port/path/to/vault`. Existing
+    /// notes aren't looked up on the target, so it needs `--replacement-strategy replace` or
+    /// `ignore-existing`. The local `--vault` holds the backups for each run.
+    #[arg(long)]
+    target: Option<String>,
+
+    /// The password for the remote target, or the passphrase of `--target-key`
+    #[arg(long, env = "EXPORT_TARGET_PASSWORD", hide_env_values = true)]
+    target_password: Option<String>,
+
+    /// The private key used to sign in to an SFTP target, rather than the SSH agent
+    #[arg(long)]
+    target_key: Option<PathBuf>,
+
+    /// Connect to an SFTP target whose host key isn't in `~/.ssh/known_hosts`. A host key which
+    /// differs from the known one is always refused
+    #[arg(long)]
+    target_trust_unknown_host: bool,
+
+    /// Send the user and password for a `webdav+http://` target even though plain http exposes
+    /// them to anyone on the network path. Without it such a target is refused
+    #[arg(long)]
+    target_allow_http_credentials: bool,
+
+    /// After a successful export, commit the changed notes to the git repository containing the
+    /// vault
+    #[arg(long, conflicts_with = "target")]
+    git_commit: bool,
+
+    /// The template for the commit message, rendered with the `created`, `updated`, and `trashed`
+    /// note paths
+    #[arg(long, requires = "git_commit", default_value = git::DEFAULT_MESSAGE_TEMPLATE, hide_default_value = true)]
+    git_message: String,
+
+    /// Push after committing the export
+    #[arg(long, requires = "git_commit")]
+    git_push: bool,
+
     /// The location within the obsidian vault where the Readwise files are stored, relative to the
     /// vault root.
     #[arg(long)]
@@ -73,16 +576,52 @@ struct ExportCommand {
     #[arg(long)]
     metadata_script: Option<PathBuf>,
 
+    /// Instead of a metadata script, a program to generate custom metadata, in any language. It is
+    /// run once per book, given `{"book": ..., "highlights": [...]}` as JSON on stdin, and prints
+    /// the frontmatter as JSON on stdout, or `{"frontmatter": ..., "append": "..."}` to also add
+    /// markdown after the note's highlights.
+    #[arg(long, conflicts_with = "metadata_script")]
+    metadata_command: Option<PathBuf>,
+
+    /// Write frontmatter with the YAML types Obsidian's Properties expect (dates as dates, tags as a
+    /// list, numbers as numbers, checkboxes as booleans), and register the types in
+    /// `.obsidian/types.json`
+    #[arg(long)]
+    typed_properties: bool,
+
+    /// A YAML file mapping property names to Obsidian property types (`text`, `multitext`,
+    /// `number`, `checkbox`, `date`, `datetime`, `tags`, or `aliases`), overriding or extending the
+    /// types of the built-in properties
+    #[arg(long, requires = "typed_properties")]
+    property_types: Option<PathBuf>,
+
     /// The template used for the initial contents of a book note. The highlights will be rendered
-    /// directly after this initial content.
+    /// directly after this initial content. A built-in template is used if unset.
     #[arg(long)]
-    book_template: PathBuf,
+    book_template: Option<PathBuf>,
 
     /// The template used for each highlight in a book note. These will be rendered after the end
     /// of the book note template, with an inserted %% HIGHLIGHTS_BEGIN %% tag separating the two
-    /// sections.
+    /// sections. A built-in template is used if unset.
+    #[arg(long)]
+    highlight_template: Option<PathBuf>,
+
+    /// A highlight template for the highlights of a color or with a tag, as
+    /// `color:<color>=<template>` or `tag:<tag>=<template>`, e.g. `color:red=task.md.tera`. Allows
+    /// multiple, the first matching a highlight is used, ahead of the book's highlight template.
+    #[arg(long)]
+    highlight_template_for: Vec<HighlightTemplateRule>,
+
+    /// A directory of alternative templates. A book tagged `template:<name>` in Readwise is
+    /// rendered with `<name>/book.md.tera` and `<name>/highlight.md.tera` from this directory, and
+    /// other books with those named after their category, e.g. `articles/highlight.md.tera`.
     #[arg(long)]
-    highlight_template: PathBuf,
+    template_dir: Option<PathBuf>,
+
+    /// What happens to a book's note which was changed since it was last exported, found by the
+    /// hash recorded in the library cache
+    #[arg(long, default_value = "overwrite")]
+    conflict_policy: ConflictPolicy,
 
     /// The strategy to use when replacing existing notes
     #[arg(long, default_value = "update")]
@@ -92,13 +631,339 @@ struct ExportCommand {
     #[arg(long)]
     mark_stranded: bool,
 
-    /// If true, will skip exporting books with no highlights
-    #[arg(long, default_value = "true")]
-    skip_empty: bool,
+    /// Move notes which no longer correspond to a Readwise book to the trash configured in
+    /// Obsidian, rather than marking them
+    #[arg(long, conflicts_with = "mark_stranded")]
+    delete_stranded: bool,
+
+    /// What happens to the notes of books a refetch found were deleted in Readwise
+    #[arg(long, default_value = "strand")]
+    deleted_books: DeletedBookPolicy,
+
+    /// Append a row for each run to this note within the base folder (e.g. `Readwise Sync Log`),
+    /// with the notes it created, updated, and removed, and the stranded notes and failed books
+    /// it left, so the vault records how syncs went
+    #[arg(long)]
+    sync_log: Option<String>,
+
+    /// How existing notes are matched to books, tried in the order given. The path and title
+    /// strategies adopt notes without the exporter's frontmatter (for example those written by
+    /// other exporters), which are then rewritten from the templates.
+    #[arg(long, value_delimiter = ',', default_value = "fk")]
+    match_by: Vec<NoteMatchStrategy>,
+
+    /// The similarity (0 to 1) a note's filename must have to a book's title to be adopted by the
+    /// title strategy
+    #[arg(long, default_value = "0.9")]
+    match_threshold: f64,
+
+    /// Skip exporting books with no highlights in these categories (e.g. `books,tweets`). Use
+    /// `all` for every category or `none` to export empty books in every category.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 0..,
+        default_value = "all",
+        default_missing_value = "all"
+    )]
+    skip_empty: Vec<String>,
+
+    /// Export highlights discarded in Readwise's review, which are skipped by default
+    #[arg(long)]
+    include_discarded: bool,
+
+    /// Leave highlights with any of these tags out of every note, e.g. `private,skip-export`. They
+    /// are still fetched and kept in the library cache.
+    #[arg(long, value_delimiter = ',')]
+    exclusion_tag: Vec<String>,
+
+    /// Prefix the text of highlights favorited in Readwise with a star
+    #[arg(long)]
+    star_favorites: bool,
+
+    /// Categories (after `--category-map`) whose consecutive highlights saved together are stitched
+    /// into threads, rendered as a single highlight, e.g. `tweets`
+    #[arg(long, value_delimiter = ',')]
+    thread_categories: Vec<String>,
+
+    /// Where Readwise tags are written: `frontmatter` for Obsidian tags in book notes' `tags`
+    /// property, and `links` for `[[Tag Name]]` links in templates (`tag_links` and
+    /// `highlight.tag_links`)
+    #[arg(long, value_delimiter = ',')]
+    tag_output: Vec<TagOutput>,
+
+    /// A prefix for the tags and tag links written, e.g. `readwise/`
+    #[arg(long, default_value = "")]
+    tag_prefix: String,
+
+    /// Truncate note filenames (without their extension) to at most this many bytes, for
+    /// filesystems with short name limits. Truncated names end with a short hash of the whole title
+    /// to keep them unique.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(16..))]
+    max_filename_bytes: Option<u16>,
+
+    /// Split the highlights of books with more than this many across continuation notes named
+    /// like `Title (Part 2).md` beside the book's note, which links to them. Highlights fill the
+    /// parts in the order they were made, so new highlights only ever join the last part.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_highlights_per_note: Option<u32>,
+
+    /// Only rewrite the notes of books with highlights added or updated since their note was last
+    /// exported, leaving the rest untouched
+    #[arg(long)]
+    only_new_highlights: bool,
+
+    /// Record in each note's frontmatter the exporter version, a hash of the templates and metadata
+    /// script it was rendered with, and when it was exported
+    #[arg(long)]
+    provenance: bool,
+
+    /// Only rewrite the notes of books rendered with templates which have since changed, as
+    /// recorded by `--provenance`, leaving the rest untouched
+    #[arg(long, requires = "provenance")]
+    stale_templates: bool,
+
+    /// Find the existing notes of books by scanning the vault's frontmatter, rather than from where
+    /// they were last exported to. Needed if notes were added to the vault by another machine.
+    #[arg(long)]
+    rescan_vault: bool,
 
     /// If set, will only export books from this category
     #[arg(long)]
     filter_category: Option<String>,
+
+    /// What happens to the supplemental books of popular highlights Readwise adds to the library.
+    /// Included supplementals are tagged `supplemental` in their frontmatter.
+    #[arg(long, default_value = "include")]
+    supplementals: SupplementalsPolicy,
+
+    /// The folder within the base folder supplemental books are written to with
+    /// `--supplementals separate-folder`
+    #[arg(long, default_value = "Supplementals")]
+    supplementals_folder: PathBuf,
+
+    /// Detect the language of each book from its highlights, and of each Reader document from its
+    /// content, writing it as `lang` in the frontmatter and giving it to templates as `language`
+    /// (e.g. `de`)
+    #[arg(long)]
+    detect_language: bool,
+
+    /// Only export books in these languages, e.g. `en,de`, or `und` for books whose language
+    /// couldn't be detected. Implies `--detect-language`.
+    #[arg(long, value_delimiter = ',')]
+    language: Vec<String>,
+
+    /// Write book notes to a folder per language within their category's folder, e.g.
+    /// `Books/de`. Implies `--detect-language`.
+    #[arg(long)]
+    language_folders: bool,
+
+    /// Choose the books to export from a fuzzy-searchable list, refreshing only their notes.
+    /// Documents, the generated files, and stranded notes are left alone.
+    #[arg(long)]
+    pick: bool,
+
+    /// Render every note and compare it with the vault without writing anything, listing the
+    /// notes which would be created (`+`), changed (`~`), or removed (`-`) and failing if there
+    /// are any, e.g. to detect drift in a backup pipeline. The library cache is left as it was,
+    /// and only images downloaded by earlier exports are embedded. Only a local vault can be
+    /// checked, and an interrupted export's backups are left for the next export to restore.
+    #[arg(long, conflicts_with_all = ["pick", "git_commit", "sync_log", "target"])]
+    check: bool,
+
+    /// An inline template for a book's display title, used for the `title` and `aliases`
+    /// frontmatter and available to templates as `display_title`, independent of the filename.
+    /// For example `{{ title }}{% if book_metadata.subtitle %}: {{ book_metadata.subtitle }}{% endif %}`
+    #[arg(long)]
+    title_template: Option<String>,
+
+    /// The names notes are given as Obsidian aliases, so links find them under the names people
+    /// type: title (unsanitized), readable-title, author-title (`Author - Title`), or none.
+    /// Aliases which are the same as the filename are left out.
+    #[arg(long, value_delimiter = ',', default_value = "title,readable-title")]
+    aliases: Vec<AliasKind>,
+
+    /// The template for a book's document note (its overall note in Readwise or Reader), rendered
+    /// with the book's context just after the highlights begin token. Unlike the book template it
+    /// is rendered on every export, so edits to the document note are kept up to date (the
+    /// built-in book template renders it too, so leave it out of yours). Also loaded as
+    /// `document_note.md.tera` from a template directory.
+    #[arg(long)]
+    document_note_template: Option<PathBuf>,
+
+    /// Only export books with a document note, for sources you've written about as a whole
+    #[arg(long)]
+    require_document_note: bool,
+
+    /// A YAML file of regex rules and aliases mapping the author strings from Readwise to
+    /// canonical names, applied before rendering
+    #[arg(long)]
+    author_map: Option<PathBuf>,
+
+    /// Rename Readwise categories to your own labels, e.g. `supplementals=Books,tweets=Social`.
+    /// The label replaces the category for the folder, the frontmatter, and in templates.
+    #[arg(long, value_delimiter = ',')]
+    category_map: Vec<CategoryMapping>,
+
+    /// A template for the link to open highlights from a source in its app, given to templates as
+    /// `highlight.open_url`, e.g. `airr=airr://open?url={{ highlight.url }}`. It is rendered with
+    /// `book` and `highlight`. Allows multiple, and replaces the default for its source. By default
+    /// Kindle books link to the Kindle app, web highlights to their passage on the page, Apple
+    /// Books, Airr, Snipd, and Twitter highlights to their own url or else the book's, and
+    /// highlights from other sources to their own url, or else to Readwise.
+    #[arg(long)]
+    open_url: Vec<OpenUrlTemplate>,
+
+    /// Write the notes for a category to a folder relative to the vault root rather than the
+    /// category's folder in the base folder, e.g. `articles=Sources/Articles,books=Library`.
+    /// Categories are matched after `--category-map`.
+    #[arg(long, value_delimiter = ',')]
+    category_folder: Vec<CategoryFolder>,
+
+    /// Clean up the text and notes of highlights as they are rendered, with steps applied in order
+    /// and joined by `+`. A pipeline is either for every category or for one, which takes its
+    /// place, e.g. `whitespace,books=dehyphenate+join-lines+whitespace`. Steps are
+    /// straight-quotes, curly-quotes, dehyphenate (soft hyphens and words broken across lines),
+    /// join-lines (line breaks within paragraphs), whitespace, escape-markdown (unmatched `==` and
+    /// `%%`), and escape-math (`$` and backslashes MathJax would read). Pipelines for a book tag,
+    /// e.g. `tag:math=escape-math`, are applied after the category's. Categories are matched after
+    /// `--category-map`.
+    #[arg(long, value_delimiter = ',')]
+    text_cleanup: Vec<TextCleanup>,
+
+    /// The template used for Reader document notes. Documents are only exported if this is set.
+    #[arg(long)]
+    document_template: Option<PathBuf>,
+
+    /// How Reader document notes are arranged within the Documents folder
+    #[arg(long, default_value = "location")]
+    document_folder_strategy: DocumentFolderStrategy,
+
+    /// How Reader documents with a parent document, such as the issues of a feed, are exported.
+    /// Highlights and notes made in Reader are always part of their document's note.
+    #[arg(long, default_value = "flat")]
+    document_hierarchy: DocumentHierarchy,
+
+    /// What happens to Reader documents which are the same source as a book in the classic
+    /// library, found by their URLs on fetch
+    #[arg(long, default_value = "keep")]
+    linked_documents: LinkedDocumentPolicy,
+
+    /// Embed a cleaned-up copy of each Reader document's article in its note, under a collapsed
+    /// callout after the highlights. Needs the HTML content, fetched with `fetch --with-html`.
+    #[arg(long, requires = "document_template")]
+    embed_article: bool,
+
+    /// Download the thumbnails of Reader documents and the images in their content and highlights,
+    /// storing them in the vault's attachments folder and pointing the notes at them. Downloads are
+    /// cached in `assets` beside the library cache.
+    #[arg(long, requires = "document_template")]
+    download_images: bool,
+
+    /// The folder within the vault downloaded images are stored in, `./` for beside each note.
+    /// Defaults to the vault's own attachments folder setting.
+    #[arg(long, requires = "download_images")]
+    attachments_folder: Option<String>,
+
+    /// Follow each highlight edited in Readwise since it was first fetched with a collapsed callout
+    /// quoting its previous versions. Highlight templates are also given them as
+    /// `highlight.previous_versions`, each with `text`, `note`, `updated`, and `replaced_at`.
+    #[arg(long)]
+    previous_versions: bool,
+
+    /// Also write every exported highlight as a block in this file within the base folder (e.g.
+    /// `Quotes.md`), for random-quote plugins. Regenerated on each export.
+    #[arg(long)]
+    quotes_file: Option<String>,
+
+    /// Alongside the quotes file, write a `Quotes/<tag>.md` file for each highlight tag
+    #[arg(long, requires = "quotes_file")]
+    quotes_by_tag: bool,
+
+    /// Also write a dashboard of the Reader documents in progress to this file within the base
+    /// folder (e.g. `Currently Reading.md`). Regenerated on each export.
+    #[arg(long)]
+    dashboard_file: Option<String>,
+
+    /// Also write notes listing the Reader documents in a location within the base folder, with
+    /// their progress, word counts, and when they were saved, e.g.
+    /// `later=Reading Queue.md,archive=Archive.md`. Regenerated on each export.
+    #[arg(long, value_delimiter = ',')]
+    reading_list: Vec<ReadingList>,
+
+    /// The template for reading list notes, rendered with `title`, `location`, and `documents`,
+    /// each with `title`, `author`, `url`, `reader_url`, `category`, `progress`, `word_count`,
+    /// `saved`, and `last_opened`. A built-in template is used if unset.
+    #[arg(long, requires = "reading_list")]
+    reading_list_template: Option<PathBuf>,
+
+    /// Also write reading summary notes for each of these periods into this folder within the base
+    /// folder (e.g. `Reviews`). Regenerated on each export.
+    #[arg(long)]
+    summary_folder: Option<String>,
+
+    /// The periods summary notes are written for
+    #[arg(long, value_delimiter = ',', default_value = "month,year")]
+    summary_period: Vec<SummaryPeriod>,
+
+    /// The template for summary notes, rendered with `period`, `kind`, `highlight_count`, `books`,
+    /// `top_tags`, `finished`, and `longest_reads`. A built-in template is used if unset.
+    #[arg(long, requires = "summary_folder")]
+    summary_template: Option<PathBuf>,
+
+    /// Find the series of books from their titles with these regexes, taking the name from a
+    /// `series` group (or the whole match) and the volume or issue from an optional `volume` group.
+    /// Books tagged `series:<name>` in Readwise belong to that series whatever their title.
+    #[arg(long)]
+    series_pattern: Vec<SeriesPattern>,
+
+    /// Also write a note for each series into this folder within the base folder (e.g. `Series`),
+    /// linking to its books with their highlights rolled up. Regenerated on each export.
+    #[arg(long)]
+    series_folder: Option<String>,
+
+    /// The template for series notes, rendered with `series`, `books` (each with `title`,
+    /// `author`, `volume`, `highlights`, `last_highlight_at`, and `link`), `book_count`,
+    /// `highlight_count`, `last_highlight_at`, and `authors`. A built-in template is used if unset.
+    #[arg(long, requires = "series_folder")]
+    series_template: Option<PathBuf>,
+
+    /// Also write a note for each Readwise tag into this folder within the base folder (e.g.
+    /// `Tags`), listing the books and highlights tagged with it, and a `Tag Index` note of every
+    /// tag with its counts. Regenerated on each export.
+    #[arg(long)]
+    tag_folder: Option<String>,
+
+    /// The template for tag notes, rendered with `tag`, `books` (each with `title` and `link`),
+    /// `highlights` (each with `book_title` and `link` to its book's note), `book_count`, and
+    /// `highlight_count`. A built-in template is used if unset.
+    #[arg(long, requires = "tag_folder")]
+    tag_template: Option<PathBuf>,
+
+    /// The template for the tag index note, rendered with `tags` (each with `tag`, `link`,
+    /// `book_count`, and `highlight_count`). A built-in template is used if unset.
+    #[arg(long, requires = "tag_folder")]
+    tag_index_template: Option<PathBuf>,
+
+    /// Also write the number of highlights made each day to this file within the base folder, for
+    /// heatmap calendar plugins. A `.json` file holds just the counts by date, otherwise a note is
+    /// written with them in its frontmatter. Regenerated on each export.
+    #[arg(long)]
+    heatmap_file: Option<String>,
+
+    /// How many months back the highlight heatmap covers
+    #[arg(long, default_value = "12", requires = "heatmap_file")]
+    heatmap_months: u32,
+
+    /// Append the highlights made since the last export to the daily note of the day they were
+    /// made, found with this strftime format relative to the vault root, e.g. `Daily/%Y-%m-%d.md`
+    #[arg(long, value_parser = daily::parse_path_format)]
+    daily_notes: Option<String>,
+
+    /// The template for the section appended to daily notes, rendered with `highlights`, each
+    /// with `id`, `text`, `note`, `book`, and `link` to the book note
+    #[arg(long, requires = "daily_notes")]
+    daily_note_template: Option<PathBuf>,
 }
 
 #[derive(ValueEnum, Debug, Clone, Deserialize)]
@@ -115,6 +980,16 @@ enum ReplacementStrategy {
     IgnoreExisting,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+enum ParseMode {
+    /// Replace the fields of a record which cannot be parsed with defaults, logging them, and skip
+    /// only records which still cannot be parsed, continuing with the rest
+    Lenient,
+
+    /// Fail with an error naming the first record which cannot be parsed
+    Strict,
+}
+
 #[derive(ValueEnum, Debug, Clone, Deserialize)]
 enum FetchStrategy {
     /// Ask for updates from the Readwise API since the last update to the library cache
@@ -122,6 +997,11 @@ enum FetchStrategy {
 
     /// Refetch the whole library from the Readwise API
     Refetch,
+
+    /// Download the whole library from the Readwise API, as a refetch does, but only replace
+    /// records which are missing from or out of date in the cache, catching drift missed by
+    /// incremental updates
+    Reconcile,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -135,20 +1015,224 @@ pub struct Library {
     #[serde(default)]
     documents: Vec<Document>,
 
+    /// Books which have been merged into another, mapping the merged book id to the id of the
+    /// book it is exported as part of.
+    #[serde(default)]
+    book_merges: HashMap<i32, i32>,
+
+    /// Books in the classic library which are the same source as a Reader document, mapping the
+    /// book id to the document id.
+    #[serde(default)]
+    document_links: HashMap<i32, String>,
+
+    /// Metadata looked up from external catalogues by the enrich command, keyed by book id.
+    #[serde(default)]
+    book_metadata: HashMap<i32, BookMetadata>,
+
+    /// Every export run made from this library, oldest first, with the stranded paths of only the
+    /// most recent.
+    #[serde(default)]
+    export_runs: Vec<ExportRun>,
+
+    /// When the Reader documents with each tag were last synced by `fetch --reader-tag`.
+    #[serde(default)]
+    reader_tag_synced_at: HashMap<String, DateTime<Utc>>,
+
+    /// When the most recently updated highlight of each book was updated when its note was last
+    /// exported, keyed by book id.
+    #[serde(default)]
+    exported_highlights_at: HashMap<i32, Option<DateTime<Utc>>>,
+
+    /// When each kind of record was last synced, `None` if it has never been and is fetched in
+    /// full. Kinds without an entry were last synced when the library was.
+    #[serde(default)]
+    kind_synced_at: HashMap<ReadwiseObjectKind, Option<DateTime<Utc>>>,
+
+    /// Where the note for each book was last exported to, keyed by book id.
+    #[serde(default)]
+    note_exports: HashMap<i32, NoteExport>,
+
+    /// Books no longer exported since their notes were removed by hand while watching the vault,
+    /// with when they were removed. Delete an entry to export the book again.
+    #[serde(default)]
+    excluded_books: HashMap<i32, DateTime<Utc>>,
+
+    /// Images downloaded into the asset cache, mapping their url to the cached file's name.
+    #[serde(default)]
+    assets: HashMap<String, String>,
+
+    /// The earlier versions of highlights edited in Readwise, oldest first, keyed by highlight id.
+    #[serde(default)]
+    highlight_versions: HashMap<i32, Vec<HighlightVersion>>,
+
+    /// Books a refetch found were deleted in Readwise, with when, kept so their notes can be dealt
+    /// with. Cleared if they are fetched again.
+    #[serde(default)]
+    deleted_books: HashMap<i32, DateTime<Utc>>,
+
+    /// Highlights a refetch found were deleted in Readwise, with when. They are never exported.
+    #[serde(default)]
+    deleted_highlights: HashMap<i32, DateTime<Utc>>,
+
+    /// When each book, highlight, and document first entered the library cache.
+    #[serde(default)]
+    first_synced: FirstSynced,
+
     updated_at: DateTime<Utc>,
 }
 
+/// Carry the stored HTML fetched for documents over to their refetched versions, unless they have
+/// been updated since.
+fn keep_html_content(previous: &[Document], documents: &mut [Document]) {
+    let previous = previous
+        .iter()
+        .filter(|d| d.html_content.is_some())
+        .map(|d| (d.id.as_str(), d))
+        .collect::<HashMap<_, _>>();
+
+    for document in documents.iter_mut().filter(|d| d.html_content.is_none()) {
+        if let Some(old) = previous.get(document.id.as_str()) {
+            if old.updated_at == document.updated_at {
+                document.html_content = old.html_content.clone();
+            }
+        }
+    }
+}
+
 impl Library {
+    /// An empty library, as of now.
+    fn empty() -> Self {
+        Library {
+            books: vec![],
+            highlights: vec![],
+            documents: vec![],
+            book_merges: Default::default(),
+            document_links: Default::default(),
+            book_metadata: Default::default(),
+            export_runs: vec![],
+            reader_tag_synced_at: Default::default(),
+            kind_synced_at: Default::default(),
+            exported_highlights_at: Default::default(),
+            note_exports: Default::default(),
+            excluded_books: Default::default(),
+            assets: Default::default(),
+            highlight_versions: Default::default(),
+            deleted_books: Default::default(),
+            deleted_highlights: Default::default(),
+            first_synced: Default::default(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Take the bookkeeping an export keeps from `exported`, the library as the export left it,
+    /// leaving this library's records and sync state as they are.
+    fn take_export_bookkeeping(&mut self, exported: Library) {
+        self.note_exports = exported.note_exports;
+        self.exported_highlights_at = exported.exported_highlights_at;
+        self.export_runs = exported.export_runs;
+        self.assets = exported.assets;
+    }
+
+    /// The books in the library which weren't deleted in Readwise.
+    fn live_book_ids(&self) -> HashSet<i32> {
+        self.books
+            .iter()
+            .map(|b| b.id)
+            .filter(|id| !self.deleted_books.contains_key(id))
+            .collect()
+    }
+
+    /// When records of a kind were last synced, or `None` if they should be fetched in full.
+    fn synced_at(&self, kind: ReadwiseObjectKind) -> Option<DateTime<Utc>> {
+        match self.kind_synced_at.get(&kind) {
+            Some(synced_at) => *synced_at,
+            None => Some(self.updated_at),
+        }
+    }
+
+    /// Write the library to its cache file. It is written to a temporary file which then replaces
+    /// the cache, so an export loading the library while a fetch saves it reads either the old or
+    /// the new library whole, never a mix. Durable writes also sync it to disk first, so an
+    /// interrupted write never corrupts it.
+    fn save(&self, path: &Path, durable: bool) -> anyhow::Result<()> {
+        // Named for this process so concurrent saves don't write into the same file
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        if durable {
+            file.sync_all()?;
+        }
+
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Record that these kinds of records were synced at `at`.
+    fn mark_synced(&mut self, kinds: &[ReadwiseObjectKind], at: DateTime<Utc>) {
+        for kind in kinds {
+            self.kind_synced_at.insert(*kind, Some(at));
+        }
+
+        self.updated_at = at;
+    }
+
+    /// Add books to the library, replacing any existing books with the same id.
+    fn upsert_books(&mut self, books: Vec<Book>) {
+        let ids = books.iter().map(|b| b.id).collect::<HashSet<_>>();
+        self.books.retain(|b| !ids.contains(&b.id));
+        self.deleted_books.retain(|id, _| !ids.contains(id));
+        self.books.extend(books);
+    }
+
+    /// Add highlights to the library, replacing any existing highlights with the same id.
+    fn upsert_highlights(&mut self, highlights: Vec<Highlight>) {
+        for (id, version) in versions::edited(&self.highlights, &highlights) {
+            self.highlight_versions.entry(id).or_default().push(version);
+        }
+
+        let ids = highlights.iter().map(|h| h.id).collect::<HashSet<_>>();
+        self.highlights.retain(|h| !ids.contains(&h.id));
+        self.deleted_highlights.retain(|id, _| !ids.contains(id));
+        self.highlights.extend(highlights);
+    }
+
+    /// Keep the earlier versions of highlights which differ from those in `previous`.
+    fn record_highlight_versions(&mut self, previous: &[Highlight]) {
+        for (id, version) in versions::edited(previous, &self.highlights) {
+            self.highlight_versions.entry(id).or_default().push(version);
+        }
+    }
+
+    /// Add documents to the library, replacing any existing documents with the same id.
+    fn upsert_documents(&mut self, mut documents: Vec<Document>) {
+        keep_html_content(&self.documents, &mut documents);
+
+        let ids = documents
+            .iter()
+            .map(|d| d.id.clone())
+            .collect::<HashSet<_>>();
+        self.documents.retain(|d| !ids.contains(&d.id));
+        self.documents.extend(documents);
+    }
+
+    fn canonical_book_id(&self, book_id: i32) -> i32 {
+        *self.book_merges.get(&book_id).unwrap_or(&book_id)
+    }
+
     fn highlights_for(&self, book: &Book) -> Vec<&Highlight> {
         self.highlights
             .iter()
-            .filter(|h| h.book_id == book.id)
+            .filter(|h| self.canonical_book_id(h.book_id) == book.id)
             .collect_vec()
     }
 }
 
 struct Exporter {
     sanitizer: Regex,
+    vault_root: PathBuf,
     export_root: PathBuf,
     library: Library,
 
@@ -156,38 +1240,282 @@ struct Exporter {
     metadata_script: Option<ScriptType>,
 
     remaining_existing: HashMap<i32, NoteReference>,
+    remaining_existing_documents: HashMap<String, NoteReference>,
+    unmanaged: UnmanagedNotes,
+
+    /// Where the note for each exported book was written during this run
+    exported_paths: HashMap<i32, PathBuf>,
 
     replacement_strategy: ReplacementStrategy,
-    skip_empty: bool,
+    conflict_policy: ConflictPolicy,
+    deleted_books: DeletedBookPolicy,
+    skip_empty: Vec<String>,
+    include_discarded: bool,
+    exclusion_tags: Vec<String>,
+    star_favorites: bool,
+    thread_categories: Vec<String>,
+    tag_output: Vec<TagOutput>,
+    tag_prefix: String,
     filter_category: Option<String>,
+    supplementals: SupplementalsPolicy,
+    supplementals_folder: PathBuf,
+    series_patterns: Vec<SeriesPattern>,
+    language_filter: Vec<String>,
+    language_folders: bool,
+
+    /// The language detected for each book, if languages are detected
+    languages: Option<HashMap<i32, String>>,
+
+    max_filename_bytes: Option<usize>,
+    max_highlights_per_note: Option<usize>,
+    only_new_highlights: bool,
+    aliases: Vec<AliasKind>,
+    require_document_note: bool,
+    provenance: bool,
+    stale_templates: bool,
+
+    /// The sources of the loaded templates, by name
+    template_sources: HashMap<String, String>,
+    highlight_template_rules: Vec<HighlightTemplateRule>,
+
+    /// A hash of the metadata script or command, if there is one
+    script_hash: Option<String>,
+
+    /// The books picked to export, if only some are
+    picked_books: Option<HashSet<i32>>,
+
+    document_folder_strategy: DocumentFolderStrategy,
+    document_hierarchy: DocumentHierarchy,
+    linked_document_policy: LinkedDocumentPolicy,
+    embed_article: bool,
+    previous_versions: bool,
+
+    /// Where images downloaded by `--download-images` are cached, once they have been
+    asset_cache: Option<PathBuf>,
+    attachments_folder: String,
+
+    authors: AuthorNormalizer,
+
+    /// Labels replacing Readwise categories, keyed by lowercase category
+    category_labels: HashMap<String, String>,
+
+    /// Vault-relative folders for categories, keyed by lowercase category
+    category_folders: HashMap<String, PathBuf>,
+
+    text_cleanup: Vec<TextCleanup>,
+
+    /// The types frontmatter is converted to, if typed properties are enabled
+    property_types: Option<PropertyTypes>,
+
+    /// Every change to the vault made by this run, applied together once the export completes
+    transaction: VaultTransaction,
+
+    /// Books which failed to export, the rest of the export continuing without them
+    failures: Vec<RecordError>,
 }
 
 impl Exporter {
     fn new(library: Library, cli: &ExportCommand) -> anyhow::Result<Self> {
-        let metadata_script = match &cli.metadata_script {
-            None => None,
-            Some(path) => Some(ScriptType::new(path)?),
+        // Existing notes are only found in the local vault, not on a remote target, so nothing on
+        // the target can be merged into, compared against, or stranded
+        if cli.target.is_some() {
+            let refused = [
+                (
+                    matches!(cli.replacement_strategy, ReplacementStrategy::Update),
+                    "--replacement-strategy update",
+                ),
+                (
+                    cli.conflict_policy != ConflictPolicy::Overwrite,
+                    "--conflict-policy skip or merge-highlights",
+                ),
+                (
+                    cli.mark_stranded || cli.delete_stranded,
+                    "--mark-stranded or --delete-stranded",
+                ),
+            ];
+
+            if let Some((_, option)) = refused.iter().find(|(refused, _)| *refused) {
+                return Err(anyhow!(
+                    "{} can't be used with a remote --target, whose existing notes aren't read. \
+                     Use --replacement-strategy replace or ignore-existing",
+                    option
+                ));
+            }
+        }
+
+        let transaction = VaultTransaction::begin(
+            &cli.vault,
+            targets::open_target(
+                &cli.vault,
+                cli.target.as_deref(),
+                cli.target_password.as_deref(),
+                cli.target_key.as_deref(),
+                cli.target_trust_unknown_host,
+                cli.target_allow_http_credentials,
+            )?,
+        )?;
+
+        Self::with_transaction(library, cli, transaction)
+    }
+
+    /// An exporter for describing or timing an export without making it, which neither connects
+    /// to a remote target nor restores the backups of an interrupted export.
+    fn inspecting(library: Library, cli: &ExportCommand) -> anyhow::Result<Self> {
+        Self::with_transaction(library, cli, VaultTransaction::inspect(&cli.vault)?)
+    }
+
+    fn with_transaction(
+        library: Library,
+        cli: &ExportCommand,
+        transaction: VaultTransaction,
+    ) -> anyhow::Result<Self> {
+        let mut template_sources = HashMap::new();
+        let languages = (cli.detect_language || !cli.language.is_empty() || cli.language_folders)
+            .then(|| {
+                library
+                    .books
+                    .iter()
+                    .filter_map(|book| {
+                        let language = language::detect_book(book, &library.highlights_for(book))?;
+                        Some((book.id, language))
+                    })
+                    .collect()
+            });
+
+        let metadata_script = match (&cli.metadata_script, &cli.metadata_command) {
+            (Some(path), _) => Some(ScriptType::new(path)?),
+            (None, Some(program)) => Some(ScriptType::command(program)),
+            (None, None) => None,
         };
 
         let vault = Vault::open(&cli.vault);
-        let existing = obsidian_rust_interface::joining::find_by::<_, i32>(
-            &vault,
-            &TypeAndKey {
-                type_key: "note-kind".to_string(),
-                note_type: "readwise".to_string(),
-                id_key: "__readwise_fk".to_string(),
-            },
-        );
+        let recorded = match cli.rescan_vault {
+            true => None,
+            false => note_exports::recorded_notes(&library, &cli.vault),
+        };
+
+        let existing = recorded.unwrap_or_else(|| {
+            obsidian_rust_interface::joining::find_by::<_, i32>(
+                &vault,
+                &TypeAndKey {
+                    type_key: "note-kind".to_string(),
+                    note_type: "readwise".to_string(),
+                    id_key: "__readwise_fk".to_string(),
+                },
+            )
+        });
 
         debug!("Found {} existing notes", existing.len());
 
+        let export_root = cli.vault.join(&cli.base_folder);
+        let unmanaged = UnmanagedNotes::new(
+            &export_root,
+            &existing.values().map(|n| n.to_path_buf()).collect(),
+            &cli.match_by,
+            cli.match_threshold,
+        )?;
+
+        let existing_documents = if cli.document_template.is_some() {
+            obsidian_rust_interface::joining::find_by::<_, String>(
+                &vault,
+                &TypeAndKey {
+                    type_key: "note-kind".to_string(),
+                    note_type: "readwise-document".to_string(),
+                    id_key: "__readwise_document_fk".to_string(),
+                },
+            )
+        } else {
+            HashMap::new()
+        };
+
+        debug!("Found {} existing document notes", existing_documents.len());
+
         Ok(Exporter {
             library,
-            export_root: cli.vault.join(&cli.base_folder),
+            vault_root: cli.vault.clone(),
+            export_root,
             templates: {
                 let mut tera = Tera::default();
-                tera.add_template_file(&cli.book_template, Some("book"))?;
-                tera.add_template_file(&cli.highlight_template, Some("highlight"))?;
+                let sources = &mut template_sources;
+                match &cli.book_template {
+                    Some(path) => templates::add_file(&mut tera, sources, "book", path)?,
+                    None => templates::add_raw(
+                        &mut tera,
+                        sources,
+                        "book",
+                        templates::DEFAULT_BOOK_TEMPLATE,
+                    )?,
+                }
+
+                match &cli.highlight_template {
+                    Some(path) => templates::add_file(&mut tera, sources, "highlight", path)?,
+                    None => templates::add_raw(
+                        &mut tera,
+                        sources,
+                        "highlight",
+                        templates::DEFAULT_HIGHLIGHT_TEMPLATE,
+                    )?,
+                }
+
+                for (source, template) in open_urls::DEFAULT_OPEN_URLS {
+                    let name = open_urls::template_name(source);
+                    templates::add_raw(&mut tera, sources, &name, template)?;
+                }
+
+                for open_url in &cli.open_url {
+                    let name = open_urls::template_name(&open_url.source);
+                    templates::add_raw(&mut tera, sources, &name, &open_url.template)?;
+                }
+
+                for (index, rule) in cli.highlight_template_for.iter().enumerate() {
+                    let name = HighlightTemplateRule::template_name(index);
+                    templates::add_file(&mut tera, sources, &name, &rule.path)?;
+                }
+
+                if let Some(document_template) = &cli.document_template {
+                    templates::add_file(&mut tera, sources, "document", document_template)?;
+                }
+
+                if let Some(daily_note_template) = &cli.daily_note_template {
+                    templates::add_file(&mut tera, sources, "daily", daily_note_template)?;
+                }
+
+                if let Some(reading_list_template) = &cli.reading_list_template {
+                    templates::add_file(&mut tera, sources, "reading_list", reading_list_template)?;
+                }
+
+                if let Some(summary_template) = &cli.summary_template {
+                    templates::add_file(&mut tera, sources, "summary", summary_template)?;
+                }
+
+                if let Some(series_template) = &cli.series_template {
+                    templates::add_file(&mut tera, sources, "series", series_template)?;
+                }
+
+                if let Some(tag_template) = &cli.tag_template {
+                    templates::add_file(&mut tera, sources, "tag", tag_template)?;
+                }
+
+                if let Some(tag_index_template) = &cli.tag_index_template {
+                    templates::add_file(&mut tera, sources, "tag_index", tag_index_template)?;
+                }
+
+                if let Some(document_note_template) = &cli.document_note_template {
+                    templates::add_file(
+                        &mut tera,
+                        sources,
+                        "document_note",
+                        document_note_template,
+                    )?;
+                }
+
+                if let Some(title_template) = &cli.title_template {
+                    templates::add_raw(&mut tera, sources, "title", title_template)?;
+                }
+
+                if let Some(template_dir) = &cli.template_dir {
+                    templates::load_template_dir(&mut tera, sources, template_dir)?;
+                }
 
                 debug!(
                     "Loaded tera templates for markdown. Templates: {}",
@@ -197,93 +1525,362 @@ impl Exporter {
                 tera
             },
             metadata_script,
+            template_sources,
 
             replacement_strategy: cli.replacement_strategy.clone(),
+            conflict_policy: cli.conflict_policy,
+            deleted_books: cli.deleted_books,
             sanitizer: Regex::new(r#"[<>"'/\\|?*]+"#).unwrap(),
             remaining_existing: existing,
-            skip_empty: cli.skip_empty,
+            remaining_existing_documents: existing_documents,
+            unmanaged,
+            exported_paths: HashMap::new(),
+            skip_empty: cli.skip_empty.clone(),
+            include_discarded: cli.include_discarded,
+            exclusion_tags: cli.exclusion_tag.clone(),
+            star_favorites: cli.star_favorites,
+            thread_categories: cli.thread_categories.clone(),
+            tag_output: cli.tag_output.clone(),
+            tag_prefix: cli.tag_prefix.clone(),
             filter_category: cli.filter_category.clone(),
+            supplementals: cli.supplementals,
+            supplementals_folder: cli.supplementals_folder.clone(),
+            series_patterns: cli.series_pattern.clone(),
+            highlight_template_rules: cli.highlight_template_for.clone(),
+            language_filter: cli.language.iter().map(|l| l.to_lowercase()).collect(),
+            language_folders: cli.language_folders,
+            languages,
+            only_new_highlights: cli.only_new_highlights,
+            aliases: cli.aliases.clone(),
+            require_document_note: cli.require_document_note,
+            provenance: cli.provenance,
+            stale_templates: cli.stale_templates,
+            script_hash: cli
+                .metadata_script
+                .as_deref()
+                .or(cli.metadata_command.as_deref())
+                .map(provenance::script_hash),
+            max_filename_bytes: cli.max_filename_bytes.map(usize::from),
+            max_highlights_per_note: cli.max_highlights_per_note.map(|n| n as usize),
+            picked_books: None,
+            document_folder_strategy: cli.document_folder_strategy,
+            document_hierarchy: cli.document_hierarchy,
+            linked_document_policy: cli.linked_documents,
+            embed_article: cli.embed_article,
+            previous_versions: cli.previous_versions,
+            asset_cache: None,
+            attachments_folder: cli
+                .attachments_folder
+                .clone()
+                .unwrap_or_else(|| assets::vault_attachment_folder(&cli.vault)),
+            authors: match &cli.author_map {
+                Some(path) => AuthorNormalizer::load(path)?,
+                None => AuthorNormalizer::default(),
+            },
+            category_labels: cli
+                .category_map
+                .iter()
+                .map(|m| (m.category.clone(), m.label.clone()))
+                .collect(),
+            category_folders: cli
+                .category_folder
+                .iter()
+                .map(|f| (f.category.clone(), f.folder.clone()))
+                .collect(),
+            text_cleanup: cli.text_cleanup.clone(),
+            property_types: match cli.typed_properties {
+                true => Some(PropertyTypes::load(cli.property_types.as_deref())?),
+                false => None,
+            },
+            transaction,
+            failures: vec![],
         })
     }
 
+    /// Why a book would not be exported, or `None` if it would be.
+    fn skip_reason(&self, book: &Book) -> Option<String> {
+        // Merged books are exported as part of their canonical book
+        if let Some(target) = self.library.book_merges.get(&book.id) {
+            return Some(format!("it is merged into book {}", target));
+        }
+
+        if self.library.excluded_books.contains_key(&book.id) {
+            return Some("its note was removed by hand".to_string());
+        }
+
+        if self.library.deleted_books.contains_key(&book.id) {
+            return Some("it was deleted in Readwise".to_string());
+        }
+
+        let skip_empty = self
+            .skip_empty
+            .iter()
+            .any(|c| c == "all" || c.eq_ignore_ascii_case(&book.category));
+
+        // No need to collect all highlights for the book now, just see if there are any
+        if skip_empty
+            && !self.library.highlights.iter().any(|h| {
+                self.library.canonical_book_id(h.book_id) == book.id && self.exports_highlight(h)
+            })
+        {
+            return Some(format!(
+                "it has no highlights and empty books are skipped in '{}'",
+                book.category
+            ));
+        }
+
+        if let Some(filtered_category) = &self.filter_category {
+            if book.category != *filtered_category {
+                return Some(format!("only '{}' is being exported", filtered_category));
+            }
+        }
+
+        if self.supplementals == SupplementalsPolicy::Exclude && self.is_supplemental(book) {
+            return Some("it is a supplemental book".to_string());
+        }
+
+        if !self.language_filter.is_empty() {
+            let language = self.book_language(book).unwrap_or("und");
+            if !self.language_filter.iter().any(|l| l == language) {
+                return Some(format!("its language is '{}'", language));
+            }
+        }
+
+        if self.require_document_note
+            && book
+                .document_note
+                .as_deref()
+                .is_none_or(|note| note.trim().is_empty())
+        {
+            return Some("it has no document note".to_string());
+        }
+
+        if let Some(picked) = &self.picked_books {
+            if !picked.contains(&book.id) {
+                return Some("it was not picked".to_string());
+            }
+        }
+
+        None
+    }
+
+    /// The language detected for a book, if languages are detected and it could be.
+    fn book_language(&self, book: &Book) -> Option<&str> {
+        self.languages.as_ref()?.get(&book.id).map(String::as_str)
+    }
+
+    /// The folder notes for books in this category are written to by default, either the folder
+    /// configured for it or the capitalised category within the base folder.
+    fn category_root(&self, category: &str) -> anyhow::Result<PathBuf> {
+        if let Some(folder) = self.category_folders.get(&category.to_lowercase()) {
+            return Ok(self.vault_root.join(folder));
+        }
+
+        let category_title = {
+            let category = category.nfc().collect::<String>();
+            let mut g = category.graphemes(true);
+            g.next().map(|f| f.to_uppercase() + g.as_str())
+        };
+
+        let category_title = category_title.ok_or(anyhow!("Invalid category {category}"))?;
+
+        Ok(self.export_root.join(category_title))
+    }
+
+    #[instrument(skip_all)]
     fn export(&mut self) -> anyhow::Result<()> {
-        let by_category = self
+        self.keep_deleted_notes();
+
+        // Sorted so categories are grouped, and each run exports in the same order whatever order
+        // the library holds the books in
+        let books = self
             .library
             .books
             .iter()
-            .filter(|book| {
-                if self.skip_empty {
-                    // No need to collect all highlights for the book now, just see if there are any
-                    self.library.highlights.iter().any(|h| h.book_id == book.id)
-                } else {
-                    return true;
-                }
-            })
-            .filter(|book| {
-                if let Some(filtered_category) = &self.filter_category {
-                    book.category == *filtered_category
-                } else {
-                    return true;
-                }
-            })
-            .chunk_by(|book| book.category.clone());
+            .filter(|book| self.skip_reason(book).is_none())
+            .cloned()
+            .map(|book| self.as_rendered(book))
+            .sorted_by(|a, b| (&a.category, &a.title, a.id).cmp(&(&b.category, &b.title, b.id)))
+            .collect_vec();
+
+        let by_category = books.iter().chunk_by(|book| book.category.clone());
 
         for (category, books) in by_category.into_iter() {
             debug!("Starting export of category: {}", category);
 
-            let category_title = {
-                let mut c = category.chars();
-                match c.next() {
-                    None => None,
-                    Some(f) => Some(f.to_uppercase().collect::<String>() + c.as_str()),
+            let category_root = self.category_root(&category)?;
+
+            for book in books {
+                let root = match self.supplementals {
+                    SupplementalsPolicy::SeparateFolder if self.is_supplemental(book) => {
+                        self.export_root.join(&self.supplementals_folder)
+                    }
+                    _ => category_root.clone(),
+                };
+
+                let root = match self.book_language(book).filter(|_| self.language_folders) {
+                    Some(language) => root.join(language),
+                    None => root,
+                };
+
+                if let Err(err) = self.export_one(&root, book) {
+                    warn!(
+                        "Failed to export book {} ({}): {:#}",
+                        book.id, book.title, err
+                    );
+                    self.failures.push(RecordError {
+                        kind: "book".to_string(),
+                        id: book.id.to_string(),
+                        title: Some(book.title.clone()),
+                        reason: format!("{:#}", err),
+                    });
                 }
-            };
+            }
+        }
 
-            let category_title = category_title.ok_or(anyhow!("Invalid category {category}"))?;
+        // The notes of books skipped on purpose, by a filter or policy, aren't stranded, only those
+        // of books no longer in the library or deleted in Readwise, and those of merged books which
+        // the book they were merged into didn't adopt
+        let live = self.library.live_book_ids();
+        let book_merges = &self.library.book_merges;
+        let exported_paths = &self.exported_paths;
+        self.remaining_existing.retain(|book_id, _| {
+            !live.contains(book_id)
+                || book_merges
+                    .get(book_id)
+                    .is_some_and(|target| exported_paths.contains_key(target))
+        });
 
-            let category_root = self.export_root.join(category_title);
-            std::fs::create_dir_all(&category_root)?;
+        Ok(())
+    }
 
-            for book in books {
-                let existing_note = self.remaining_existing.remove(&book.id);
+    /// Export a single book, staging its note. An existing note for the book is claimed even if
+    /// this fails, so it is never treated as stranded.
+    #[instrument(skip_all, fields(book = book.id))]
+    fn export_one(&mut self, category_root: &PathBuf, book: &Book) -> anyhow::Result<()> {
+        // Without a note of its own, the book adopts the note written for the lowest id of the
+        // books merged into it. The notes of the others are left to be stranded.
+        let existing_note = self.remaining_existing.remove(&book.id).or_else(|| {
+            let merged = self
+                .library
+                .book_merges
+                .iter()
+                .filter(|(merged, target)| {
+                    **target == book.id && self.remaining_existing.contains_key(merged)
+                })
+                .map(|(merged, _)| *merged)
+                .min()?;
+
+            self.remaining_existing.remove(&merged)
+        });
+
+        let latest_highlight = self.latest_highlight(book);
+        if let Some(existing) = existing_note.as_ref().filter(|_| {
+            self.only_new_highlights
+                && self.library.exported_highlights_at.get(&book.id) == Some(&latest_highlight)
+        }) {
+            debug!("Leaving '{}' as it has no new highlights", book.title);
+            self.exported_paths.insert(book.id, existing.to_path_buf());
+            return Ok(());
+        }
 
-                let existing_file = existing_note.clone().map(|n| n.to_path_buf());
+        if let Some(existing) = existing_note.as_ref().filter(|_| self.stale_templates) {
+            if self.has_current_templates(book, existing)? {
+                debug!("Leaving '{}' as its templates haven't changed", book.title);
+                self.exported_paths.insert(book.id, existing.to_path_buf());
+                return Ok(());
+            }
+        }
 
-                match self.replacement_strategy {
-                    ReplacementStrategy::Update => {
-                        self.export_book(&category_root, book, existing_note.as_ref())?
-                            .write(existing_file.as_ref())?;
-                    }
+        if let Some(existing) = &existing_note {
+            if self.resolve_conflict(book, &existing.to_path_buf())? {
+                self.library
+                    .exported_highlights_at
+                    .insert(book.id, latest_highlight);
+                return Ok(());
+            }
+        }
 
-                    ReplacementStrategy::Replace => {
-                        self.export_book(&category_root, book, None)?
-                            .write(existing_file.as_ref())?;
-                    }
+        let existing_file = match &existing_note {
+            Some(note) => Some(note.to_path_buf()),
+            None => {
+                let default_path = category_root
+                    .join(self.sanitize_title(&book.title, &format!("book-{}", book.id)))
+                    .with_extension("md");
 
-                    ReplacementStrategy::IgnoreExisting => {
-                        if let Some(existing_file_path) = &existing_file {
-                            debug!(
-                                "Ignoring existing file '{:?}' for book '{}'",
-                                existing_file_path, &book.title
-                            );
-                        }
+                self.unmanaged.adopt(&book.title, &default_path)
+            }
+        };
 
-                        self.export_book(&category_root, book, None)?.write(None)?;
-                    }
+        let note = match self.replacement_strategy {
+            ReplacementStrategy::Update => {
+                self.export_book(category_root, book, existing_note.as_ref())?
+            }
+
+            ReplacementStrategy::Replace | ReplacementStrategy::IgnoreExisting => {
+                self.export_book(category_root, book, None)?
+            }
+        };
+        self.confine(
+            &note.default_path,
+            &format!("book {} '{}'", book.id, book.title),
+        )?;
+
+        let target = match self.replacement_strategy {
+            ReplacementStrategy::IgnoreExisting => {
+                if let Some(existing_file_path) = &existing_file {
+                    debug!(
+                        "Ignoring existing file '{:?}' for book '{}'",
+                        existing_file_path, &book.title
+                    );
                 }
+
+                None
             }
+
+            _ => existing_file,
+        };
+
+        if let (Some(existing), ReplacementStrategy::Replace) =
+            (&target, &self.replacement_strategy)
+        {
+            self.transaction.stage_trash(existing)?;
         }
 
+        let path = target.unwrap_or(note.default_path);
+        let contents = self.stage_parts(book, &path, note.contents)?;
+        self.transaction
+            .stage_note(&path, &note.metadata, &contents)?;
+        self.library.note_exports.insert(
+            book.id,
+            NoteExport {
+                path: path.strip_prefix(&self.vault_root)?.to_path_buf(),
+                hash: note_exports::content_hash(&render_note(&note.metadata, &contents)?),
+                exported_at: Utc::now(),
+            },
+        );
+        self.exported_paths.insert(book.id, path);
+        self.library
+            .exported_highlights_at
+            .insert(book.id, latest_highlight);
         Ok(())
     }
 
+    /// When the most recently updated of a book's exported highlights was updated.
+    fn latest_highlight(&self, book: &Book) -> Option<DateTime<Utc>> {
+        self.highlights_for(book)
+            .iter()
+            .filter_map(|h| readwise::parse_timestamp(&h.updated))
+            .max()
+    }
+
     fn render_templates(
         &self,
         book: &&Book,
         highlights: &Vec<&Highlight>,
+        template_context: &Context,
         existing_note: Option<&NoteReference>,
     ) -> anyhow::Result<String> {
-        let template_context = Self::create_template_context(&book, &highlights)?;
         let highlights_begin_token = "%% HIGHLIGHTS_BEGIN %%";
 
         let contents = if let Some(existing_note) = existing_note {
@@ -302,28 +1899,74 @@ impl Exporter {
 
             persisted_contents.to_string()
         } else {
-            self.templates.render("book", &template_context)?
+            self.templates
+                .render(&self.template_for(book, "book"), template_context)?
+        };
+
+        let highlight_contents = self.render_highlights(book, highlights, template_context)?;
+
+        let document_note_template = self.template_for(book, "document_note");
+        let document_note = match &book.document_note {
+            Some(note)
+                if !note.trim().is_empty()
+                    && self
+                        .templates
+                        .get_template_names()
+                        .any(|name| name == document_note_template) =>
+            {
+                let rendered = self
+                    .templates
+                    .render(&document_note_template, template_context)?;
+                format!("{}\n\n", rendered.trim())
+            }
+            _ => String::new(),
         };
 
-        let highlight_contents = highlights
-            .iter()
-            .rev()
+        Ok(format!(
+            "{}\n\n%% HIGHLIGHTS_BEGIN %%\n\n{}{}\n",
+            contents.trim(),
+            document_note,
+            highlight_contents
+        ))
+    }
+
+    /// Render each of the highlights with the book's highlight template.
+    fn render_highlights(
+        &self,
+        book: &Book,
+        highlights: &[&Highlight],
+        template_context: &Context,
+    ) -> anyhow::Result<String> {
+        let highlight_contents = self
+            .highlight_blocks(book, highlights)?
+            .into_iter()
             .map(|highlight| {
                 let mut highlight_context = template_context.clone();
                 highlight_context.insert("highlight", &highlight);
 
-                self.templates.render("highlight", &highlight_context)
-            })
-            .collect::<Result<Vec<String>, _>>()?;
+                let rendered = self.templates.render(
+                    &self.highlight_template_for(book, &highlight),
+                    &highlight_context,
+                )?;
 
-        let highlight_contents = highlight_contents.join("\n\n");
-        let highlight_contents = highlight_contents.trim();
+                let versions = serde_json::from_value::<Vec<HighlightVersion>>(
+                    highlight["previous_versions"].clone(),
+                )
+                .unwrap_or_default();
 
-        Ok(format!(
-            "{}\n\n%% HIGHLIGHTS_BEGIN %%\n\n{}\n",
-            contents.trim(),
-            highlight_contents
-        ))
+                if versions.is_empty() || !self.previous_versions {
+                    return Ok(rendered);
+                }
+
+                Ok(format!(
+                    "{}\n\n{}",
+                    rendered.trim_end(),
+                    versions::previous_version_callouts(&versions)
+                ))
+            })
+            .collect::<anyhow::Result<Vec<String>>>()?;
+
+        Ok(highlight_contents.join("\n\n").trim().to_string())
     }
 
     fn export_book(
@@ -337,21 +1980,40 @@ impl Exporter {
             book.title, &root
         );
 
-        let title = self.sanitize_title(&book.title);
-        let highlights = self.library.highlights_for(book);
+        let title = self.sanitize_title(&book.title, &format!("book-{}", book.id));
+        let highlights = self.highlights_for(book);
         debug!("Found {} highlights in library", highlights.len());
 
-        let contents = self.render_templates(&book, &highlights, existing_note)?;
+        // Highlights beyond the first part are written to continuation notes by export_one
+        let main_part = self
+            .note_parts(&highlights)
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+
+        let template_context = self.create_template_context(&book, &highlights)?;
+        let mut contents =
+            self.render_templates(&book, &main_part, &template_context, existing_note)?;
 
         let mut metadata: serde_yml::Value = match &self.metadata_script {
-            None => serde_yml::to_value(&book)?,
-            Some(script) => script.execute(book, &highlights)?,
+            None => serde_yml::to_value(book)?,
+            Some(script) => {
+                let output = script.execute(book, &highlights)?;
+                if let Some(append) = output.append.filter(|a| !a.trim().is_empty()) {
+                    contents = format!("{}\n\n{}\n", contents.trim_end(), append.trim());
+                }
+                output.metadata
+            }
         };
 
         {
-            let metadata = metadata
-                .as_mapping_mut()
-                .expect("Metadata was not a mapping, this is invalid");
+            let metadata = metadata.as_mapping_mut().ok_or_else(|| {
+                anyhow!(
+                    "Metadata for book {} ({}) was not a mapping",
+                    book.id,
+                    book.title
+                )
+            })?;
 
             metadata.insert(
                 serde_yml::Value::from("note-kind"),
@@ -362,6 +2024,72 @@ impl Exporter {
                 serde_yml::Value::from("__readwise_fk"),
                 serde_yml::Value::from(book.id),
             );
+
+            if self.has_title_template() {
+                let display_title = template_context
+                    .get("display_title")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or(&book.title);
+
+                metadata.insert(
+                    serde_yml::Value::from("title"),
+                    serde_yml::Value::from(display_title),
+                );
+
+                aliases::add_alias(metadata, display_title);
+            }
+
+            self.add_aliases(
+                metadata,
+                &title,
+                &book.title,
+                book.author.as_deref(),
+                book.readable_title.as_deref(),
+            );
+
+            if let Some(document) = self
+                .linked_document(book)
+                .filter(|_| self.linked_document_policy == LinkedDocumentPolicy::MergeProgress)
+            {
+                metadata.insert(
+                    serde_yml::Value::from("reading_progress"),
+                    serde_yml::Value::from(document.reading_progress),
+                );
+                metadata.insert(
+                    serde_yml::Value::from("reader_location"),
+                    serde_yml::to_value(&document.location)?,
+                );
+                metadata.insert(
+                    serde_yml::Value::from("last_opened_at"),
+                    serde_yml::to_value(&document.last_opened_at)?,
+                );
+                metadata.insert(
+                    serde_yml::Value::from("reader_url"),
+                    serde_yml::Value::from(format!(
+                        "https://read.readwise.io/read/{}",
+                        document.id
+                    )),
+                );
+            }
+
+            if let Some(language) = self.book_language(book) {
+                metadata.insert(
+                    serde_yml::Value::from("lang"),
+                    serde_yml::Value::from(language),
+                );
+            }
+
+            self.add_book_provenance(book, metadata);
+
+            if let Some(tags) = self.frontmatter_tags(book, &highlights) {
+                metadata.insert(serde_yml::Value::from("tags"), serde_yml::to_value(tags)?);
+            }
+
+            self.tag_supplemental(book, metadata);
+
+            if let Some(property_types) = &self.property_types {
+                property_types.apply(metadata);
+            }
         }
 
         debug!("Computed metadata for book {:?} as {:?}", &book, metadata);
@@ -375,15 +2103,27 @@ impl Exporter {
     }
 
     fn create_template_context(
+        &self,
         book: &&Book,
         highlights: &Vec<&Highlight>,
     ) -> anyhow::Result<Context> {
         let context = {
-            let mut context = Context::from_value(serde_json::to_value(book)?)?;
+            let mut book_value = serde_json::to_value(book)?;
+            book_value["readwise_url"] = serde_json::to_value(readwise::book_review_url(book.id))?;
+            tweets::add_book_fields(book, &mut book_value);
+            if let Some(series) = self.book_series(book) {
+                book_value["series"] = serde_json::Value::from(series.name);
+                book_value["series_volume"] = serde_json::to_value(series.volume)?;
+            }
+            book_value["stats"] = serde_json::to_value(stats::book_stats(highlights))?;
+            book_value["first_synced_at"] =
+                serde_json::to_value(self.library.first_synced.books.get(&book.id))?;
+
+            let mut context = Context::from_value(book_value.clone())?;
             let augmented_highlights = highlights.iter()
                 .sorted_by_key(|h| h.location)
                 .map(|highlight| {
-                    let mut v = serde_json::to_value(highlight).unwrap();
+                    let mut v = self.highlight_value(book, highlight).unwrap();
                     if let Some(asin) = &book.asin {
                         v.as_object_mut()
                             .unwrap()
@@ -401,35 +2141,213 @@ impl Exporter {
                 })
                 .collect_vec();
 
-            context.insert("book", &book);
+            context.insert("book", &book_value);
             context.insert("highlights", &augmented_highlights);
+            context.insert("book_metadata", &self.library.book_metadata.get(&book.id));
+            context.insert("tag_links", &self.book_tag_links(book));
+            context.insert(
+                "document",
+                &self
+                    .linked_document(book)
+                    .map(|document| self.document_value(document))
+                    .transpose()?,
+            );
+            context.insert("language", &self.book_language(book));
+
+            let display_title = if self.has_title_template() {
+                self.templates.render("title", &context)?.trim().to_string()
+            } else {
+                book.title.clone()
+            };
+
+            context.insert("display_title", &display_title);
             context
         };
         Ok(context)
     }
 
-    fn sanitize_title(&self, title: &str) -> String {
-        self.sanitizer.replace_all(title, "")
+    /// The highlights of a book which are exported, leaving out those deleted in Readwise, those
+    /// with an exclusion tag, and those discarded in Readwise unless they are included. Ordered by
+    /// location, as the library moves highlights to the end as they are updated.
+    fn highlights_for(&self, book: &Book) -> Vec<&Highlight> {
+        let mut highlights = self.library.highlights_for(book);
+        highlights.retain(|h| self.exports_highlight(h));
+        highlights.sort_by_key(|h| (h.location, h.id));
+        highlights
+    }
+
+    /// Whether a highlight is exported with its book, as it isn't deleted in Readwise, doesn't have
+    /// an exclusion tag, and isn't discarded in Readwise unless those are included.
+    fn exports_highlight(&self, highlight: &Highlight) -> bool {
+        (self.include_discarded || !highlight.is_discard)
+            && !self.library.deleted_highlights.contains_key(&highlight.id)
+            && !self.is_excluded(highlight)
+    }
+
+    /// Whether a highlight has one of the exclusion tags, and is never rendered.
+    fn is_excluded(&self, highlight: &Highlight) -> bool {
+        highlight.tags.iter().any(|tag| {
+            self.exclusion_tags
+                .iter()
+                .any(|excluded| excluded.eq_ignore_ascii_case(&tag.name))
+        })
+    }
+
+    /// A highlight as it is given to templates, with podcast and video timestamps, the tweet's url,
+    /// links to its tags and to Readwise, and starred if it is a favorite and favorites are starred.
+    fn highlight_value(
+        &self,
+        book: &Book,
+        highlight: &Highlight,
+    ) -> serde_json::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(highlight)?;
+        value["tags"] = serde_json::to_value(sorted_tags(&highlight.tags))?;
+        if !self.text_cleanup.is_empty() {
+            value["text"] = serde_json::Value::from(self.clean_text(book, &highlight.text));
+            value["note"] = serde_json::Value::from(self.clean_text(book, &highlight.note));
+        }
+
+        podcasts::add_podcast_fields(book, highlight, &mut value);
+        video::add_video_fields(book, highlight, &mut value);
+        tweets::add_tweet_fields(book, highlight, &mut value);
+        if self.star_favorites && highlight.is_favorite {
+            value["text"] = serde_json::Value::from(format!(
+                "⭐ {}",
+                value["text"].as_str().unwrap_or(&highlight.text)
+            ));
+        }
+
+        value["tag_links"] = serde_json::to_value(self.highlight_tag_links(highlight))?;
+        value["readwise_url"] = serde_json::to_value(readwise::highlight_open_url(highlight.id))?;
+        value["open_url"] = serde_json::to_value(self.open_url(book, &value))?;
+        value["first_synced_at"] =
+            serde_json::to_value(self.library.first_synced.highlights.get(&highlight.id))?;
+        value["previous_versions"] = serde_json::to_value(
+            self.library
+                .highlight_versions
+                .get(&highlight.id)
+                .cloned()
+                .unwrap_or_default(),
+        )?;
+
+        Ok(value)
+    }
+
+    /// Highlight text with the cleanup pipelines for the book's category and tags applied.
+    fn clean_text(&self, book: &Book, text: &str) -> String {
+        let tags = book.tags.iter().map(|t| t.name.as_str()).collect_vec();
+        text_cleanup::clean(
+            &self.text_cleanup,
+            &self.category_label(&book.category),
+            &tags,
+            text,
+        )
+    }
+
+    /// The book as it is rendered, with its author mapped to their canonical name, its category
+    /// to the user's label, and its tags sorted by name.
+    fn as_rendered(&self, mut book: Book) -> Book {
+        book.author = book.author.map(|author| self.authors.normalize(&author));
+        book.category = self.category_label(&book.category);
+        book.tags = sorted_tags(&book.tags);
+        book
+    }
+
+    /// The user's label for a category, or the category itself if it isn't remapped.
+    fn category_label(&self, category: &str) -> String {
+        self.category_labels
+            .get(&category.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| category.to_string())
+    }
+
+    fn has_title_template(&self) -> bool {
+        self.templates
+            .get_template_names()
+            .any(|name| name == "title")
+    }
+
+    fn sanitize_title(&self, original: &str, fallback: &str) -> String {
+        // Compose so visually identical titles from different sources produce the same filename
+        let title = original
+            .nfc()
+            .filter(|c| !c.is_control())
+            .collect::<String>();
+
+        let title = self
+            .sanitizer
+            .replace_all(&title, "")
             .replace(":", "-")
-            .replace(".", "-") // Logic for determining file extensions breaks if we have dots in the title
+            .replace(".", "-"); // Logic for determining file extensions breaks if we have dots in the title
+
+        let title = title.trim();
+        if title.chars().all(|c| c == '-' || c.is_whitespace()) {
+            return fallback.to_string();
+        }
+
+        match self.max_filename_bytes {
+            Some(max) if title.len() > max => {
+                // From the whole title, so titles sharing a long prefix are still kept distinct
+                let suffix = format!(" {}", &note_exports::content_hash(original)[..6]);
+
+                let mut truncated = String::new();
+                for grapheme in title.graphemes(true) {
+                    if truncated.len() + grapheme.len() + suffix.len() > max {
+                        break;
+                    }
+                    truncated.push_str(grapheme);
+                }
+
+                format!("{}{}", truncated.trim_end(), suffix)
+            }
+            _ => title.to_string(),
+        }
+    }
+
+    /// The vault-relative link target for a note, without its extension.
+    fn wikilink_target(&self, path: &Path) -> String {
+        path.strip_prefix(&self.vault_root)
+            .unwrap_or(path)
+            .with_extension("")
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    fn delete_stranded(&mut self) -> anyhow::Result<()> {
+        for (book_id, note_reference) in &self.remaining_existing {
+            info!("Trashing stranded note {:?}", note_reference.to_path_buf());
+            self.transaction
+                .stage_trash(&note_reference.to_path_buf())?;
+            self.library.note_exports.remove(book_id);
+        }
+
+        Ok(())
     }
 
-    fn mark_stranded(&self) -> anyhow::Result<()> {
-        let remaining = &self.remaining_existing;
-        for note_reference in remaining.values() {
+    fn mark_stranded(&mut self) -> anyhow::Result<()> {
+        for note_reference in self.remaining_existing.values() {
             let mut note = note_reference
                 .parse::<serde_yml::Value>()
                 .context("Failed to parse note metadata")?;
 
             note.metadata
                 .as_mapping_mut()
-                .expect("Metadata was not a mapping, this is invalid")
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Metadata of note {:?} was not a mapping",
+                        note_reference.to_path_buf()
+                    )
+                })?
                 .insert(
                     serde_yml::Value::from("stranded"),
                     serde_yml::Value::from(true),
                 );
 
-            note.write()?;
+            self.transaction.stage_note(
+                &note_reference.to_path_buf(),
+                &note.metadata,
+                &note.contents,
+            )?;
         }
 
         Ok(())
@@ -438,14 +2356,24 @@ impl Exporter {
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    tracing_subscriber::fmt::init();
+    let _telemetry = telemetry::init()?;
 
     let cli = Cli::parse();
+    readwise::trace_http(cli.trace_http);
     debug!("Parsed CLI: {:?}", &cli);
 
+    let error_report = cli
+        .error_report
+        .clone()
+        .unwrap_or_else(|| errors::default_report_path(&cli.library));
+
     match &cli.command {
         Commands::Fetch(fetch_cmd) => {
-            let readwise = readwise::Readwise::new(&fetch_cmd.api_token);
+            let readwise = readwise::Readwise::new(&fetch_cmd.api_token, cli.parse_mode)
+                .with_reader_categories(fetch_cmd.reader_category.clone())
+                .with_until(fetch_cmd.until)
+                .with_page_size(fetch_cmd.fetch_page_size)
+                .with_export_endpoint(fetch_cmd.export_endpoint);
             let kinds = if fetch_cmd.kind.is_empty() {
                 vec![
                     ReadwiseObjectKind::ReaderDocument,
@@ -456,14 +2384,22 @@ async fn main() -> Result<(), anyhow::Error> {
                 fetch_cmd.kind.clone()
             };
 
-            let library = if !cli.library.exists() {
+            // Tagged documents are synced on their own below
+            let kinds = match &fetch_cmd.reader_tag {
+                Some(_) => kinds
+                    .into_iter()
+                    .filter(|k| *k != ReadwiseObjectKind::ReaderDocument)
+                    .collect_vec(),
+                None => kinds,
+            };
+
+            let mut last_updated = None;
+            let mut library = if !cli.library.exists() {
                 info!(
                     "No cache found at {:?}. Fetching whole library from readwise.",
                     cli.library
                 );
-                let library: Library = readwise.fetch_library(&kinds).await?;
-                serde_json::to_writer(std::fs::File::create(&cli.library)?, &library)?;
-                library
+                readwise.fetch_library(&kinds).await?
             } else {
                 info!("Loading library from cache: {:?}", cli.library);
                 let mut library: Library =
@@ -471,20 +2407,116 @@ async fn main() -> Result<(), anyhow::Error> {
 
                 match fetch_cmd.strategy {
                     FetchStrategy::Update => {
-                        info!("Fetching updates since {:?}", library.updated_at);
-                        readwise.update_library(&mut library, &kinds).await?;
+                        let since = fetch_cmd.since.unwrap_or(library.updated_at);
+                        info!("Fetching updates since {:?}", since);
+                        last_updated = Some(since);
+                        readwise
+                            .update_library(&mut library, &kinds, fetch_cmd.since)
+                            .await?;
+                    }
+
+                    FetchStrategy::Reconcile => {
+                        info!("Reconciling library against readwise");
+                        readwise.reconcile_library(&mut library, &kinds).await?;
                     }
 
                     FetchStrategy::Refetch => {
                         info!("Fetching whole library from readwise");
-                        library = readwise.fetch_library(&kinds).await?;
+                        let previous_books = std::mem::take(&mut library.books);
+                        let previous_highlights = std::mem::take(&mut library.highlights);
+                        let previous_documents = std::mem::take(&mut library.documents);
+                        library = Library {
+                            // Not from the Readwise API so survive a refetch
+                            book_metadata: library.book_metadata,
+                            export_runs: library.export_runs,
+                            exported_highlights_at: library.exported_highlights_at,
+                            note_exports: library.note_exports,
+                            excluded_books: library.excluded_books,
+                            assets: library.assets,
+                            highlight_versions: library.highlight_versions,
+                            deleted_books: library.deleted_books,
+                            deleted_highlights: library.deleted_highlights,
+                            first_synced: library.first_synced,
+                            reader_tag_synced_at: library.reader_tag_synced_at,
+                            ..readwise.fetch_library(&kinds).await?
+                        };
+                        library.record_highlight_versions(&previous_highlights);
+                        library.record_deletions(&kinds, previous_books, previous_highlights);
+                        keep_html_content(&previous_documents, &mut library.documents);
+                        // Imported documents, with ids like `pocket:<id>`, aren't in Reader
+                        library.documents.extend(
+                            previous_documents
+                                .into_iter()
+                                .filter(|d| d.id.contains(':')),
+                        );
                     }
                 }
 
-                serde_json::to_writer(std::fs::File::create(&cli.library)?, &library)?;
                 library
             };
 
+            if let Some(tag) = &fetch_cmd.reader_tag {
+                let full = !matches!(fetch_cmd.strategy, FetchStrategy::Update);
+                readwise
+                    .update_tagged_documents(&mut library, tag, full)
+                    .await?;
+            }
+
+            if let (Some(user), Some(token)) =
+                (&fetch_cmd.hypothesis_user, &fetch_cmd.hypothesis_token)
+            {
+                let (books, highlights) = hypothesis::Hypothesis::new(user, token)
+                    .fetch_annotations(last_updated)
+                    .await?;
+
+                library.upsert_books(books);
+                library.upsert_highlights(highlights);
+            }
+
+            if let Some(token) = &fetch_cmd.raindrop_token {
+                let (documents, books, highlights) = raindrop::Raindrop::new(token)
+                    .fetch_raindrops(&fetch_cmd.raindrop_collection, last_updated)
+                    .await?;
+
+                library.upsert_documents(documents);
+                library.upsert_books(books);
+                library.upsert_highlights(highlights);
+            }
+
+            import::reconcile_readwise_csv(&mut library);
+            library.record_first_synced(Utc::now());
+            retention::apply_retention(&mut library, &fetch_cmd.retain);
+            library.book_merges = merging::find_merges(&library.books, &fetch_cmd.merge_by);
+            library.document_links =
+                merging::find_document_links(&library.books, &library.documents);
+
+            if fetch_cmd.dry_run {
+                let before = match cli.library.exists() {
+                    true => serde_json::from_reader(std::fs::File::open(&cli.library)?)?,
+                    false => Library::empty(),
+                };
+
+                print!("{}", dry_run::summarize(&before, &library)?);
+                return Ok(());
+            }
+
+            library.save(&cli.library, cli.durable_writes)?;
+
+            if fetch_cmd.with_html {
+                readwise
+                    .fetch_html_contents(
+                        &mut library,
+                        fetch_cmd.content_concurrency,
+                        fetch_cmd.content_rate,
+                        fetch_cmd.content_checkpoint,
+                        |library| library.save(&cli.library, cli.durable_writes),
+                    )
+                    .await?;
+                library.save(&cli.library, cli.durable_writes)?;
+            }
+
+            errors::write_report(&error_report, "fetch", &readwise.skipped())?;
+
             info!(
                 "Collected library of {} books and {} highlights",
                 library.books.len(),
@@ -493,16 +2525,455 @@ async fn main() -> Result<(), anyhow::Error> {
         }
 
         Commands::Export(export_cmd) => {
+            // The export works from this snapshot throughout, whatever a concurrent fetch saves
             let library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
 
-            let mut exporter = Exporter::new(library, export_cmd)?;
+            // A check never commits, so it mustn't restore or discard an interrupted export's backups
+            let mut exporter = match export_cmd.check {
+                true => Exporter::inspecting(library, export_cmd)?,
+                false => Exporter::new(library, export_cmd)?,
+            };
+
+            if export_cmd.download_images {
+                let cache = assets::default_cache_dir(&cli.library);
+                match export_cmd.check {
+                    // Compared with the images downloaded by earlier exports, fetching none
+                    true => exporter.use_cached_images(&cache),
+                    false => exporter.download_images(&cache).await?,
+                }
+            }
+
+            if export_cmd.pick {
+                exporter.pick_books()?;
+            }
+
             exporter.export()?;
 
-            if export_cmd.mark_stranded {
-                exporter.mark_stranded()?;
+            // A picked export only refreshes the notes of the picked books
+            if !export_cmd.pick {
+                if export_cmd.document_template.is_some() {
+                    exporter.export_documents()?;
+                }
+
+                if let Some(quotes_file) = &export_cmd.quotes_file {
+                    exporter.write_quotes(quotes_file, export_cmd.quotes_by_tag)?;
+                }
+
+                if let Some(dashboard_file) = &export_cmd.dashboard_file {
+                    exporter.write_dashboard(dashboard_file)?;
+                }
+
+                if !export_cmd.reading_list.is_empty() {
+                    exporter.write_reading_lists(&export_cmd.reading_list)?;
+                }
+
+                if let Some(summary_folder) = &export_cmd.summary_folder {
+                    exporter.write_summaries(summary_folder, &export_cmd.summary_period)?;
+                }
+
+                if let Some(series_folder) = &export_cmd.series_folder {
+                    exporter.write_series_notes(series_folder)?;
+                }
+
+                if let Some(tag_folder) = &export_cmd.tag_folder {
+                    exporter.write_tag_notes(tag_folder)?;
+                }
+
+                if let Some(heatmap_file) = &export_cmd.heatmap_file {
+                    exporter.write_heatmap(heatmap_file, export_cmd.heatmap_months)?;
+                }
+
+                if let Some(daily_notes) = &export_cmd.daily_notes {
+                    // Without an earlier run, only today's highlights are new
+                    let since = match exporter.library.export_runs.last() {
+                        Some(run) => run.exported_at,
+                        None => Local::now()
+                            .date_naive()
+                            .and_hms_opt(0, 0, 0)
+                            .and_then(|t| t.and_local_timezone(Local).single())
+                            .map(|t| t.to_utc())
+                            .unwrap_or_else(Utc::now),
+                    };
+
+                    exporter.append_to_daily_notes(daily_notes, since)?;
+                }
+
+                if export_cmd.mark_stranded {
+                    exporter.mark_stranded()?;
+                }
+
+                if export_cmd.delete_stranded {
+                    exporter.delete_stranded()?;
+                }
+            }
+
+            exporter.write_property_types()?;
+
+            if export_cmd.check {
+                let drift = exporter.transaction.drift()?;
+                let marked = [
+                    ("+", &drift.created),
+                    ("~", &drift.updated),
+                    ("-", &drift.trashed),
+                ];
+                for (marker, paths) in marked {
+                    for path in paths {
+                        println!("{} {}", marker, path.display());
+                    }
+                }
+
+                let changes = drift.created.len() + drift.updated.len() + drift.trashed.len();
+                if changes > 0 {
+                    return Err(anyhow!("{} files in the vault would change", changes));
+                }
+
+                println!("The vault is up to date");
+                return Ok(());
+            }
+
+            // A picked export isn't a run of the whole library
+            if let Some(sync_log) = export_cmd.sync_log.as_ref().filter(|_| !export_cmd.pick) {
+                let stranded = match export_cmd.delete_stranded {
+                    true => 0,
+                    false => exporter.remaining_existing.len(),
+                };
+                exporter.append_sync_log(sync_log, stranded)?;
+            }
+
+            let excluded_highlights = exporter
+                .library
+                .highlights
+                .iter()
+                .filter(|h| exporter.is_excluded(h))
+                .count();
+            if excluded_highlights > 0 {
+                info!(
+                    "Left out {} highlights tagged {}",
+                    excluded_highlights,
+                    export_cmd.exclusion_tag.join(", ")
+                );
+            }
+
+            let applied = exporter.transaction.commit()?;
+
+            if export_cmd.git_commit {
+                git::commit_export(
+                    &export_cmd.vault,
+                    &applied,
+                    &export_cmd.git_message,
+                    export_cmd.git_push,
+                )?;
+            }
+
+            let stranded = exporter
+                .remaining_existing
+                .values()
+                .map(|n| n.to_path_buf())
+                .sorted()
+                .collect_vec();
+
+            let failures = std::mem::take(&mut exporter.failures);
+            let mut library = exporter.library;
+
+            // Picked exports aren't runs of the whole library, so don't move on what the next
+            // export, changelog, or daily notes consider new
+            if !export_cmd.pick {
+                library.record_export_run(ExportRun {
+                    exported_at: Utc::now(),
+                    stranded_count: stranded.len(),
+                    stranded,
+                    excluded_highlights,
+                });
+            }
+
+            // A fetch may have saved the library while this export ran, so only the export's own
+            // bookkeeping is written back, into the library as it is now
+            let mut latest: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+            latest.take_export_bookkeeping(library);
+            latest.save(&cli.library, cli.durable_writes)?;
+            errors::write_report(&error_report, "export", &failures)?;
+
+            if !failures.is_empty() {
+                eprintln!("{} records failed to export:", failures.len());
+                for failure in &failures {
+                    eprintln!(
+                        "  {} {} ({}): {}",
+                        failure.kind,
+                        failure.title.as_deref().unwrap_or_default(),
+                        failure.id,
+                        failure.reason
+                    );
+                }
+
+                return Err(anyhow!("{} records failed to export", failures.len()));
+            }
+        }
+
+        Commands::Prune(prune_cmd) => {
+            let mut library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            retention::apply_retention(&mut library, &prune_cmd.retain);
+            library.save(&cli.library, cli.durable_writes)?;
+        }
+
+        Commands::SyncState(sync_state_cmd) => {
+            let mut library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            match &sync_state_cmd.action {
+                SyncStateAction::Show => print!("{}", sync_state::show(&library)?),
+                SyncStateAction::Reset { kind, reader_tag } => {
+                    sync_state::reset(&mut library, kind, reader_tag);
+                    library.save(&cli.library, cli.durable_writes)?;
+                }
+            }
+        }
+
+        Commands::Show(show_cmd) => {
+            let library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            match &show_cmd.record {
+                ShowRecord::Book { id } => {
+                    print!("{}", inspect::show_book(&library, *id, show_cmd.json)?)
+                }
+            }
+        }
+
+        Commands::Explain(explain_cmd) => {
+            let library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            let mut exporter = Exporter::inspecting(library, &explain_cmd.export)?;
+            print!("{}", exporter.explain(explain_cmd.book_id)?);
+        }
+
+        Commands::Adopt(adopt_cmd) => {
+            let mut library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            match adopt_cmd.match_by {
+                AdoptMatchStrategy::Title => adopt::adopt(
+                    &mut library,
+                    &adopt_cmd.vault,
+                    adopt_cmd.folder.as_deref(),
+                    adopt_cmd.match_threshold,
+                    adopt_cmd.yes,
+                )?,
+            }
+
+            library.save(&cli.library, cli.durable_writes)?;
+        }
+
+        Commands::Init(init_cmd) => {
+            init::init(
+                &init_cmd.dir,
+                &cli.library,
+                &init_cmd.vault,
+                &init_cmd.base_folder,
+                init_cmd.force,
+            )?;
+        }
+
+        Commands::Watch(watch_cmd) => {
+            watch::watch(
+                &cli.library,
+                cli.durable_writes,
+                &watch_cmd.export,
+                watch_cmd.on_removal,
+            )?;
+        }
+
+        Commands::Bench(bench_cmd) => {
+            let mut timings = bench::Timings::default();
+
+            let started = Instant::now();
+            let library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+            timings.library_load = started.elapsed();
+
+            let started = Instant::now();
+            let exporter = Exporter::inspecting(library, &bench_cmd.export)?;
+            timings.setup = started.elapsed();
+
+            print!("{}", exporter.bench(timings)?);
+        }
+
+        Commands::ExportCanvas(canvas_cmd) => {
+            let library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            let mut exporter = Exporter::new(library, &canvas_cmd.export)?;
+            exporter.export_canvas(
+                canvas_cmd.book,
+                canvas_cmd.tag.as_deref(),
+                canvas_cmd.output.as_deref(),
+            )?;
+            exporter.transaction.commit()?;
+        }
+
+        Commands::Search(search_cmd) => {
+            let library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            let mut exporter = Exporter::new(library, &search_cmd.export)?;
+            exporter.search(
+                &search_cmd.query,
+                search_cmd.to_note.as_deref(),
+                search_cmd.search_template.as_deref(),
+            )?;
+            exporter.transaction.commit()?;
+        }
+
+        Commands::Changelog(changelog_cmd) => {
+            let library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+            let since = changelog::resolve_since(&library, &changelog_cmd.since)?;
+
+            print!("{}", changelog::changelog(&library, since));
+        }
+
+        Commands::Enrich(enrich_cmd) => {
+            info!("Loading library from cache: {:?}", cli.library);
+            let mut library: Library = serde_json::from_reader(std::fs::File::open(&cli.library)?)?;
+
+            enrich::Enricher::new(enrich_cmd.provider)
+                .enrich_library(&mut library, &enrich_cmd.category, enrich_cmd.refresh)
+                .await?;
+
+            library.save(&cli.library, cli.durable_writes)?;
+        }
+
+        Commands::Import(import_cmd) => {
+            let mut library = if cli.library.exists() {
+                info!("Loading library from cache: {:?}", cli.library);
+                serde_json::from_reader(std::fs::File::open(&cli.library)?)?
+            } else {
+                Library::empty()
+            };
+
+            match &import_cmd.source {
+                ImportSource::ReadwiseCsv { file } => {
+                    import::import_readwise_csv(&mut library, file)?;
+                }
+
+                ImportSource::Pocket { file } => {
+                    import::import_pocket(&mut library, file)?;
+                }
+
+                ImportSource::Instapaper { file } => {
+                    import::import_instapaper(&mut library, file)?;
+                }
             }
+
+            library.record_first_synced(Utc::now());
+            library.save(&cli.library, cli.durable_writes)?;
+
+            info!(
+                "Collected library of {} books, {} highlights, and {} documents",
+                library.books.len(),
+                library.highlights.len(),
+                library.documents.len()
+            );
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{book, exporter, highlight, library, vault};
+    use std::collections::HashMap;
+
+    #[test]
+    fn truncated_titles_stay_distinct() {
+        let root = vault("truncated-titles");
+        let exporter = exporter(
+            &root,
+            library(vec![], vec![]),
+            &["--max-filename-bytes", "24"],
+        );
+
+        let first = exporter.sanitize_title("A very long shared prefix, part one", "untitled");
+        let second = exporter.sanitize_title("A very long shared prefix, part two", "untitled");
+
+        assert!(first.len() <= 24);
+        assert!(first.starts_with("A very long"));
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            exporter.sanitize_title("A very long shared prefix, part one", "untitled")
+        );
+    }
+
+    #[test]
+    fn only_notes_of_books_gone_from_the_library_are_stranded() {
+        let root = vault("stranding");
+        let article = |id, title| {
+            let mut book = book(id, title);
+            book.category = "articles".to_string();
+            book
+        };
+        let highlights = || {
+            vec![
+                highlight(10, 1, "One"),
+                highlight(20, 2, "Two"),
+                highlight(30, 3, "Three"),
+            ]
+        };
+
+        let mut first = exporter(
+            &root,
+            library(
+                vec![
+                    book(1, "Filtered"),
+                    article(2, "Exported"),
+                    article(3, "Removed"),
+                ],
+                highlights(),
+            ),
+            &[],
+        );
+        first.export().unwrap();
+        first.transaction.commit().unwrap();
+
+        let mut second = exporter(
+            &root,
+            library(
+                vec![book(1, "Filtered"), article(2, "Exported")],
+                highlights(),
+            ),
+            &["--filter-category", "articles"],
+        );
+        second.export().unwrap();
+
+        assert_eq!(
+            second.remaining_existing.keys().collect::<Vec<_>>(),
+            vec![&3]
+        );
+
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn a_merged_book_adopts_the_note_of_the_lowest_id() {
+        let root = vault("merged-notes");
+        let books = || vec![book(3, "Dune (Kindle)"), book(2, "Dune")];
+        let highlights = || vec![highlight(20, 2, "Two"), highlight(30, 3, "Three")];
+
+        let mut first = exporter(&root, library(books(), highlights()), &[]);
+        first.export().unwrap();
+        first.transaction.commit().unwrap();
+
+        let mut merged = library(
+            [book(1, "Dune (Deluxe)")]
+                .into_iter()
+                .chain(books())
+                .collect(),
+            highlights(),
+        );
+        merged.book_merges = HashMap::from([(2, 1), (3, 1)]);
+        let mut second = exporter(&root, merged, &[]);
+        second.export().unwrap();
+
+        assert_eq!(
+            second.remaining_existing.keys().collect::<Vec<_>>(),
+            vec![&3]
+        );
+
+        std::fs::remove_dir_all(root).ok();
+    }
+}
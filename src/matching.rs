@@ -0,0 +1,168 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// How existing notes in the vault are matched to Readwise books.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum NoteMatchStrategy {
+    /// Notes carrying the `__readwise_fk` frontmatter key written by this exporter
+    Fk,
+
+    /// Notes without the frontmatter key which sit exactly where the book's note would be written
+    Path,
+
+    /// Notes without the frontmatter key anywhere in the base folder whose filename closely
+    /// matches the book's title
+    Title,
+}
+
+/// Markdown notes in the base folder which are not managed by the exporter, and so are candidates
+/// for adoption by the path and title strategies. Each note can be adopted by at most one book.
+pub struct UnmanagedNotes {
+    strategies: Vec<NoteMatchStrategy>,
+    threshold: f64,
+    notes: Vec<PathBuf>,
+}
+
+pub(crate) fn markdown_files(dir: &Path, into: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            markdown_files(&path, into)?;
+        } else if path.extension().is_some_and(|e| e == "md") {
+            into.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reduce a title to lowercase alphanumerics so that punctuation and the substitutions made when
+/// sanitizing filenames don't affect matching.
+pub(crate) fn normalize(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl UnmanagedNotes {
+    pub fn new(
+        root: &Path,
+        managed: &HashSet<PathBuf>,
+        strategies: &[NoteMatchStrategy],
+        threshold: f64,
+    ) -> anyhow::Result<Self> {
+        let mut notes = vec![];
+        let adopting = strategies.iter().any(|s| *s != NoteMatchStrategy::Fk);
+
+        if adopting && root.exists() {
+            markdown_files(root, &mut notes)?;
+            notes.retain(|note| !managed.contains(note));
+        }
+
+        debug!("Found {} unmanaged notes", notes.len());
+
+        Ok(UnmanagedNotes {
+            strategies: strategies.to_vec(),
+            threshold,
+            notes,
+        })
+    }
+
+    /// Find an unmanaged note for the book with this title, whose note would by default be written
+    /// to `default_path`, removing it from the candidates if found.
+    pub fn adopt(&mut self, title: &str, default_path: &Path) -> Option<PathBuf> {
+        for strategy in &self.strategies {
+            let index = match strategy {
+                NoteMatchStrategy::Fk => None,
+                NoteMatchStrategy::Path => self.notes.iter().position(|n| n == default_path),
+                NoteMatchStrategy::Title => {
+                    let title = normalize(title);
+                    self.notes
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, note)| {
+                            let stem = note.file_stem()?.to_string_lossy();
+                            let score = strsim::normalized_levenshtein(&title, &normalize(&stem));
+                            (score >= self.threshold).then_some((i, score))
+                        })
+                        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                        .map(|(i, _)| i)
+                }
+            };
+
+            if let Some(index) = index {
+                let note = self.notes.remove(index);
+                info!(
+                    "Adopting unmanaged note {:?} for '{}' by {:?}",
+                    note, title, strategy
+                );
+                return Some(note);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, NoteMatchStrategy, UnmanagedNotes};
+    use std::path::{Path, PathBuf};
+
+    fn unmanaged(strategies: &[NoteMatchStrategy], notes: &[&str]) -> UnmanagedNotes {
+        UnmanagedNotes {
+            strategies: strategies.to_vec(),
+            threshold: 0.8,
+            notes: notes.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    #[test]
+    fn normalize_ignores_punctuation_case_and_spacing() {
+        assert_eq!(
+            normalize("  The Pragmatic-Programmer:  20th Anniversary "),
+            "the pragmaticprogrammer 20th anniversary"
+        );
+    }
+
+    #[test]
+    fn each_note_is_adopted_by_at_most_one_book() {
+        let mut notes = unmanaged(
+            &[NoteMatchStrategy::Path, NoteMatchStrategy::Title],
+            &["Readwise/Books/Dune.md", "Notes/Thinking Fast and Slow.md"],
+        );
+
+        assert_eq!(
+            notes.adopt("Dune", Path::new("Readwise/Books/Dune.md")),
+            Some(PathBuf::from("Readwise/Books/Dune.md"))
+        );
+        assert_eq!(
+            notes.adopt("Dune", Path::new("Readwise/Books/Dune.md")),
+            None
+        );
+        assert_eq!(
+            notes.adopt(
+                "Thinking, Fast and Slow",
+                Path::new("Readwise/Books/Thinking, Fast and Slow.md")
+            ),
+            Some(PathBuf::from("Notes/Thinking Fast and Slow.md"))
+        );
+    }
+
+    #[test]
+    fn fk_alone_adopts_nothing() {
+        let mut notes = unmanaged(&[NoteMatchStrategy::Fk], &["Readwise/Books/Dune.md"]);
+        assert_eq!(
+            notes.adopt("Dune", Path::new("Readwise/Books/Dune.md")),
+            None
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::readwise::{Book, Document};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// How two Readwise books are recognised as being the same underlying work.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum MergeKey {
+    /// Books sharing an Amazon ASIN
+    Asin,
+
+    /// Books in the same category with the same title and author, ignoring case and surrounding
+    /// whitespace. Books without an author are never merged by title alone.
+    TitleAuthor,
+}
+
+impl MergeKey {
+    fn key_for(&self, book: &Book) -> Option<String> {
+        match self {
+            MergeKey::Asin => book
+                .asin
+                .as_ref()
+                .map(|asin| asin.trim().to_lowercase())
+                .filter(|asin| !asin.is_empty()),
+
+            MergeKey::TitleAuthor => {
+                let author = book.author.as_deref()?.trim().to_lowercase();
+                (!author.is_empty()).then(|| {
+                    format!(
+                        "{}\u{0}{}\u{0}{}",
+                        book.category.trim().to_lowercase(),
+                        book.title.trim().to_lowercase(),
+                        author
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Sets of book ids found to be the same work, the root of each set being its lowest id.
+#[derive(Default)]
+struct Groups {
+    parent: HashMap<i32, i32>,
+}
+
+impl Groups {
+    fn find(&mut self, id: i32) -> i32 {
+        let parent = *self.parent.get(&id).unwrap_or(&id);
+        if parent == id {
+            return id;
+        }
+
+        let root = self.find(parent);
+        self.parent.insert(id, root);
+        root
+    }
+
+    fn union(&mut self, a: i32, b: i32) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent.insert(a.max(b), a.min(b));
+        }
+    }
+}
+
+/// Compute which books should be exported together. The returned map goes from the id of each
+/// merged book to the id of the canonical book it is folded into, the canonical book being the
+/// one with the lowest id among all the books any key connects it to, whatever order the books
+/// and keys are given in.
+pub fn find_merges(books: &[Book], keys: &[MergeKey]) -> HashMap<i32, i32> {
+    let mut groups = Groups::default();
+
+    for key in keys {
+        let mut first_with_key: HashMap<String, i32> = HashMap::new();
+        for book in books {
+            if let Some(k) = key.key_for(book) {
+                let first = *first_with_key.entry(k).or_insert(book.id);
+                groups.union(first, book.id);
+            }
+        }
+    }
+
+    let merges = books
+        .iter()
+        .filter_map(|book| {
+            let target = groups.find(book.id);
+            (target != book.id).then_some((book.id, target))
+        })
+        .collect::<HashMap<_, _>>();
+
+    debug!("Merging {} books into others", merges.len());
+    merges
+}
+
+/// A URL reduced to what identifies the page, ignoring the scheme, a `www.` prefix, the fragment,
+/// and any trailing slash.
+fn url_key(url: &str) -> Option<String> {
+    let url = url.trim().to_lowercase();
+    let url = url.split('#').next()?;
+    let url = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let url = url
+        .strip_prefix("www.")
+        .unwrap_or(url)
+        .trim_end_matches('/');
+
+    (!url.is_empty()).then(|| url.to_string())
+}
+
+/// Find the Reader documents which are the same source as a book in the classic library, by their
+/// source URLs. The returned map goes from each book's id to the id of its top level document.
+pub fn find_document_links(books: &[Book], documents: &[Document]) -> HashMap<i32, String> {
+    let mut by_url: HashMap<String, &str> = HashMap::new();
+    for document in documents.iter().filter(|d| d.parent_id.is_none()) {
+        for url in document.source_url.iter().chain([&document.url]) {
+            if let Some(key) = url_key(url) {
+                by_url.entry(key).or_insert(&document.id);
+            }
+        }
+    }
+
+    let links = books
+        .iter()
+        .filter_map(|book| {
+            let document = book
+                .unique_url
+                .iter()
+                .chain(&book.source_url)
+                .filter_map(|url| url_key(url))
+                .find_map(|key| by_url.get(&key))?;
+
+            Some((book.id, document.to_string()))
+        })
+        .collect::<HashMap<_, _>>();
+
+    debug!("Linked {} books to Reader documents", links.len());
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_merges, MergeKey};
+    use crate::readwise::Book;
+    use crate::testing::book;
+    use std::collections::HashMap;
+
+    #[test]
+    fn books_sharing_an_asin_merge_into_the_lowest_id() {
+        let mut kindle = book(7, "Dune");
+        kindle.asin = Some("B00B7NPRY8".to_string());
+        let mut reader = book(3, "Dune (Deluxe Edition)");
+        reader.asin = Some(" b00b7npry8 ".to_string());
+        let unrelated = book(5, "Dune");
+
+        let merges = find_merges(&[kindle, reader, unrelated], &[MergeKey::Asin]);
+        assert_eq!(merges, HashMap::from([(7, 3)]));
+    }
+
+    #[test]
+    fn title_and_author_ignore_case_and_whitespace() {
+        let mut first = book(1, "Thinking, Fast and Slow");
+        first.author = Some("Daniel Kahneman".to_string());
+        let mut second = book(2, "  thinking, fast and slow ");
+        second.author = Some("DANIEL KAHNEMAN".to_string());
+        let mut other_author = book(3, "Thinking, Fast and Slow");
+        other_author.author = Some("Someone Else".to_string());
+
+        let merges = find_merges(&[first, second, other_author], &[MergeKey::TitleAuthor]);
+        assert_eq!(merges, HashMap::from([(2, 1)]));
+    }
+
+    fn by_herbert(id: i32, title: &str) -> Book {
+        Book {
+            author: Some("Frank Herbert".to_string()),
+            ..book(id, title)
+        }
+    }
+
+    fn with_asin(id: i32, title: &str, asin: &str) -> Book {
+        Book {
+            asin: Some(asin.to_string()),
+            ..by_herbert(id, title)
+        }
+    }
+
+    #[test]
+    fn title_and_author_need_an_author_and_the_same_category() {
+        let article = Book {
+            category: "articles".to_string(),
+            ..by_herbert(3, "Dune")
+        };
+        let books = vec![
+            book(1, "Dune"),
+            book(2, "Dune"),
+            article,
+            by_herbert(4, "Dune"),
+            Book {
+                author: Some(" ".to_string()),
+                ..book(5, "Dune")
+            },
+        ];
+
+        let merges = find_merges(&books, &[MergeKey::TitleAuthor]);
+        assert_eq!(merges, HashMap::new());
+    }
+
+    #[test]
+    fn chains_collapse_to_the_lowest_id() {
+        // 30 and 20 share an ASIN, 20 and 10 a title, so all three are one work
+        let books = vec![
+            with_asin(30, "Dune (Kindle Edition)", "B00B7NPRY8"),
+            with_asin(20, "Dune", "B00B7NPRY8"),
+            by_herbert(10, "Dune"),
+            by_herbert(40, "Children of Dune"),
+        ];
+
+        let merges = find_merges(&books, &[MergeKey::Asin, MergeKey::TitleAuthor]);
+
+        assert_eq!(merges, HashMap::from([(30, 10), (20, 10)]));
+    }
+
+    #[test]
+    fn merges_ignore_book_and_key_order() {
+        let books = vec![
+            with_asin(30, "Dune (Kindle Edition)", "B00B7NPRY8"),
+            with_asin(20, "Dune", "B00B7NPRY8"),
+            by_herbert(10, "Dune"),
+            with_asin(50, "Dune Messiah", "B00B7NPRY8"),
+        ];
+        let expected = find_merges(&books, &[MergeKey::Asin, MergeKey::TitleAuthor]);
+
+        let mut reversed = books.clone();
+        reversed.reverse();
+        assert_eq!(
+            find_merges(&reversed, &[MergeKey::TitleAuthor, MergeKey::Asin]),
+            expected
+        );
+        assert_eq!(expected, HashMap::from([(50, 10), (30, 10), (20, 10)]));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::Library;
+use chrono::{DateTime, Utc};
+use obsidian_rust_interface::NoteReference;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Where a book's note was last written, so later exports can find it without scanning the vault.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoteExport {
+    /// The note's path relative to the vault root
+    pub path: PathBuf,
+
+    /// A hash of the note as it was written, frontmatter included
+    pub hash: String,
+
+    pub exported_at: DateTime<Utc>,
+}
+
+/// A stable hash of a note's contents, as 16 hex digits.
+pub fn content_hash(contents: &str) -> String {
+    // FNV-1a, chosen over the std hasher as its output is stable between releases
+    let hash = contents.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("{:016x}", hash)
+}
+
+/// The notes of books recorded by earlier exports, or `None` if there are none or any of them has
+/// since been moved or deleted, in which case the vault has to be scanned for them.
+pub fn recorded_notes(library: &Library, vault_root: &Path) -> Option<HashMap<i32, NoteReference>> {
+    if library.note_exports.is_empty() {
+        return None;
+    }
+
+    let mut notes = HashMap::new();
+    for (book_id, export) in &library.note_exports {
+        let path = vault_root.join(&export.path);
+        if !path.is_file() {
+            debug!("Recorded note {:?} is missing, scanning the vault", path);
+            return None;
+        }
+
+        notes.insert(*book_id, NoteReference::from_path(&path));
+    }
+
+    Some(notes)
+}
@@ -0,0 +1,148 @@
+use crate::readwise::Book;
+use crate::Exporter;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::str::FromStr;
+use tera::Context;
+use tracing::warn;
+
+/// The built-in open-in-app links, by the source of the book. Highlights from web pages link to
+/// the highlighted passage with a text fragment, and those from apps which give each highlight its
+/// own link, such as Apple Books' `ibooks://` or Snipd's snip links, to that or else to the book's
+/// page. Sources without one link to the highlight's own url, if it has one.
+pub const DEFAULT_OPEN_URLS: &[(&str, &str)] = &[
+    (
+        "kindle",
+        "{% if book.asin %}https://readwise.io/to_kindle?action=open&asin={{ book.asin }}&location={{ highlight.location }}{% endif %}",
+    ),
+    (
+        "web",
+        r#"{% if book.source_url %}{{ book.source_url }}#:~:text={{ highlight.text | trim | split(pat="\n") | first | urlencode_strict }}{% endif %}"#,
+    ),
+    ("ibooks", OWN_LINK_OR_SOURCE),
+    ("airr", OWN_LINK_OR_SOURCE),
+    ("snipd", OWN_LINK_OR_SOURCE),
+    ("twitter", OWN_LINK_OR_SOURCE),
+];
+
+/// The highlight's own link, or else the book's.
+const OWN_LINK_OR_SOURCE: &str =
+    "{% if highlight.url %}{{ highlight.url }}{% elif book.source_url %}{{ book.source_url }}{% endif %}";
+
+/// The name the open-in-app link template for a source is registered as.
+pub fn template_name(source: &str) -> String {
+    format!("open_url/{}", source.to_lowercase())
+}
+
+/// A template for the open-in-app links of highlights from a source, rendered with `book` and
+/// `highlight`, e.g. `airr=airr://open?url={{ highlight.url }}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct OpenUrlTemplate {
+    pub source: String,
+    pub template: String,
+}
+
+impl FromStr for OpenUrlTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, template) = s
+            .split_once('=')
+            .map(|(source, template)| (source.trim(), template.trim()))
+            .filter(|(source, template)| !source.is_empty() && !template.is_empty())
+            .ok_or_else(|| anyhow!("Expected <source>=<url template>, got '{}'", s))?;
+
+        Ok(OpenUrlTemplate {
+            source: source.to_lowercase(),
+            template: template.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for OpenUrlTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Exporter {
+    /// The best link to open a highlight in the app it came from: from the template for the
+    /// book's source if it renders one, otherwise the highlight's own url, otherwise Readwise.
+    pub(crate) fn open_url(&self, book: &Book, highlight: &serde_json::Value) -> Option<String> {
+        let name = template_name(book.source.as_deref().unwrap_or_default());
+
+        let rendered = self
+            .templates
+            .get_template_names()
+            .any(|n| n == name)
+            .then(|| {
+                let mut context = Context::new();
+                context.insert("book", book);
+                context.insert("highlight", highlight);
+
+                self.templates
+                    .render(&name, &context)
+                    .inspect_err(|err| {
+                        warn!(
+                            "Failed to render the open url for highlight {}: {}",
+                            highlight["id"], err
+                        )
+                    })
+                    .ok()
+            })
+            .flatten()
+            .map(|url| url.trim().to_string());
+
+        rendered
+            .into_iter()
+            .chain(highlight["url"].as_str().map(str::to_string))
+            .chain(highlight["readwise_url"].as_str().map(str::to_string))
+            .find(|url| !url.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{template_name, DEFAULT_OPEN_URLS};
+    use crate::testing::{book, highlight};
+    use tera::{Context, Tera};
+
+    fn render(source: &str, context: &Context) -> String {
+        let mut tera = Tera::default();
+        for (source, template) in DEFAULT_OPEN_URLS {
+            tera.add_raw_template(&template_name(source), template)
+                .unwrap();
+        }
+
+        tera.render(&template_name(source), context).unwrap()
+    }
+
+    #[test]
+    fn default_links_for_each_source() {
+        let mut book = book(1, "Title");
+        book.asin = Some("B000".to_string());
+        book.source_url = Some("https://example.com/post".to_string());
+        let mut highlight = highlight(10, 1, "A passage, quoted\nand more");
+
+        let mut context = Context::new();
+        context.insert("book", &book);
+        context.insert("highlight", &highlight);
+
+        assert_eq!(
+            render("kindle", &context),
+            "https://readwise.io/to_kindle?action=open&asin=B000&location=10"
+        );
+        assert_eq!(
+            render("web", &context),
+            "https://example.com/post#:~:text=A%20passage%2C%20quoted"
+        );
+        assert_eq!(render("snipd", &context), "https://example.com/post");
+
+        highlight.url = Some("ibooks://assetid/1#cfi".to_string());
+        context.insert("highlight", &highlight);
+        assert_eq!(render("ibooks", &context), "ibooks://assetid/1#cfi");
+    }
+}
@@ -0,0 +1,163 @@
+use crate::readwise::{parse_timestamp, Book, Highlight};
+use crate::Exporter;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// The path of a continuation note of the note at `path`, numbering from the note itself as part 1.
+fn part_path(path: &Path, part: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    path.with_file_name(format!("{} (Part {}).md", stem, part))
+}
+
+impl Exporter {
+    /// Split a book's highlights between its note and its continuation notes, if notes are limited
+    /// in length. Highlights are assigned to parts in the order they were made, keeping their
+    /// order within each part, so existing highlights never move between parts.
+    pub(crate) fn note_parts<'a>(&self, highlights: &[&'a Highlight]) -> Vec<Vec<&'a Highlight>> {
+        let Some(max) = self.max_highlights_per_note else {
+            return vec![highlights.to_vec()];
+        };
+
+        let part_of = highlights
+            .iter()
+            .sorted_by_key(|h| (h.highlighted_at.as_deref().and_then(parse_timestamp), h.id))
+            .enumerate()
+            .map(|(index, highlight)| (highlight.id, index / max))
+            .collect::<HashMap<_, _>>();
+
+        let count = highlights.len().div_ceil(max).max(1);
+        (0..count)
+            .map(|part| {
+                highlights
+                    .iter()
+                    .copied()
+                    .filter(|h| part_of[&h.id] == part)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Stage the continuation notes of the book's note at `path`, trashing any left from when it
+    /// had more, and return the note's contents with links to them.
+    pub(crate) fn stage_parts(
+        &mut self,
+        book: &Book,
+        path: &Path,
+        contents: String,
+    ) -> anyhow::Result<String> {
+        if self.max_highlights_per_note.is_none() {
+            return Ok(contents);
+        }
+
+        let highlights = self.highlights_for(book);
+        let parts = self.note_parts(&highlights);
+        let context = self.create_template_context(&book, &highlights)?;
+        let main_link = self.wikilink_target(path);
+
+        let mut rendered = vec![];
+        for (index, highlights) in parts.iter().enumerate().skip(1) {
+            let part = index + 1;
+            let part_contents = format!(
+                "Part {} of [[{}|{}]]\n\n%% HIGHLIGHTS_BEGIN %%\n\n{}\n",
+                part,
+                main_link,
+                book.title,
+                self.render_highlights(book, highlights, &context)?
+            );
+
+            rendered.push((part, part_path(path, part), part_contents));
+        }
+
+        let part_count = parts.len();
+        let mut links = vec![];
+        for (part, part_path, part_contents) in rendered {
+            debug!(
+                "Writing part {} of '{}' to {:?}",
+                part, book.title, part_path
+            );
+
+            let metadata = serde_yml::to_value(serde_json::json!({
+                "note-kind": "readwise-part",
+                "__readwise_part_of": book.id,
+                "part": part,
+            }))?;
+
+            self.transaction
+                .stage_note(&part_path, &metadata, &part_contents)?;
+            links.push(format!(
+                "[[{}|Part {}]]",
+                self.wikilink_target(&part_path),
+                part
+            ));
+        }
+
+        // Parts left over from when the book had more highlights, or a lower limit
+        for part in part_count + 1.. {
+            let stale = part_path(path, part);
+            if !self.transaction.exists(&stale)? {
+                break;
+            }
+
+            info!("Trashing leftover part {:?}", stale);
+            self.transaction.stage_trash(&stale)?;
+        }
+
+        if links.is_empty() {
+            return Ok(contents);
+        }
+
+        Ok(format!(
+            "{}\n\nContinued in {}\n",
+            contents.trim_end(),
+            links.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::part_path;
+    use crate::testing::{exporter, highlight, library, vault};
+    use std::path::Path;
+
+    #[test]
+    fn parts_are_numbered_beside_the_note() {
+        assert_eq!(
+            part_path(Path::new("Readwise/Books/Dune.md"), 2),
+            Path::new("Readwise/Books/Dune (Part 2).md")
+        );
+    }
+
+    #[test]
+    fn highlights_fill_parts_in_the_order_they_were_made() {
+        let vault = vault("note-parts");
+        let exporter = exporter(
+            &vault,
+            library(vec![], vec![]),
+            &["--max-highlights-per-note", "2"],
+        );
+
+        let mut highlights = (1..=5)
+            .map(|id| highlight(id, 1, &format!("Highlight {}", id)))
+            .collect::<Vec<_>>();
+        for (highlight, day) in highlights.iter_mut().zip([5, 1, 4, 2, 3]) {
+            highlight.highlighted_at = Some(format!("2024-01-0{}T00:00:00Z", day));
+        }
+
+        let parts = exporter.note_parts(&highlights.iter().collect::<Vec<_>>());
+        let ids = parts
+            .iter()
+            .map(|part| part.iter().map(|h| h.id).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // Made in the order 2, 4, 5, 3, 1, and kept in their given order within each part
+        assert_eq!(ids, vec![vec![2, 4], vec![3, 5], vec![1]]);
+        std::fs::remove_dir_all(&vault).unwrap();
+    }
+}
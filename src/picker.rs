@@ -0,0 +1,60 @@
+use crate::readwise::Book;
+use crate::Exporter;
+use inquire::MultiSelect;
+use itertools::Itertools;
+use std::collections::HashSet;
+use std::fmt;
+
+/// A book as listed in the picker, searched by its title, author, and category.
+struct PickerEntry {
+    id: i32,
+    label: String,
+}
+
+impl fmt::Display for PickerEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.label)
+    }
+}
+
+fn entry(book: &Book) -> PickerEntry {
+    let label = match &book.author {
+        Some(author) => format!("{} — {} ({})", book.title, author, book.category),
+        None => format!("{} ({})", book.title, book.category),
+    };
+
+    PickerEntry { id: book.id, label }
+}
+
+impl Exporter {
+    /// Ask which of the books that would be exported should be, from a fuzzy-searchable list,
+    /// restricting the export to them.
+    pub(crate) fn pick_books(&mut self) -> anyhow::Result<()> {
+        let entries = self
+            .library
+            .books
+            .iter()
+            .filter(|book| self.skip_reason(book).is_none())
+            .sorted_by_key(|book| book.title.to_lowercase())
+            .map(entry)
+            .collect_vec();
+
+        if entries.is_empty() {
+            anyhow::bail!("There are no books to pick from");
+        }
+
+        let picked = MultiSelect::new("Books to export:", entries)
+            .with_page_size(15)
+            .prompt()?
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect::<HashSet<_>>();
+
+        if picked.is_empty() {
+            anyhow::bail!("No books were picked");
+        }
+
+        self.picked_books = Some(picked);
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::readwise::{Book, Highlight};
+use regex::Regex;
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::sync::LazyLock;
+
+/// A timestamp such as `12:34` or `1:02:03` written into a highlight by a podcast app.
+static TIMESTAMP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(?:(\d{1,2}):)?(\d{1,2}):(\d{2})\b").unwrap());
+
+/// Podcast apps which sync their snips to Readwise, identified by the host of the highlight url.
+const PODCAST_HOSTS: [&str; 2] = ["snipd.com", "airr.io"];
+
+fn is_podcast(book: &Book, highlight: &Highlight) -> bool {
+    let podcast_host = highlight
+        .url
+        .as_deref()
+        .and_then(|url| Url::parse(url).ok())
+        .and_then(|url| url.host_str().map(str::to_string))
+        .is_some_and(|host| PODCAST_HOSTS.iter().any(|h| host.ends_with(h)));
+
+    podcast_host || book.category.eq_ignore_ascii_case("podcasts")
+}
+
+/// Seconds into the episode the highlight was made at, from its location when Readwise records
+/// a time offset and otherwise from the first timestamp in its text or note.
+pub(crate) fn offset_seconds(highlight: &Highlight) -> Option<u32> {
+    if highlight.location_type == "time_offset" {
+        return u32::try_from(highlight.location).ok();
+    }
+
+    let captures = TIMESTAMP
+        .captures(&highlight.text)
+        .or_else(|| TIMESTAMP.captures(&highlight.note))?;
+
+    let part = |i: usize| {
+        captures
+            .get(i)
+            .and_then(|m| m.as_str().parse::<u32>().ok())
+            .unwrap_or(0)
+    };
+
+    Some(part(1) * 3600 + part(2) * 60 + part(3))
+}
+
+fn format_offset(seconds: u32) -> String {
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, minutes, seconds) => format!("{}:{:02}", minutes, seconds),
+        (hours, minutes, seconds) => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    }
+}
+
+/// Add `timestamp`, `timestamp_seconds`, and `audio_url` to a podcast highlight's template value.
+/// The audio link is the app's share link for the snip when there is one, and otherwise the
+/// episode's audio with a media fragment for the timestamp.
+pub fn add_podcast_fields(book: &Book, highlight: &Highlight, value: &mut Value) {
+    if !is_podcast(book, highlight) {
+        return;
+    }
+
+    let Some(seconds) = offset_seconds(highlight) else {
+        return;
+    };
+
+    let audio_url = highlight.url.clone().or_else(|| {
+        book.source_url
+            .as_ref()
+            .map(|url| format!("{}#t={}", url, seconds))
+    });
+
+    if let Some(object) = value.as_object_mut() {
+        object.insert("timestamp".to_string(), json!(format_offset(seconds)));
+        object.insert("timestamp_seconds".to_string(), json!(seconds));
+        object.insert("audio_url".to_string(), json!(audio_url));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{book, highlight};
+
+    #[test]
+    fn timestamps_come_from_the_offset_then_the_text() {
+        let mut snip = highlight(1, 1, "Interesting point at 1:02:03 about soil");
+        assert_eq!(offset_seconds(&snip), Some(3723));
+
+        snip.location_type = "time_offset".to_string();
+        snip.location = 95;
+        assert_eq!(offset_seconds(&snip), Some(95));
+
+        let mut plain = highlight(2, 1, "No time here");
+        assert_eq!(offset_seconds(&plain), None);
+        plain.note = "see 4:05".to_string();
+        assert_eq!(offset_seconds(&plain), Some(245));
+    }
+
+    #[test]
+    fn offsets_drop_the_hours_when_there_are_none() {
+        assert_eq!(format_offset(245), "4:05");
+        assert_eq!(format_offset(3723), "1:02:03");
+    }
+
+    #[test]
+    fn episode_audio_is_linked_at_the_timestamp() {
+        let mut episode = book(1, "Episode");
+        episode.category = "podcasts".to_string();
+        episode.source_url = Some("https://example.com/episode.mp3".to_string());
+
+        let mut value = json!({});
+        add_podcast_fields(&episode, &highlight(1, 1, "at 12:34"), &mut value);
+
+        assert_eq!(value["timestamp"], "12:34");
+        assert_eq!(value["timestamp_seconds"], 754);
+        assert_eq!(value["audio_url"], "https://example.com/episode.mp3#t=754");
+    }
+
+    #[test]
+    fn highlights_of_other_books_are_left_alone() {
+        let mut value = json!({});
+        add_podcast_fields(&book(1, "A Book"), &highlight(1, 1, "at 12:34"), &mut value);
+        assert_eq!(value, json!({}));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::readwise::parse_timestamp;
+use crate::Exporter;
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_yml::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// The types of Obsidian properties, as named in `.obsidian/types.json`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PropertyType {
+    Text,
+    Multitext,
+    Number,
+    Checkbox,
+    Date,
+    Datetime,
+    Tags,
+    Aliases,
+}
+
+/// The types of the properties written for book and document notes by default.
+const DEFAULT_TYPES: &[(&str, PropertyType)] = &[
+    ("id", PropertyType::Number),
+    ("title", PropertyType::Text),
+    ("author", PropertyType::Text),
+    ("category", PropertyType::Text),
+    ("num_highlights", PropertyType::Number),
+    ("last_highlight_at", PropertyType::Datetime),
+    ("updated", PropertyType::Datetime),
+    ("cover_image_url", PropertyType::Text),
+    ("highlights_url", PropertyType::Text),
+    ("source_url", PropertyType::Text),
+    ("asin", PropertyType::Text),
+    ("readable_title", PropertyType::Text),
+    ("document_note", PropertyType::Text),
+    ("source", PropertyType::Text),
+    ("unique_url", PropertyType::Text),
+    ("tags", PropertyType::Tags),
+    ("aliases", PropertyType::Aliases),
+    ("stranded", PropertyType::Checkbox),
+    ("word_count", PropertyType::Number),
+    ("reading_progress", PropertyType::Number),
+    ("published_date", PropertyType::Date),
+    ("created_at", PropertyType::Datetime),
+    ("updated_at", PropertyType::Datetime),
+    ("saved_at", PropertyType::Datetime),
+    ("first_opened_at", PropertyType::Datetime),
+    ("last_opened_at", PropertyType::Datetime),
+    ("last_moved_at", PropertyType::Datetime),
+    ("reader_location", PropertyType::Text),
+    ("reader_url", PropertyType::Text),
+];
+
+/// The `.obsidian/types.json` file, keeping any other keys Obsidian writes there.
+#[derive(Default, Serialize, Deserialize)]
+struct TypesFile {
+    #[serde(default)]
+    types: BTreeMap<String, serde_json::Value>,
+    #[serde(flatten)]
+    other: BTreeMap<String, serde_json::Value>,
+}
+
+/// Converts note metadata to the YAML types Obsidian expects for each property, so that dates,
+/// numbers, checkboxes, and tags show with the right widget in the Properties view.
+pub struct PropertyTypes {
+    types: HashMap<String, PropertyType>,
+}
+
+impl PropertyTypes {
+    /// The default types for book properties, overridden and extended by a manifest mapping
+    /// property names to types, for example:
+    ///
+    /// ```yaml
+    /// published: date
+    /// rating: number
+    /// read: checkbox
+    /// ```
+    pub fn load(manifest: Option<&Path>) -> anyhow::Result<Self> {
+        let mut types: HashMap<String, PropertyType> = DEFAULT_TYPES
+            .iter()
+            .map(|(name, kind)| (name.to_string(), *kind))
+            .collect();
+
+        if let Some(path) = manifest {
+            let overrides: HashMap<String, PropertyType> =
+                serde_yml::from_reader(std::fs::File::open(path)?)
+                    .with_context(|| format!("Failed to parse property types {:?}", path))?;
+            types.extend(overrides);
+        }
+
+        Ok(PropertyTypes { types })
+    }
+
+    /// Convert each property of `metadata` with a known type in place. Values which can't be
+    /// converted are left as they are.
+    pub fn apply(&self, metadata: &mut serde_yml::Mapping) {
+        for (key, value) in metadata.iter_mut() {
+            let Some(kind) = key.as_str().and_then(|k| self.types.get(k)) else {
+                continue;
+            };
+
+            if let Some(converted) = convert(value, *kind) {
+                *value = converted;
+            } else if !value.is_null() {
+                warn!(
+                    "Could not convert property {:?} with value {:?} to {:?}",
+                    key, value, kind
+                );
+            }
+        }
+    }
+}
+
+fn scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        // Readwise tags are objects with a name
+        Value::Mapping(m) => m.get("name").and_then(|n| n.as_str()).map(str::to_string),
+        _ => None,
+    }
+}
+
+fn convert(value: &Value, kind: PropertyType) -> Option<Value> {
+    if value.is_null() {
+        return None;
+    }
+
+    let items = || match value {
+        Value::Sequence(items) => items.iter().filter_map(scalar_string).collect(),
+        // Reader tags are a mapping keyed by the tag name
+        Value::Mapping(m) if !m.contains_key("name") => {
+            m.keys().filter_map(scalar_string).collect()
+        }
+        value => scalar_string(value).into_iter().collect::<Vec<_>>(),
+    };
+
+    match kind {
+        PropertyType::Text => scalar_string(value).map(Value::from),
+        PropertyType::Multitext | PropertyType::Aliases => Some(Value::Sequence(
+            items().into_iter().map(Value::from).collect(),
+        )),
+        PropertyType::Tags => Some(Value::Sequence(
+            items()
+                .into_iter()
+                .map(|tag| Value::from(tag.trim().replace(char::is_whitespace, "-")))
+                .collect(),
+        )),
+        PropertyType::Number => match value {
+            Value::Number(_) => Some(value.clone()),
+            Value::String(s) => s
+                .trim()
+                .parse::<i64>()
+                .map(Value::from)
+                .or_else(|_| s.trim().parse::<f64>().map(Value::from))
+                .ok(),
+            _ => None,
+        },
+        PropertyType::Checkbox => match value {
+            Value::Bool(_) => Some(value.clone()),
+            Value::String(s) => match s.trim().to_lowercase().as_str() {
+                "true" | "yes" => Some(Value::from(true)),
+                "false" | "no" | "" => Some(Value::from(false)),
+                _ => None,
+            },
+            _ => None,
+        },
+        // Obsidian stores dates and times in local time without an offset
+        PropertyType::Date | PropertyType::Datetime => {
+            let timestamp = match value {
+                // Reader gives some dates as milliseconds since the epoch
+                Value::Number(n) => DateTime::from_timestamp_millis(n.as_i64()?)?,
+                value => parse_timestamp(value.as_str()?)?,
+            }
+            .with_timezone(&Local);
+            let format = match kind {
+                PropertyType::Date => "%Y-%m-%d",
+                _ => "%Y-%m-%dT%H:%M:%S",
+            };
+            Some(Value::from(timestamp.format(format).to_string()))
+        }
+    }
+}
+
+impl Exporter {
+    /// Record the types of the exported properties in `.obsidian/types.json`, so Obsidian shows
+    /// them with the right widget. Types the user has already set in Obsidian are kept.
+    pub(crate) fn write_property_types(&mut self) -> anyhow::Result<()> {
+        let Some(property_types) = &self.property_types else {
+            return Ok(());
+        };
+
+        let path = self.vault_root.join(".obsidian").join("types.json");
+        let mut file: TypesFile = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse {:?}", path))?,
+            Err(_) => TypesFile::default(),
+        };
+
+        let before = file.types.len();
+        for (name, kind) in &property_types.types {
+            if !file.types.contains_key(name) {
+                file.types.insert(name.clone(), serde_json::to_value(kind)?);
+            }
+        }
+
+        if file.types.len() == before {
+            return Ok(());
+        }
+
+        info!(
+            "Registering {} property types in {:?}",
+            file.types.len() - before,
+            path
+        );
+        self.transaction
+            .stage_file(&path, serde_json::to_string_pretty(&file)?)
+    }
+}
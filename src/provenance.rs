@@ -0,0 +1,91 @@
+use crate::note_exports::content_hash;
+use crate::readwise::Book;
+use crate::templates::HighlightTemplateRule;
+use crate::Exporter;
+use chrono::Utc;
+use obsidian_rust_interface::NoteReference;
+use std::path::Path;
+
+const VERSION_KEY: &str = "__readwise_exporter_version";
+const TEMPLATE_HASH_KEY: &str = "__readwise_template_hash";
+const SCRIPT_HASH_KEY: &str = "__readwise_script_hash";
+const EXPORTED_AT_KEY: &str = "__readwise_exported_at";
+
+/// A hash of a metadata script, or of a metadata command's program if it can be read, falling back
+/// to its name for programs found on the `PATH`.
+pub fn script_hash(path: &Path) -> String {
+    match std::fs::read(path) {
+        Ok(contents) => content_hash(&String::from_utf8_lossy(&contents)),
+        Err(_) => content_hash(&path.to_string_lossy()),
+    }
+}
+
+impl Exporter {
+    /// A hash of the sources of the named templates, or those they fall back to, so a change to any
+    /// of them changes the hash.
+    fn templates_hash(&self, names: &[&str]) -> String {
+        let sources = names
+            .iter()
+            .filter_map(|name| Some((name, self.template_sources.get(*name)?)))
+            .map(|(name, source)| format!("{}\0{}", name, source))
+            .collect::<Vec<_>>()
+            .join("\0");
+
+        content_hash(&sources)
+    }
+
+    /// A hash of the templates a book's note is rendered with, including every highlight template
+    /// rule as any of them may select its highlights.
+    fn book_templates_hash(&self, book: &Book) -> String {
+        let mut names = vec![
+            self.template_for(book, "book"),
+            self.template_for(book, "highlight"),
+            self.template_for(book, "document_note"),
+            "title".to_string(),
+        ];
+        names.extend(
+            (0..self.highlight_template_rules.len()).map(HighlightTemplateRule::template_name),
+        );
+
+        self.templates_hash(&names.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    /// Record the exporter version, template hash, script hash, and time of export in a note's
+    /// frontmatter, if provenance is recorded.
+    fn add_provenance(&self, template_hash: String, metadata: &mut serde_yml::Mapping) {
+        if !self.provenance {
+            return;
+        }
+
+        metadata.insert(VERSION_KEY.into(), env!("CARGO_PKG_VERSION").into());
+        metadata.insert(TEMPLATE_HASH_KEY.into(), template_hash.into());
+        if let Some(script_hash) = &self.script_hash {
+            metadata.insert(SCRIPT_HASH_KEY.into(), script_hash.as_str().into());
+        }
+        metadata.insert(EXPORTED_AT_KEY.into(), Utc::now().to_rfc3339().into());
+    }
+
+    pub(crate) fn add_book_provenance(&self, book: &Book, metadata: &mut serde_yml::Mapping) {
+        self.add_provenance(self.book_templates_hash(book), metadata);
+    }
+
+    pub(crate) fn add_document_provenance(&self, metadata: &mut serde_yml::Mapping) {
+        self.add_provenance(self.templates_hash(&["document", "highlight"]), metadata);
+    }
+
+    /// Whether a book's note was rendered with the templates as they are now.
+    pub(crate) fn has_current_templates(
+        &self,
+        book: &Book,
+        note: &NoteReference,
+    ) -> anyhow::Result<bool> {
+        let recorded = note
+            .parse::<serde_yml::Value>()?
+            .metadata
+            .get(TEMPLATE_HASH_KEY)
+            .and_then(|hash| hash.as_str())
+            .map(str::to_string);
+
+        Ok(recorded.is_some_and(|hash| hash == self.book_templates_hash(book)))
+    }
+}
@@ -0,0 +1,69 @@
+use crate::Exporter;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use tracing::info;
+
+impl Exporter {
+    /// Write every exported highlight as its own quote block into a single file in the base
+    /// folder, for random-quote plugins to embed. Each block links back to the highlight's block
+    /// in its book note. With `by_tag`, a `Quotes/<tag>.md` file is also written per highlight tag.
+    pub(crate) fn write_quotes(&mut self, file_name: &str, by_tag: bool) -> anyhow::Result<()> {
+        let mut all = String::new();
+        let mut tagged: BTreeMap<String, String> = BTreeMap::new();
+
+        let books = self
+            .library
+            .books
+            .iter()
+            .filter(|b| self.exported_paths.contains_key(&b.id))
+            .sorted_by(|a, b| a.title.cmp(&b.title));
+
+        for book in books {
+            let target = self.wikilink_target(&self.exported_paths[&book.id]);
+
+            for highlight in self
+                .highlights_for(book)
+                .into_iter()
+                .sorted_by_key(|h| h.location)
+            {
+                let mut block = String::new();
+                writeln!(block, "> {}", highlight.text.trim().replace('\n', "\n> "))?;
+                writeln!(
+                    block,
+                    "> — [[{}#^{}|{}]]{}",
+                    target,
+                    highlight.id,
+                    book.title,
+                    book.author
+                        .as_ref()
+                        .map(|a| format!(", {}", self.authors.normalize(a)))
+                        .unwrap_or_default()
+                )?;
+                writeln!(block, "^quote{}\n", highlight.id.unsigned_abs())?;
+
+                all.push_str(&block);
+                if by_tag {
+                    for tag in &highlight.tags {
+                        tagged.entry(tag.name.clone()).or_default().push_str(&block);
+                    }
+                }
+            }
+        }
+
+        let path = self.export_root.join(file_name);
+        info!("Writing quotes file {:?}", path);
+        self.transaction.stage_file(&path, all)?;
+
+        if by_tag {
+            let tag_root = self.export_root.join("Quotes");
+            for (tag, contents) in tagged {
+                let name = self.sanitize_title(&tag, "untitled");
+                self.transaction
+                    .stage_file(&tag_root.join(name).with_extension("md"), contents)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use crate::import::{archived_document, synthetic_id};
+use crate::readwise::{http_client, send, Book, Document, Highlight, Tag};
+use chrono::{DateTime, Utc};
+use reqwest::header::AUTHORIZATION;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::{debug, info, instrument};
+
+/// A client for the Raindrop.io API, used to sync bookmarks (and any highlights made on them) into
+/// the library alongside Reader documents.
+pub struct Raindrop {
+    token: String,
+    api_endpoint: Url,
+    api_page_size: i32,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct CollectionsResponse {
+    items: Vec<Collection>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Collection {
+    #[serde(rename = "_id")]
+    id: i64,
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaindropsResponse {
+    items: Vec<RaindropItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RaindropItem {
+    #[serde(rename = "_id")]
+    id: i64,
+    link: String,
+    title: Option<String>,
+    excerpt: Option<String>,
+    note: Option<String>,
+    cover: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    created: DateTime<Utc>,
+    last_update: DateTime<Utc>,
+    collection_id: Option<i64>,
+    domain: Option<String>,
+    #[serde(default)]
+    highlights: Vec<RaindropHighlight>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RaindropHighlight {
+    #[serde(rename = "_id")]
+    id: String,
+    text: String,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    color: String,
+    created: String,
+    last_update: Option<String>,
+}
+
+impl Raindrop {
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            api_endpoint: "https://api.raindrop.io/rest/v1".parse().unwrap(),
+            api_page_size: 50,
+            client: http_client(),
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: Url) -> anyhow::Result<T> {
+        debug!("Raindrop api url: {}", url);
+
+        let response = send(
+            self.client
+                .get(url)
+                .header(AUTHORIZATION, format!("Bearer {}", self.token)),
+        )
+        .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Unexpected response: {:?}", response));
+        }
+
+        Ok(response.json::<T>().await?)
+    }
+
+    async fn collection_titles(&self) -> anyhow::Result<HashMap<i64, String>> {
+        let mut titles = HashMap::new();
+
+        for segment in ["collections", "collections/childrens"] {
+            let mut url = self.api_endpoint.clone();
+            url.path_segments_mut().unwrap().extend(segment.split('/'));
+            let response: CollectionsResponse = self.get(url).await?;
+            titles.extend(response.items.into_iter().map(|c| (c.id, c.title)));
+        }
+
+        Ok(titles)
+    }
+
+    /// Fetch bookmarks from the given collections (or all collections if empty) as documents, each
+    /// tagged with the title of its collection. Bookmarks with highlights additionally get a
+    /// synthetic article book holding those highlights.
+    #[instrument(skip_all)]
+    pub async fn fetch_raindrops(
+        &self,
+        collections: &[i64],
+        last_updated: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Vec<Document>, Vec<Book>, Vec<Highlight>)> {
+        info!(
+            "Fetching bookmarks from Raindrop, since {}",
+            last_updated
+                .map(|v| v.to_rfc3339())
+                .unwrap_or("[all]".to_string())
+        );
+
+        let titles = self.collection_titles().await?;
+        // Collection 0 is Raindrop's alias for all collections
+        let collections = if collections.is_empty() {
+            vec![0]
+        } else {
+            collections.to_vec()
+        };
+
+        let mut items = vec![];
+        for collection in collections {
+            let mut page = 0;
+            loop {
+                let mut url = self.api_endpoint.clone();
+                url.path_segments_mut()
+                    .unwrap()
+                    .push("raindrops")
+                    .push(&collection.to_string());
+                url.query_pairs_mut()
+                    .append_pair("sort", "-lastUpdate")
+                    .append_pair("perpage", &self.api_page_size.to_string())
+                    .append_pair("page", &page.to_string());
+
+                let response: RaindropsResponse = self.get(url).await?;
+                let page_len = response.items.len();
+
+                // Sorted by most recently updated, so stop at the first unchanged bookmark
+                let fresh = response
+                    .items
+                    .into_iter()
+                    .take_while(|item| last_updated.is_none_or(|since| item.last_update > since))
+                    .collect::<Vec<_>>();
+
+                let exhausted = fresh.len() < page_len || page_len < self.api_page_size as usize;
+                items.extend(fresh);
+
+                if exhausted {
+                    break;
+                }
+
+                page += 1;
+            }
+        }
+
+        let mut documents = vec![];
+        let mut books = vec![];
+        let mut highlights = vec![];
+
+        for item in items {
+            let collection = item.collection_id.and_then(|id| titles.get(&id)).cloned();
+
+            let mut tags = item.tags.clone();
+            tags.extend(collection.clone());
+
+            documents.push(Document {
+                id: format!("raindrop:{}", item.id),
+                location: None,
+                reading_progress: 0.0,
+                site_name: item.domain.clone(),
+                summary: item.excerpt.clone().filter(|e| !e.is_empty()),
+                notes: item.note.clone().filter(|n| !n.is_empty()),
+                image_url: item.cover.clone().filter(|c| !c.is_empty()),
+                updated_at: item.last_update.to_rfc3339(),
+                ..archived_document(
+                    "raindrop",
+                    item.link.clone(),
+                    item.title.clone(),
+                    item.created,
+                    tags,
+                )
+            });
+
+            if item.highlights.is_empty() {
+                continue;
+            }
+
+            let book_id = synthetic_id("raindrop-book", &item.id.to_string());
+            books.push(Book {
+                id: book_id,
+                title: item.title.clone().unwrap_or_else(|| item.link.clone()),
+                author: None,
+                category: "articles".to_string(),
+                num_highlights: item.highlights.len() as i32,
+                last_highlight_at: item.highlights.iter().map(|h| h.created.clone()).max(),
+                updated: Some(item.last_update.to_rfc3339()),
+                cover_image_url: item.cover.clone().filter(|c| !c.is_empty()),
+                highlights_url: None,
+                source_url: Some(item.link.clone()),
+                asin: None,
+                tags: collection
+                    .iter()
+                    .map(|name| Tag {
+                        id: synthetic_id("raindrop-collection", name),
+                        name: name.clone(),
+                    })
+                    .collect(),
+                readable_title: None,
+                document_note: item.note.clone().filter(|n| !n.is_empty()),
+                source: Some("raindrop".to_string()),
+                unique_url: Some(item.link.clone()),
+            });
+
+            highlights.extend(
+                item.highlights
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, h)| Highlight {
+                        id: synthetic_id("raindrop", &h.id),
+                        text: h.text,
+                        note: h.note,
+                        location: i as i32,
+                        location_type: "order".to_string(),
+                        highlighted_at: Some(h.created.clone()),
+                        url: Some(item.link.clone()),
+                        color: h.color,
+                        updated: h.last_update.unwrap_or(h.created),
+                        book_id,
+                        tags: vec![],
+                        is_favorite: false,
+                        is_discard: false,
+                        end_location: None,
+                        external_id: Some(h.id.clone()),
+                    }),
+            );
+        }
+
+        info!(
+            "Fetched {} bookmarks and {} highlights from Raindrop",
+            documents.len(),
+            highlights.len()
+        );
+
+        Ok((documents, books, highlights))
+    }
+}
@@ -0,0 +1,140 @@
+use crate::readwise::parse_timestamp;
+use crate::Exporter;
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use tera::Context;
+use tracing::info;
+
+const DEFAULT_READING_LIST_TEMPLATE: &str = r#"# {{ title }}
+
+{% if documents %}| Title | Author | Progress | Words | Saved |
+| --- | --- | ---: | ---: | --- |
+{% for document in documents -%}
+| [{{ document.title | replace(from="|", to="\|") }}]({{ document.reader_url }}) | {{ document.author | replace(from="|", to="\|") }} | {{ document.progress }}% | {% if document.word_count %}{{ document.word_count }}{% endif %} | {{ document.saved }} |
+{% endfor %}{% else %}Nothing here.
+{% endif %}"#;
+
+/// A note listing the Reader documents in a location, e.g. `later=Reading Queue.md`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ReadingList {
+    pub location: String,
+    pub file: String,
+}
+
+impl FromStr for ReadingList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (location, file) = s.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "Expected <location>=<file>, e.g. later=Reading Queue.md, got '{}'",
+                s
+            )
+        })?;
+
+        let (location, file) = (location.trim(), file.trim());
+        if location.is_empty() || file.is_empty() {
+            return Err(anyhow!("Empty location or file in reading list '{}'", s));
+        }
+
+        Ok(ReadingList {
+            location: location.to_lowercase(),
+            file: file.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for ReadingList {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// A document as listed in a reading list note.
+#[derive(Serialize)]
+struct ListedDocument<'a> {
+    id: &'a str,
+    title: &'a str,
+    author: &'a str,
+    url: &'a str,
+    reader_url: String,
+    category: Option<&'a str>,
+    progress: String,
+    word_count: Option<i64>,
+    saved: String,
+    last_opened: String,
+}
+
+impl Exporter {
+    /// Write a note for each reading list within the base folder, listing the Reader documents in
+    /// its location most recently saved first, with their progress, length, and when they were
+    /// saved. Regenerated on each export.
+    pub(crate) fn write_reading_lists(&mut self, lists: &[ReadingList]) -> anyhow::Result<()> {
+        if !self
+            .templates
+            .get_template_names()
+            .any(|n| n == "reading_list")
+        {
+            self.templates
+                .add_raw_template("reading_list", DEFAULT_READING_LIST_TEMPLATE)?;
+        }
+
+        let date = |timestamp: Option<&str>| {
+            timestamp
+                .and_then(parse_timestamp)
+                .map(|t| t.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        };
+
+        for list in lists {
+            let documents = self
+                .library
+                .documents
+                .iter()
+                .filter(|d| d.parent_id.is_none())
+                .filter(|d| d.location.as_deref() == Some(list.location.as_str()))
+                .sorted_by(|a, b| b.saved_at.cmp(&a.saved_at))
+                .map(|d| ListedDocument {
+                    id: &d.id,
+                    title: d.title.as_deref().unwrap_or(&d.url),
+                    author: d.author.as_deref().unwrap_or(""),
+                    url: d.source_url.as_deref().unwrap_or(&d.url),
+                    reader_url: format!("https://read.readwise.io/read/{}", d.id),
+                    category: d.category.as_deref(),
+                    progress: format!("{:.0}", d.reading_progress * 100.0),
+                    word_count: d.word_count,
+                    saved: date(Some(&d.saved_at)),
+                    last_opened: date(d.last_opened_at.as_deref()),
+                })
+                .collect_vec();
+
+            let title = Path::new(&list.file)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| list.location.clone());
+
+            let mut context = Context::new();
+            context.insert("title", &title);
+            context.insert("location", &list.location);
+            context.insert("documents", &documents);
+
+            let path = self.export_root.join(&list.file);
+            info!(
+                "Writing reading list {:?} with {} documents",
+                path,
+                documents.len()
+            );
+
+            let contents = self.templates.render("reading_list", &context)?;
+            self.transaction.stage_file(&path, contents)?;
+        }
+
+        Ok(())
+    }
+}
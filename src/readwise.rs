@@ -1,20 +1,43 @@
 use chrono::{DateTime, Utc};
 use reqwest::header::AUTHORIZATION;
 use reqwest::{StatusCode, Url};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Formatter};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
 
 pub struct Readwise {
     token: String,
     api_endpoint: Url,
     api_page_size: i32,
+    parse_mode: ParseMode,
+    client: reqwest::Client,
+
+    /// Only fetch Reader documents in these categories, or every category if empty
+    reader_categories: Vec<ReaderCategory>,
+
+    /// Only fetch records updated before this time, if set
+    until: Option<DateTime<Utc>>,
+
+    /// Fetch books and highlights together through the export endpoint
+    export_endpoint: bool,
+
+    /// Malformed records skipped in lenient mode, for the error report
+    skipped: Mutex<Vec<RecordError>>,
 }
 
-use crate::{Library, ReadwiseObjectKind};
-use serde::de::DeserializeOwned;
+use crate::errors::RecordError;
+use crate::{Library, ParseMode, ReadwiseObjectKind};
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use tracing::{debug, info};
+use serde_json::{json, Map, Value};
+use tracing::{debug, info, info_span, instrument, warn, Instrument};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Book {
@@ -29,7 +52,19 @@ pub struct Book {
     pub highlights_url: Option<String>,
     pub source_url: Option<String>,
     pub asin: Option<String>,
+    /// Given as `book_tags` by the export endpoint
+    #[serde(default, alias = "book_tags")]
     pub tags: Vec<Tag>,
+    #[serde(default)]
+    pub readable_title: Option<String>,
+    /// The note written on the document as a whole in Readwise or Reader
+    #[serde(default)]
+    pub document_note: Option<String>,
+    /// Where the book was imported from, e.g. `kindle`, `reader`, or `instapaper`
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub unique_url: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,9 +77,21 @@ pub struct Highlight {
     pub highlighted_at: Option<String>,
     pub url: Option<String>,
     pub color: String,
+    /// Given as `updated_at` by the export endpoint
+    #[serde(alias = "updated_at")]
     pub updated: String,
     pub book_id: i32,
     pub tags: Vec<Tag>,
+    #[serde(default)]
+    pub is_favorite: bool,
+    #[serde(default)]
+    pub is_discard: bool,
+    /// Where the highlight ends, for highlights spanning a range of locations
+    #[serde(default)]
+    pub end_location: Option<i32>,
+    /// The highlight's id in the app it was imported from
+    #[serde(default)]
+    pub external_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -53,6 +100,39 @@ pub struct Tag {
     pub name: String,
 }
 
+/// The categories of Reader documents the v3 list endpoint can filter by.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReaderCategory {
+    Article,
+    Pdf,
+    Epub,
+    Tweet,
+    Video,
+    Email,
+    Rss,
+    Note,
+    Highlight,
+}
+
+impl Display for ReaderCategory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            ReaderCategory::Article => "article",
+            ReaderCategory::Pdf => "pdf",
+            ReaderCategory::Epub => "epub",
+            ReaderCategory::Tweet => "tweet",
+            ReaderCategory::Video => "video",
+            ReaderCategory::Email => "email",
+            ReaderCategory::Rss => "rss",
+            ReaderCategory::Note => "note",
+            ReaderCategory::Highlight => "highlight",
+        };
+
+        write!(f, "{}", str)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum Resource {
     Books,
@@ -70,67 +150,647 @@ impl Display for Resource {
     }
 }
 
+/// Parse a timestamp as found in Readwise records, which are usually RFC 3339 but appear with a
+/// space separator in Readwise's CSV export.
+pub fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .or_else(|_| DateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%:z"))
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// The page reviewing a book in Readwise, for books which came from Readwise rather than being
+/// imported from elsewhere (which have negative synthetic ids).
+pub fn book_review_url(book_id: i32) -> Option<String> {
+    (book_id > 0).then(|| format!("https://readwise.io/bookreview/{}", book_id))
+}
+
+/// The link opening a highlight in Readwise, for highlights which came from Readwise.
+pub fn highlight_open_url(highlight_id: i32) -> Option<String> {
+    (highlight_id > 0).then(|| format!("https://readwise.io/open/{}", highlight_id))
+}
+
+/// Parse a date (YYYY-MM-DD, as midnight UTC) or an RFC 3339 timestamp given on the command line.
+pub fn parse_date_arg(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+
+    parse_timestamp(value)
+        .ok_or_else(|| format!("expected a date (YYYY-MM-DD) or timestamp, got '{}'", value))
+}
+
+/// Build the HTTP client shared by every request made to an API, so that connections and TLS
+/// sessions are reused between pages rather than renegotiated for each one.
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(120))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(4)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Whether API requests are logged, set by `--trace-http`
+static TRACE_HTTP: AtomicBool = AtomicBool::new(false);
+
+/// Log every API request sent with [`send`], and the response to it.
+pub(crate) fn trace_http(enabled: bool) {
+    TRACE_HTTP.store(enabled, Ordering::Relaxed);
+}
+
+/// Send an API request in an `api_request` span.
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let span = info_span!("api_request", method = %request.method(), url = %request.url());
+
+    execute(client, request).instrument(span).await
+}
+
+/// Execute a request, logging its method, URL, and headers, and the status, timing, and rate limit
+/// headers of the response if HTTP tracing is enabled. The Authorization header is redacted.
+async fn execute(
+    client: reqwest::Client,
+    request: reqwest::Request,
+) -> reqwest::Result<reqwest::Response> {
+    if !TRACE_HTTP.load(Ordering::Relaxed) {
+        return client.execute(request).await;
+    }
+
+    let method = request.method().clone();
+    let url = request.url().clone();
+
+    let headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| match *name == AUTHORIZATION {
+            true => format!("{}: <redacted>", name),
+            false => format!("{}: {}", name, value.to_str().unwrap_or("<binary>")),
+        })
+        .join(", ");
+    info!(target: "http", "{} {} [{}]", method, url, headers);
+
+    let started = Instant::now();
+    match client.execute(request).await {
+        Ok(response) => {
+            let rate_limits = response
+                .headers()
+                .iter()
+                .filter(|(name, _)| {
+                    name.as_str().contains("ratelimit") || name.as_str() == "retry-after"
+                })
+                .map(|(name, value)| format!("{}: {}", name, value.to_str().unwrap_or("<binary>")))
+                .join(", ");
+
+            info!(
+                target: "http",
+                "{} {} -> {} in {} ms [{}]",
+                method,
+                url,
+                response.status(),
+                started.elapsed().as_millis(),
+                rate_limits
+            );
+            Ok(response)
+        }
+
+        Err(err) => {
+            info!(
+                target: "http",
+                "{} {} failed after {} ms: {}",
+                method,
+                url,
+                started.elapsed().as_millis(),
+                err
+            );
+            Err(err)
+        }
+    }
+}
+
+/// Spaces requests made by concurrent tasks out to a fixed rate.
+struct RateLimiter {
+    interval: Duration,
+    next: tokio::sync::Mutex<tokio::time::Instant>,
+}
+
+impl RateLimiter {
+    fn per_minute(requests: u32) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs(60) / requests.max(1),
+            next: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+        }
+    }
+
+    /// Wait for the next free slot.
+    async fn acquire(&self) {
+        let slot = {
+            let mut next = self.next.lock().await;
+            let slot = (*next).max(tokio::time::Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Hold back every request for `delay`, after being rate limited anyway.
+    async fn back_off(&self, delay: Duration) {
+        let mut next = self.next.lock().await;
+        *next = (*next).max(tokio::time::Instant::now() + delay);
+    }
+}
+
+/// Fetch the stored HTML of a single Reader document, `None` if it has none.
+async fn fetch_html_content(
+    client: &reqwest::Client,
+    token: &str,
+    limiter: &RateLimiter,
+    id: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut url = Url::parse("https://readwise.io/api/v3/list").unwrap();
+    url.query_pairs_mut()
+        .append_pair("id", id)
+        .append_pair("withHtmlContent", "true");
+
+    loop {
+        limiter.acquire().await;
+        let response = send(
+            client
+                .get(url.clone())
+                .header(AUTHORIZATION, format!("Token {}", token)),
+        )
+        .await?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_delay = retry_delay(&response);
+            debug!("Rate limited, retrying in {} seconds", retry_delay);
+            limiter.back_off(Duration::from_secs(retry_delay)).await;
+            continue;
+        } else if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Unexpected response: {:?}", response));
+        }
+
+        let response = response.json::<DocumentListResponse>().await?;
+        return Ok(response
+            .results
+            .first()
+            .and_then(|document| document.get("html_content"))
+            .and_then(|html| html.as_str())
+            .map(str::to_string));
+    }
+}
+
+/// How long to wait before retrying a rate limited request, as requested by the API.
+fn retry_delay(response: &reqwest::Response) -> u64 {
+    response
+        .headers()
+        .get("Retry-After")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(5)
+}
+
 impl Readwise {
-    pub fn new(token: &str) -> Self {
+    pub fn new(token: &str, parse_mode: ParseMode) -> Self {
         Self {
             token: token.to_string(),
             api_endpoint: "https://readwise.io/api/v2".parse().unwrap(),
             api_page_size: 1000,
+            parse_mode,
+            client: http_client(),
+            reader_categories: vec![],
+            until: None,
+            export_endpoint: false,
+            skipped: Mutex::new(vec![]),
         }
     }
 
+    /// Only fetch Reader documents in these categories, or every category if empty.
+    pub fn with_reader_categories(mut self, categories: Vec<ReaderCategory>) -> Self {
+        self.reader_categories = categories;
+        self
+    }
+
+    /// Only fetch records updated before `until`, if set.
+    pub fn with_until(mut self, until: Option<DateTime<Utc>>) -> Self {
+        self.until = until;
+        self
+    }
+
+    /// Fetch books and highlights together through the v2 export endpoint, with each book's
+    /// highlights nested in it, rather than listing each separately.
+    pub fn with_export_endpoint(mut self, export_endpoint: bool) -> Self {
+        self.export_endpoint = export_endpoint;
+        self
+    }
+
+    /// Request this many records per page from the v2 API, at most 1000.
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.api_page_size = page_size;
+        self
+    }
+
+    /// The malformed records skipped so far.
+    pub fn skipped(&self) -> Vec<RecordError> {
+        self.skipped.lock().unwrap().clone()
+    }
+
+    /// Parse the records of a response page one by one so that a single malformed record is
+    /// either kept with its malformed fields defaulted (lenient) or reported by its id (strict),
+    /// rather than failing the whole page.
+    fn parse_records<T: DeserializeOwned>(
+        &self,
+        resource: &str,
+        records: Vec<Value>,
+    ) -> anyhow::Result<Vec<T>> {
+        let mut parsed = Vec::with_capacity(records.len());
+
+        for record in records {
+            let id = record.get("id").cloned().unwrap_or(Value::Null);
+            let title = record
+                .get("title")
+                .and_then(|t| t.as_str())
+                .map(str::to_string);
+
+            let result = match self.parse_mode {
+                ParseMode::Lenient => parse_leniently::<T>(record),
+                ParseMode::Strict => serde_json::from_value::<T>(record).map(|r| (r, vec![])),
+            };
+
+            match result {
+                Ok((record, defaulted)) => {
+                    if !defaulted.is_empty() {
+                        warn!(
+                            "Defaulted the malformed fields {} of {} record {}",
+                            defaulted.join(", "),
+                            resource,
+                            id
+                        );
+                    }
+
+                    parsed.push(record);
+                }
+
+                Err(err) => match self.parse_mode {
+                    ParseMode::Lenient => {
+                        warn!("Skipping malformed {} record {}: {}", resource, id, err);
+                        self.skipped.lock().unwrap().push(RecordError {
+                            kind: resource.to_string(),
+                            id: id.as_str().map(str::to_string).unwrap_or(id.to_string()),
+                            title,
+                            reason: err.to_string(),
+                        });
+                    }
+
+                    ParseMode::Strict => {
+                        return Err(anyhow::anyhow!(
+                            "Failed to parse {} record {}: {}",
+                            resource,
+                            id,
+                            err
+                        ))
+                    }
+                },
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    #[instrument(skip(self))]
     pub async fn fetch_library(&self, kinds: &[ReadwiseObjectKind]) -> anyhow::Result<Library> {
+        let (books, highlights) = if self.uses_export_endpoint(kinds) {
+            let (mut books, highlights) = self.fetch_export(None).await?;
+            tally_highlights(&mut books, &highlights);
+            (books, highlights)
+        } else {
+            (vec![], vec![])
+        };
+
         Ok(Library {
-            books: if kinds.contains(&ReadwiseObjectKind::Book) {
-                self.fetch_books(None).await?
-            } else {
+            books: if !kinds.contains(&ReadwiseObjectKind::Book) {
                 vec![]
-            },
-            highlights: if kinds.contains(&ReadwiseObjectKind::Highlight) {
-                self.fetch_highlights(None).await?
+            } else if self.export_endpoint {
+                books
             } else {
+                self.fetch_books(None).await?
+            },
+            highlights: if !kinds.contains(&ReadwiseObjectKind::Highlight) {
                 vec![]
+            } else if self.export_endpoint {
+                highlights
+            } else {
+                self.fetch_highlights(None).await?
             },
 
             documents: if kinds.contains(&ReadwiseObjectKind::ReaderDocument) {
-                self.fetch_document_list(None, None).await?
+                self.fetch_document_list(None, None, None).await?
             } else {
                 vec![]
             },
-            updated_at: Utc::now(),
+            // Kinds which weren't fetched are fetched in full by the next update
+            kind_synced_at: ReadwiseObjectKind::value_variants()
+                .iter()
+                .map(|kind| (*kind, kinds.contains(kind).then(Utc::now)))
+                .collect(),
+            ..Library::empty()
         })
     }
 
+    /// Fetch the records of each kind updated since the kind was last synced, or since `since` if
+    /// given. A run over a window given by `since` or an `until` is a one-off backfill, so it
+    /// leaves the library's sync state where it was.
+    #[instrument(skip_all)]
     pub async fn update_library(
         &self,
         library: &mut Library,
         kinds: &[ReadwiseObjectKind],
+        since: Option<DateTime<Utc>>,
     ) -> anyhow::Result<()> {
-        let last_updated = library.updated_at;
+        let synced_at = Utc::now();
+        let since_for = |library: &Library, kind| since.or_else(|| library.synced_at(kind));
+
+        if self.uses_export_endpoint(kinds) {
+            // From whichever kind was synced longest ago, as both come in the one pass
+            let since = [ReadwiseObjectKind::Book, ReadwiseObjectKind::Highlight]
+                .into_iter()
+                .filter(|kind| kinds.contains(kind))
+                .map(|kind| since_for(library, kind))
+                .min()
+                .flatten();
+
+            let (mut books, highlights) = self.fetch_export(since).await?;
+            if kinds.contains(&ReadwiseObjectKind::Highlight) {
+                library.upsert_highlights(highlights);
+            }
+
+            // Only the highlights updated since are given, so each book is tallied from the
+            // library as a whole
+            if kinds.contains(&ReadwiseObjectKind::Book) {
+                tally_highlights(&mut books, &library.highlights);
+                library.upsert_books(books);
+            }
+        }
+
+        // Upserted, as a backfill window overlaps records already in the library
+        if kinds.contains(&ReadwiseObjectKind::Book) && !self.export_endpoint {
+            let books = self
+                .fetch_books(since_for(library, ReadwiseObjectKind::Book))
+                .await?;
+            library.upsert_books(books);
+        }
+
+        if kinds.contains(&ReadwiseObjectKind::Highlight) && !self.export_endpoint {
+            let highlights = self
+                .fetch_highlights(since_for(library, ReadwiseObjectKind::Highlight))
+                .await?;
+            library.upsert_highlights(highlights);
+        }
+
+        if kinds.contains(&ReadwiseObjectKind::ReaderDocument) {
+            let documents = self
+                .fetch_document_list(
+                    since_for(library, ReadwiseObjectKind::ReaderDocument),
+                    None,
+                    None,
+                )
+                .await?;
+            library.upsert_documents(documents);
+        }
+
+        if since.is_none() && self.until.is_none() {
+            library.mark_synced(kinds, synced_at);
+        }
+
+        Ok(())
+    }
+
+    /// Download every record of each kind in full, as the API has no lighter listing of just ids
+    /// and updated timestamps, and bring the library in line with them, replacing only the records
+    /// which are missing locally or whose updated timestamp differs. This catches changes that
+    /// incremental `updated__gt` syncs miss while leaving records from other sources (imports,
+    /// other services) untouched.
+    #[instrument(skip_all)]
+    pub async fn reconcile_library(
+        &self,
+        library: &mut Library,
+        kinds: &[ReadwiseObjectKind],
+    ) -> anyhow::Result<()> {
+        // From before the sweep, so records changed while it runs are picked up by the next sync
+        let synced_at = Utc::now();
 
         if kinds.contains(&ReadwiseObjectKind::Book) {
-            library
+            let local = library
                 .books
-                .extend(self.fetch_books(Some(last_updated)).await?);
+                .iter()
+                .map(|b| (b.id, b.updated.clone()))
+                .collect::<HashMap<_, _>>();
+
+            let drifted = self
+                .fetch_books(None)
+                .await?
+                .into_iter()
+                .filter(|b| local.get(&b.id) != Some(&b.updated))
+                .collect::<Vec<_>>();
+
+            info!("Reconciled {} missing or stale books", drifted.len());
+            library.upsert_books(drifted);
         }
 
         if kinds.contains(&ReadwiseObjectKind::Highlight) {
-            library
+            let local = library
                 .highlights
-                .extend(self.fetch_highlights(Some(last_updated)).await?);
+                .iter()
+                .map(|h| (h.id, h.updated.clone()))
+                .collect::<HashMap<_, _>>();
+
+            let drifted = self
+                .fetch_highlights(None)
+                .await?
+                .into_iter()
+                .filter(|h| local.get(&h.id) != Some(&h.updated))
+                .collect::<Vec<_>>();
+
+            info!("Reconciled {} missing or stale highlights", drifted.len());
+            library.upsert_highlights(drifted);
         }
 
         if kinds.contains(&ReadwiseObjectKind::ReaderDocument) {
-            library
+            let local = library
                 .documents
-                .extend(self.fetch_document_list(Some(last_updated), None).await?);
+                .iter()
+                .map(|d| (d.id.clone(), d.updated_at.clone()))
+                .collect::<HashMap<_, _>>();
+
+            let drifted = self
+                .fetch_document_list(None, None, None)
+                .await?
+                .into_iter()
+                .filter(|d| local.get(&d.id) != Some(&d.updated_at))
+                .collect::<Vec<_>>();
+
+            info!("Reconciled {} missing or stale documents", drifted.len());
+            library.upsert_documents(drifted);
         }
 
-        library.updated_at = Utc::now();
+        library.mark_synced(kinds, synced_at);
 
         Ok(())
     }
 
+    /// Sync only the Reader documents with `tag`, since the tag was last synced or in full. The
+    /// tag keeps its own sync state, apart from the library's, so a tagged subset of Reader can be
+    /// kept up to date without syncing every document.
+    #[instrument(skip(self, library))]
+    pub async fn update_tagged_documents(
+        &self,
+        library: &mut Library,
+        tag: &str,
+        full: bool,
+    ) -> anyhow::Result<()> {
+        let since = match full {
+            true => None,
+            false => library.reader_tag_synced_at.get(tag).copied(),
+        };
+
+        let synced_at = Utc::now();
+        let documents = self.fetch_document_list(since, None, Some(tag)).await?;
+
+        info!("Synced {} documents tagged '{}'", documents.len(), tag);
+        library.upsert_documents(documents);
+        library
+            .reader_tag_synced_at
+            .insert(tag.to_string(), synced_at);
+
+        Ok(())
+    }
+
+    fn uses_export_endpoint(&self, kinds: &[ReadwiseObjectKind]) -> bool {
+        self.export_endpoint
+            && (kinds.contains(&ReadwiseObjectKind::Book)
+                || kinds.contains(&ReadwiseObjectKind::Highlight))
+    }
+
+    /// Fetch books updated since `updated_after`, or every book, with their highlights through the
+    /// export endpoint. When `updated_after` is given only the highlights updated since then are
+    /// included.
+    #[instrument(skip(self))]
+    pub async fn fetch_export(
+        &self,
+        updated_after: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Vec<Book>, Vec<Highlight>)> {
+        info!(
+            "Fetching books with their highlights from the Readwise export endpoint, since {}",
+            updated_after
+                .map(|v| v.to_rfc3339())
+                .unwrap_or("[all]".to_string())
+        );
+
+        let mut base_url = self.api_endpoint.clone();
+        base_url
+            .path_segments_mut()
+            .unwrap()
+            .push("export")
+            .push("");
+        if let Some(updated_after) = updated_after {
+            base_url
+                .query_pairs_mut()
+                .append_pair("updatedAfter", &updated_after.to_rfc3339());
+        }
+
+        let mut books = vec![];
+        let mut highlights = vec![];
+        let mut next_page_cursor: Option<String> = None;
+
+        loop {
+            let mut url = base_url.clone();
+            if let Some(cursor) = &next_page_cursor {
+                url.query_pairs_mut().append_pair("pageCursor", cursor);
+            }
+
+            debug!("Readwise api url: {}", url);
+
+            let response = send(
+                self.client
+                    .get(url)
+                    .header(AUTHORIZATION, format!("Token {}", self.token)),
+            )
+            .await?;
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let retry_delay = retry_delay(&response);
+
+                debug!("Rate limited, retrying in {} seconds", retry_delay);
+
+                tokio::time::sleep(Duration::from_secs(retry_delay)).await;
+                continue;
+            } else if !response.status().is_success() {
+                return Err(anyhow::anyhow!("Unexpected response: {:?}", response));
+            }
+
+            let response = response.json::<ExportResponse>().await?;
+
+            debug!(
+                "Received api response: results={}, next_cursor={:?}",
+                response.results.len(),
+                response.next_page_cursor
+            );
+
+            for book in self.parse_records::<ExportedBook>("book", response.results)? {
+                // The export endpoint can't filter by an upper bound, so highlights after it are
+                // dropped
+                highlights.extend(
+                    self.parse_records::<Highlight>("highlight", book.highlights)?
+                        .into_iter()
+                        .filter(|h| match (self.until, parse_timestamp(&h.updated)) {
+                            (Some(until), Some(updated)) => updated < until,
+                            _ => true,
+                        }),
+                );
+
+                books.push(Book {
+                    id: book.user_book_id,
+                    title: book.title,
+                    author: book.author,
+                    category: book.category,
+                    num_highlights: 0,
+                    last_highlight_at: None,
+                    updated: None,
+                    cover_image_url: book.cover_image_url,
+                    highlights_url: book.readwise_url,
+                    source_url: book.source_url,
+                    asin: book.asin,
+                    tags: book.book_tags,
+                    readable_title: book.readable_title,
+                    document_note: book.document_note,
+                    source: book.source,
+                    unique_url: book.unique_url,
+                });
+            }
+
+            next_page_cursor = response.next_page_cursor;
+            if next_page_cursor.is_none() {
+                break;
+            }
+        }
+
+        debug!(
+            "Fetched {} books and {} highlights total",
+            books.len(),
+            highlights.len()
+        );
+
+        Ok((books, highlights))
+    }
+
     pub async fn fetch_books(
         &self,
         last_updated: Option<DateTime<Utc>>,
@@ -145,6 +805,7 @@ impl Readwise {
         self.fetch_paged(Resource::Highlights, last_updated).await
     }
 
+    #[instrument(skip(self))]
     pub(crate) async fn fetch_paged<T: DeserializeOwned>(
         &self,
         resource: Resource,
@@ -172,25 +833,26 @@ impl Readwise {
                 .append_pair("updated__gt", &last_updated.to_rfc3339());
         }
 
+        if let Some(until) = self.until {
+            url.query_pairs_mut()
+                .append_pair("updated__lt", &until.to_rfc3339());
+        }
+
         debug!("Readwise api url: {}", url);
 
         let mut entities = vec![];
         let mut next_url = url.clone();
 
         loop {
-            let response = reqwest::Client::new()
-                .get(next_url.clone())
-                .header(AUTHORIZATION, format!("Token {}", self.token))
-                .send()
-                .await?;
+            let response = send(
+                self.client
+                    .get(next_url.clone())
+                    .header(AUTHORIZATION, format!("Token {}", self.token)),
+            )
+            .await?;
 
             if response.status() == StatusCode::TOO_MANY_REQUESTS {
-                let retry_delay = response
-                    .headers()
-                    .get("Retry-After")
-                    .map(|v| v.to_str().unwrap())
-                    .map(|v| v.parse::<u64>().unwrap())
-                    .unwrap_or(5);
+                let retry_delay = retry_delay(&response);
 
                 debug!("Rate limited, retrying in {} seconds", retry_delay);
 
@@ -200,7 +862,7 @@ impl Readwise {
                 return Err(anyhow::anyhow!("Unexpected response: {:?}", response));
             }
 
-            let mut response = response.json::<CollectionResponse<T>>().await?;
+            let response = response.json::<CollectionResponse<Value>>().await?;
 
             debug!(
                 "Received api response: count={count}, next={next:?}, previous={previous:?}",
@@ -209,31 +871,131 @@ impl Readwise {
                 previous = response.previous,
             );
 
-            entities.append(&mut response.results);
+            entities.extend(self.parse_records(&resource.to_string(), response.results)?);
 
             if let Some(next) = response.next {
-                next_url = Url::parse(&next).unwrap();
+                next_url = Url::parse(&next)
+                    .map_err(|e| anyhow::anyhow!("Invalid next page url {:?}: {}", next, e))?;
             } else {
                 break;
             }
         }
 
-        return Ok(entities);
+        Ok(entities)
+    }
+
+    /// Fetch the stored HTML of the Reader documents which don't have it yet, `concurrency` at a
+    /// time and at most `per_minute` requests a minute between them. The library is passed to
+    /// `checkpoint` after every `checkpoint_every` documents, so an interrupted fetch picks up
+    /// where it left off.
+    #[instrument(skip_all)]
+    pub async fn fetch_html_contents(
+        &self,
+        library: &mut Library,
+        concurrency: usize,
+        per_minute: u32,
+        checkpoint_every: usize,
+        mut checkpoint: impl FnMut(&Library) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut pending = library
+            .documents
+            .iter()
+            .filter(|d| d.parent_id.is_none() && d.html_content.is_none())
+            .map(|d| d.id.clone())
+            .collect::<VecDeque<_>>();
+
+        let total = pending.len();
+        info!("Fetching the content of {} Reader documents", total);
+
+        let limiter = Arc::new(RateLimiter::per_minute(per_minute));
+        let mut tasks = JoinSet::new();
+        let mut fetched = 0;
+
+        loop {
+            while tasks.len() < concurrency.max(1) {
+                let Some(id) = pending.pop_front() else {
+                    break;
+                };
+
+                let client = self.client.clone();
+                let token = self.token.clone();
+                let limiter = limiter.clone();
+                tasks.spawn(async move {
+                    let html = fetch_html_content(&client, &token, &limiter, &id).await;
+                    (id, html)
+                });
+            }
+
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+
+            match joined? {
+                // Documents without stored HTML get an empty one, so they aren't asked for again
+                (id, Ok(html)) => {
+                    if let Some(document) = library.documents.iter_mut().find(|d| d.id == id) {
+                        document.html_content = Some(html.unwrap_or_default());
+                    }
+                }
+
+                (id, Err(err)) => {
+                    warn!("Failed to fetch the content of document {}: {:#}", id, err)
+                }
+            }
+
+            fetched += 1;
+            if fetched % checkpoint_every.max(1) == 0 {
+                info!("Fetched the content of {}/{} documents", fetched, total);
+                checkpoint(library)?;
+            }
+        }
+
+        Ok(())
     }
 
+    /// Fetch the Reader documents in the configured categories, with a request per category as the
+    /// list endpoint filters by one category at a time.
     pub async fn fetch_document_list(
         &self,
         updated_after: Option<DateTime<Utc>>,
         location: Option<String>,
+        tag: Option<&str>,
+    ) -> Result<Vec<Document>, anyhow::Error> {
+        if self.reader_categories.is_empty() {
+            return self
+                .fetch_document_category(updated_after, location, tag, None)
+                .await;
+        }
+
+        let mut documents = Vec::new();
+        for category in &self.reader_categories {
+            documents.extend(
+                self.fetch_document_category(updated_after, location.clone(), tag, Some(*category))
+                    .await?,
+            );
+        }
+
+        Ok(documents)
+    }
+
+    #[instrument(skip(self))]
+    async fn fetch_document_category(
+        &self,
+        updated_after: Option<DateTime<Utc>>,
+        location: Option<String>,
+        tag: Option<&str>,
+        category: Option<ReaderCategory>,
     ) -> Result<Vec<Document>, anyhow::Error> {
         info!(
-            "Fetching reader documents from Readwise, since {}",
+            "Fetching reader documents{}{} from Readwise, since {}",
+            category.map(|c| format!(" in '{}'", c)).unwrap_or_default(),
+            tag.map(|t| format!(" tagged '{}'", t)).unwrap_or_default(),
             updated_after
                 .map(|v| v.to_rfc3339())
                 .unwrap_or("[all]".to_string())
         );
 
-        let mut base_url = Url::parse("https://readwise.io/api/v3/list").unwrap();
+        let base_url = Url::parse("https://readwise.io/api/v3/list").unwrap();
         let mut full_data = Vec::new();
         let mut next_page_cursor: Option<String> = None;
 
@@ -254,6 +1016,14 @@ impl Readwise {
                 if let Some(loc) = &location {
                     query_params.append_pair("location", loc);
                 }
+
+                if let Some(tag) = tag {
+                    query_params.append_pair("tag", tag);
+                }
+
+                if let Some(category) = category {
+                    query_params.append_pair("category", &category.to_string());
+                }
             }
 
             debug!(
@@ -261,19 +1031,15 @@ impl Readwise {
                 url.query().unwrap_or("")
             );
 
-            let response = reqwest::Client::new()
-                .get(url.clone())
-                .header(AUTHORIZATION, format!("Token {}", self.token))
-                .send()
-                .await?;
+            let response = send(
+                self.client
+                    .get(url.clone())
+                    .header(AUTHORIZATION, format!("Token {}", self.token)),
+            )
+            .await?;
 
             if response.status() == StatusCode::TOO_MANY_REQUESTS {
-                let retry_delay = response
-                    .headers()
-                    .get("Retry-After")
-                    .map(|v| v.to_str().unwrap())
-                    .map(|v| v.parse::<u64>().unwrap())
-                    .unwrap_or(5);
+                let retry_delay = retry_delay(&response);
 
                 debug!("Rate limited, retrying in {} seconds", retry_delay);
 
@@ -294,7 +1060,15 @@ impl Readwise {
                 response_json.next_page_cursor
             );
 
-            full_data.extend(response_json.results);
+            // The list endpoint can't filter by an upper bound, so documents after it are dropped
+            full_data.extend(
+                self.parse_records::<Document>("document", response_json.results)?
+                    .into_iter()
+                    .filter(|d| match (self.until, parse_timestamp(&d.updated_at)) {
+                        (Some(until), Some(updated_at)) => updated_at < until,
+                        _ => true,
+                    }),
+            );
             next_page_cursor = response_json.next_page_cursor;
 
             if next_page_cursor.is_none() {
@@ -318,39 +1092,97 @@ struct CollectionResponse<T> {
     results: Vec<T>,
 }
 
+/// Set the highlight count and last highlighted time of books from their highlights, with the
+/// last highlight also standing in for when the book was updated, which the export endpoint
+/// doesn't give.
+fn tally_highlights(books: &mut [Book], highlights: &[Highlight]) {
+    let mut tallies: HashMap<i32, (i32, Option<&str>)> = HashMap::new();
+    for highlight in highlights {
+        let (count, last) = tallies.entry(highlight.book_id).or_default();
+        *count += 1;
+        *last = (*last).max(highlight.highlighted_at.as_deref());
+    }
+
+    for book in books {
+        let (count, last) = tallies.get(&book.id).copied().unwrap_or_default();
+        book.num_highlights = count;
+        book.last_highlight_at = last.map(str::to_string);
+        book.updated = book.last_highlight_at.clone();
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct DocumentListResponse {
-    results: Vec<Document>,
+struct ExportResponse {
+    results: Vec<Value>,
     next_page_cursor: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Document {
-    id: String,
-    url: String,
-    title: Option<String>,
+/// A book as given by the export endpoint, with its highlights.
+#[derive(Debug, Deserialize)]
+struct ExportedBook {
+    user_book_id: i32,
+    title: String,
     author: Option<String>,
+    category: String,
+    #[serde(default)]
+    readable_title: Option<String>,
+    #[serde(default)]
     source: Option<String>,
-    category: Option<String>,
-    location: Option<String>,
-    tags: Option<Value>,
-    site_name: Option<String>,
-    word_count: Option<i64>,
-    created_at: String,
-    updated_at: String,
-    published_date: Option<PublishedDate>,
-    summary: Option<String>,
-    image_url: Option<String>,
-    content: Option<String>,
+    #[serde(default)]
+    cover_image_url: Option<String>,
+    #[serde(default)]
+    unique_url: Option<String>,
+    #[serde(default)]
+    book_tags: Vec<Tag>,
+    #[serde(default)]
+    document_note: Option<String>,
+    #[serde(default)]
+    readwise_url: Option<String>,
+    #[serde(default)]
     source_url: Option<String>,
-    notes: Option<String>,
-    parent_id: Option<String>,
-    reading_progress: f64,
-    first_opened_at: Option<String>,
-    last_opened_at: Option<String>,
-    saved_at: String,
-    last_moved_at: String,
+    #[serde(default)]
+    asin: Option<String>,
+    #[serde(default)]
+    highlights: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentListResponse {
+    results: Vec<Value>,
+    next_page_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub id: String,
+    pub url: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub source: Option<String>,
+    pub category: Option<String>,
+    pub location: Option<String>,
+    pub tags: Option<Value>,
+    pub site_name: Option<String>,
+    pub word_count: Option<i64>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub published_date: Option<PublishedDate>,
+    pub summary: Option<String>,
+    pub image_url: Option<String>,
+    pub content: Option<String>,
+    /// The stored HTML of the document, only present when fetched with `--with-html`.
+    #[serde(default)]
+    pub html_content: Option<String>,
+    pub source_url: Option<String>,
+    pub notes: Option<String>,
+    pub parent_id: Option<String>,
+    pub reading_progress: f64,
+    pub first_opened_at: Option<String>,
+    pub last_opened_at: Option<String>,
+    pub saved_at: String,
+    pub last_moved_at: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -359,3 +1191,206 @@ pub enum PublishedDate {
     Integer(i64),
     String(String),
 }
+
+/// An error parsing a record, naming the field it came from if a field was missing or couldn't be
+/// parsed, rather than the record as a whole.
+#[derive(Debug)]
+struct FieldError {
+    field: Option<String>,
+    message: String,
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+impl de::Error for FieldError {
+    fn custom<T: Display>(msg: T) -> Self {
+        FieldError {
+            field: None,
+            message: msg.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        FieldError {
+            field: Some(field.to_string()),
+            message: format!("missing field `{}`", field),
+        }
+    }
+}
+
+/// The fields of a record, deserialized one by one so an error is attributed to its field.
+struct Fields<'a>(&'a Map<String, Value>);
+
+impl<'de> Deserializer<'de> for Fields<'de> {
+    type Error = FieldError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FieldError> {
+        visitor.visit_map(FieldValues {
+            fields: self.0.iter(),
+            current: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+struct FieldValues<'a> {
+    fields: serde_json::map::Iter<'a>,
+    current: Option<(&'a String, &'a Value)>,
+}
+
+impl<'de> MapAccess<'de> for FieldValues<'de> {
+    type Error = FieldError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, FieldError> {
+        let Some((key, value)) = self.fields.next() else {
+            return Ok(None);
+        };
+
+        self.current = Some((key, value));
+        seed.deserialize(key.as_str().into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, FieldError> {
+        let Some((key, value)) = self.current.take() else {
+            return Err(de::Error::custom(
+                "a field's value was asked for before its name",
+            ));
+        };
+
+        seed.deserialize(value).map_err(|err| FieldError {
+            field: Some(key.clone()),
+            message: err.to_string(),
+        })
+    }
+}
+
+/// Parse a record, replacing each field which is missing or fails to parse with the first of
+/// `null` or an empty value of each JSON type that it accepts, and give the names of the fields
+/// replaced. A record's `id` is never replaced, as without it the record can't be told apart.
+fn parse_leniently<T: DeserializeOwned>(record: Value) -> serde_json::Result<(T, Vec<String>)> {
+    let mut object = match record {
+        Value::Object(object) => object,
+        other => return serde_json::from_value(other).map(|record| (record, vec![])),
+    };
+    let fallbacks = [
+        Value::Null,
+        json!(""),
+        json!(0),
+        json!(false),
+        json!([]),
+        json!({}),
+    ];
+
+    let mut defaulted = vec![];
+    loop {
+        let field = match T::deserialize(Fields(&object)) {
+            Ok(record) => return Ok((record, defaulted)),
+            Err(FieldError {
+                field: Some(field), ..
+            }) if field != "id" && !defaulted.contains(&field) => field,
+            Err(_) => break,
+        };
+
+        // A fallback fixes the field if the record then parses, or fails on another field
+        let fallback = fallbacks.iter().find(|fallback| {
+            let mut trial = object.clone();
+            trial.insert(field.clone(), (*fallback).clone());
+
+            match T::deserialize(Fields(&trial)) {
+                Ok(_) => true,
+                Err(err) => err.field.as_ref() != Some(&field),
+            }
+        });
+
+        match fallback {
+            Some(fallback) => {
+                object.insert(field.clone(), fallback.clone());
+                defaulted.push(field);
+            }
+            None => break,
+        }
+    }
+
+    serde_json::from_value(Value::Object(object)).map(|record| (record, defaulted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_leniently;
+    use crate::readwise::Highlight;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    #[test]
+    fn malformed_fields_are_defaulted_rather_than_the_record_dropped() {
+        let record = json!({
+            "id": 10,
+            "text": "Kept",
+            "note": null,
+            "location": "page 4",
+            "location_type": "page",
+            "highlighted_at": 20240101,
+            "url": null,
+            "color": "yellow",
+            "updated": "2024-01-01T00:00:00Z",
+            "book_id": 1,
+            "tags": [],
+        });
+
+        let (highlight, defaulted) = parse_leniently::<Highlight>(record).unwrap();
+        assert_eq!(highlight.id, 10);
+        assert_eq!(highlight.text, "Kept");
+        assert_eq!(highlight.note, "");
+        assert_eq!(highlight.location, 0);
+        assert_eq!(highlight.highlighted_at, None);
+        assert_eq!(highlight.color, "yellow");
+        assert_eq!(
+            defaulted.iter().map(String::as_str).collect::<HashSet<_>>(),
+            HashSet::from(["highlighted_at", "location", "note"])
+        );
+    }
+
+    #[test]
+    fn missing_fields_are_defaulted_too() {
+        let record = json!({
+            "id": 10,
+            "location": 4,
+            "location_type": "page",
+            "color": "yellow",
+            "updated": "2024-01-01T00:00:00Z",
+            "book_id": 1,
+            "tags": [],
+        });
+
+        let (highlight, defaulted) = parse_leniently::<Highlight>(record).unwrap();
+        assert_eq!(highlight.text, "");
+        assert_eq!(highlight.note, "");
+        assert_eq!(
+            defaulted.iter().map(String::as_str).collect::<HashSet<_>>(),
+            HashSet::from(["text", "note"])
+        );
+    }
+
+    #[test]
+    fn records_missing_their_id_are_still_rejected() {
+        let record = json!({ "text": "No id" });
+        assert!(parse_leniently::<Highlight>(record).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::readwise::parse_timestamp;
+use crate::Library;
+use anyhow::anyhow;
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::str::FromStr;
+use tracing::info;
+
+/// How long records in a category (or Reader location) are kept in the library, e.g. `feed=30d`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RetentionRule {
+    pub category: String,
+    pub max_age: Duration,
+}
+
+impl FromStr for RetentionRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (category, age) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected <category>=<age>, e.g. feed=30d, got '{}'", s))?;
+
+        let split = age
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("Missing unit in retention age '{}'", age))?;
+        let (count, unit) = age.split_at(split);
+        let count = count
+            .parse::<i64>()
+            .map_err(|_| anyhow!("Invalid retention age '{}'", age))?;
+
+        let max_age = match unit {
+            "h" => Duration::hours(count),
+            "d" => Duration::days(count),
+            "w" => Duration::weeks(count),
+            "m" => Duration::days(count * 30),
+            "y" => Duration::days(count * 365),
+            _ => {
+                return Err(anyhow!(
+                    "Unknown unit '{}' in retention age, use h/d/w/m/y",
+                    unit
+                ))
+            }
+        };
+
+        Ok(RetentionRule {
+            category: category.trim().to_lowercase(),
+            max_age,
+        })
+    }
+}
+
+impl TryFrom<String> for RetentionRule {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Remove documents and books older than the retention rule for their category. Documents match
+/// a rule by either category or Reader location, and are aged by when they were saved; books are
+/// aged by their most recent highlight. Records in categories without a rule are kept forever.
+pub fn apply_retention(library: &mut Library, rules: &[RetentionRule]) {
+    if rules.is_empty() {
+        return;
+    }
+
+    let now = Utc::now();
+    let expired = |category: Option<&str>, timestamp: Option<&str>| {
+        let Some(age) = timestamp.and_then(parse_timestamp).map(|t| now - t) else {
+            return false;
+        };
+
+        rules.iter().any(|rule| {
+            category.is_some_and(|c| c.eq_ignore_ascii_case(&rule.category)) && age > rule.max_age
+        })
+    };
+
+    let expired_documents = library
+        .documents
+        .iter()
+        .filter(|d| {
+            expired(d.category.as_deref(), Some(&d.saved_at))
+                || expired(d.location.as_deref(), Some(&d.saved_at))
+        })
+        .map(|d| d.id.clone())
+        .collect::<HashSet<_>>();
+
+    let expired_books = library
+        .books
+        .iter()
+        .filter(|b| {
+            expired(
+                Some(&b.category),
+                b.last_highlight_at.as_deref().or(b.updated.as_deref()),
+            )
+        })
+        .map(|b| b.id)
+        .collect::<HashSet<_>>();
+
+    let before = (library.documents.len(), library.books.len());
+
+    // Children (Reader highlights and notes) go with their parent
+    library.documents.retain(|d| {
+        !expired_documents.contains(&d.id)
+            && !d
+                .parent_id
+                .as_ref()
+                .is_some_and(|p| expired_documents.contains(p))
+    });
+    library.books.retain(|b| !expired_books.contains(&b.id));
+    library
+        .highlights
+        .retain(|h| !expired_books.contains(&h.book_id));
+
+    info!(
+        "Retention removed {} documents and {} books",
+        before.0 - library.documents.len(),
+        before.1 - library.books.len()
+    );
+}
@@ -1,11 +1,32 @@
 use crate::readwise::{Book, Highlight};
+use crate::stats::book_with_stats;
 use rhai::serde::to_dynamic;
 use rhai::{Dynamic, Engine, Scope, AST};
 use serde_json::json;
 use std::cell::RefCell;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use tracing::debug;
 
+/// What a metadata script produced for a book.
+pub struct ScriptOutput {
+    /// The note's frontmatter
+    pub metadata: serde_yml::Value,
+
+    /// Markdown appended to the note after its highlights, e.g. a generated summary
+    pub append: Option<String>,
+}
+
+impl From<serde_yml::Value> for ScriptOutput {
+    fn from(metadata: serde_yml::Value) -> Self {
+        ScriptOutput {
+            metadata,
+            append: None,
+        }
+    }
+}
+
 pub enum ScriptType {
     Rhai {
         metadata_script: AST,
@@ -15,6 +36,13 @@ pub enum ScriptType {
     Javascript {
         script: RefCell<js_sandbox::Script>,
     },
+
+    /// An external program, given the book and its highlights as JSON on stdin, which prints the
+    /// frontmatter as JSON on stdout. It may instead print a control object,
+    /// `{"frontmatter": {...}, "append": "..."}`, to also add markdown to the note.
+    Command {
+        program: PathBuf,
+    },
 }
 
 impl ScriptType {
@@ -41,11 +69,14 @@ impl ScriptType {
         }
     }
 
-    pub fn execute(
-        &self,
-        book: &Book,
-        highlights: &[&Highlight],
-    ) -> anyhow::Result<serde_yml::Value> {
+    pub fn command(program: &Path) -> Self {
+        debug!("Using {:?} as the metadata command", program);
+        ScriptType::Command {
+            program: program.to_path_buf(),
+        }
+    }
+
+    pub fn execute(&self, book: &Book, highlights: &[&Highlight]) -> anyhow::Result<ScriptOutput> {
         match self {
             ScriptType::Rhai {
                 metadata_script,
@@ -54,7 +85,7 @@ impl ScriptType {
                 let mut scope = {
                     let mut scope = Scope::new();
 
-                    let book: Dynamic = to_dynamic(book)?;
+                    let book: Dynamic = to_dynamic(book_with_stats(book, highlights)?)?;
                     let highlights = to_dynamic(highlights)?;
 
                     scope.push_dynamic("book", book);
@@ -66,20 +97,98 @@ impl ScriptType {
                 let dynamic: Dynamic =
                     engine.eval_ast_with_scope::<Dynamic>(&mut scope, metadata_script)?;
 
-                Ok(serde_yml::to_value(&dynamic)?)
+                Ok(serde_yml::to_value(&dynamic)?.into())
             }
 
             ScriptType::Javascript { script } => {
                 let a: serde_json::Value = script.borrow_mut().call(
                     "metadata",
                     &json!({
-                        "book": book,
+                        "book": book_with_stats(book, highlights)?,
                         "highlights": highlights,
                     }),
                 )?;
 
-                Ok(serde_yml::to_value(&a)?)
+                Ok(serde_yml::to_value(&a)?.into())
             }
+
+            ScriptType::Command { program } => run_command(program, book, highlights),
         }
     }
 }
+
+fn run_command(
+    program: &Path,
+    book: &Book,
+    highlights: &[&Highlight],
+) -> anyhow::Result<ScriptOutput> {
+    let input = serde_json::to_vec(&json!({
+        "book": book_with_stats(book, highlights)?,
+        "highlights": highlights,
+    }))?;
+
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run metadata command {:?}: {}", program, e))?;
+
+    // Written from another thread so a command printing before it has read all of its input
+    // cannot deadlock against us
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()?;
+    // A command which ignores its input may exit before reading it, closing the pipe
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Metadata command {:?} failed for book {} ({}): {}",
+            program,
+            book.id,
+            book.title,
+            output.status
+        ));
+    }
+
+    let stdout = String::from_utf8(output.stdout)?;
+    if stdout.trim().is_empty() {
+        return Ok(serde_yml::to_value(book)?.into());
+    }
+
+    let value: serde_json::Value = serde_json::from_str(&stdout).map_err(|e| {
+        anyhow::anyhow!(
+            "Metadata command {:?} printed invalid JSON for book {} ({}): {}",
+            program,
+            book.id,
+            book.title,
+            e
+        )
+    })?;
+
+    match value {
+        serde_json::Value::Object(mut control) if control.contains_key("frontmatter") => {
+            let append = match control.remove("append") {
+                None | Some(serde_json::Value::Null) => None,
+                Some(serde_json::Value::String(append)) => Some(append),
+                Some(other) => {
+                    return Err(anyhow::anyhow!(
+                        "Metadata command {:?} returned a non-string append for book {}: {}",
+                        program,
+                        book.id,
+                        other
+                    ))
+                }
+            };
+
+            Ok(ScriptOutput {
+                metadata: serde_yml::to_value(&control["frontmatter"])?,
+                append,
+            })
+        }
+
+        value => Ok(serde_yml::to_value(&value)?.into()),
+    }
+}
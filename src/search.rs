@@ -0,0 +1,112 @@
+use crate::Exporter;
+use chrono::Local;
+use itertools::Itertools;
+use std::path::Path;
+use tera::Context;
+use tracing::info;
+
+const DEFAULT_SEARCH_TEMPLATE: &str = r#"# Search: {{ query }}
+
+{{ highlight_count }} highlights from {{ book_count }} books, searched {{ searched_at }}.
+{% for highlight in highlights %}
+> {{ highlight.text | replace(from="
+", to="
+> ") }}
+{% if highlight.note %}
+{{ highlight.note }}
+{% endif %}
+— [[{{ highlight.link }}#^{{ highlight.id }}|{{ highlight.book_title }}]]
+{% endfor %}"#;
+
+impl Exporter {
+    /// The highlights of exported books whose text or note contain `query`, ignoring case, by book
+    /// title and then location. Each is given with its book's title and author, and a link to the
+    /// book's note.
+    fn search_highlights(&self, query: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+        let query = query.to_lowercase();
+        let mut matches = vec![];
+
+        let books = self
+            .library
+            .books
+            .iter()
+            .filter(|book| self.skip_reason(book).is_none())
+            .cloned()
+            .map(|book| self.as_rendered(book))
+            .sorted_by(|a, b| a.title.cmp(&b.title));
+
+        for book in books {
+            let link = self.wikilink_target(&self.note_path(&book)?);
+            let highlights = self
+                .highlights_for(&book)
+                .into_iter()
+                .filter(|h| {
+                    h.text.to_lowercase().contains(&query) || h.note.to_lowercase().contains(&query)
+                })
+                .sorted_by_key(|h| h.location);
+
+            for highlight in highlights {
+                let mut value = self.highlight_value(&book, highlight)?;
+                value["book_title"] = serde_json::Value::from(book.title.as_str());
+                value["book_author"] = serde_json::to_value(&book.author)?;
+                value["link"] = serde_json::Value::from(link.as_str());
+                matches.push(value);
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Search the highlights of exported books for `query`, printing the matches, or rendering
+    /// them into a note at `to_note` within the vault with links back to the notes they are from.
+    pub(crate) fn search(
+        &mut self,
+        query: &str,
+        to_note: Option<&Path>,
+        template: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        let highlights = self.search_highlights(query)?;
+
+        let Some(to_note) = to_note else {
+            for highlight in &highlights {
+                println!(
+                    "{} ({}): {}",
+                    highlight["book_title"].as_str().unwrap_or_default(),
+                    highlight["id"],
+                    highlight["text"].as_str().unwrap_or_default().trim()
+                );
+            }
+            return Ok(());
+        };
+
+        match template {
+            Some(path) => self.templates.add_template_file(path, Some("search"))?,
+            None => self
+                .templates
+                .add_raw_template("search", DEFAULT_SEARCH_TEMPLATE)?,
+        }
+
+        let mut context = Context::new();
+        context.insert("query", query);
+        context.insert("highlights", &highlights);
+        context.insert("highlight_count", &highlights.len());
+        context.insert(
+            "book_count",
+            &highlights.iter().map(|h| &h["link"]).unique().count(),
+        );
+        context.insert(
+            "searched_at",
+            &Local::now().format("%Y-%m-%d %H:%M").to_string(),
+        );
+
+        let path = self.vault_root.join(to_note);
+        info!(
+            "Writing {} highlights matching '{}' to {:?}",
+            highlights.len(),
+            query,
+            path
+        );
+        let contents = self.templates.render("search", &context)?;
+        self.transaction.stage_file(&path, contents)
+    }
+}
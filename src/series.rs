@@ -0,0 +1,221 @@
+use crate::readwise::Book;
+use crate::Exporter;
+use itertools::Itertools;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use tera::Context;
+use tracing::info;
+
+/// Books tagged `series:<name>` in Readwise belong to that series.
+const SERIES_TAG_PREFIX: &str = "series:";
+
+const DEFAULT_SERIES_TEMPLATE: &str = r#"# {{ series }}
+
+- Books: {{ book_count }}
+- Highlights: {{ highlight_count }}{% if last_highlight_at %}
+- Last highlighted: {{ last_highlight_at | truncate(length=10, end="") }}{% endif %}{% if authors %}
+- Authors: {{ authors | join(sep=", ") }}{% endif %}
+{% for book in books %}
+- {% if book.volume %}{{ book.volume }}. {% endif %}{% if book.link %}[[{{ book.link }}|{{ book.title }}]]{% else %}{{ book.title }}{% endif %} ({{ book.highlights }} highlights){% endfor %}
+"#;
+
+/// A regex matched against book titles to find their series, from its `series` group (or the whole
+/// match), and the volume or issue number from an optional `volume` group, e.g.
+/// `^(?P<series>.+?),? (?:Vol\.|Volume|Issue) (?P<volume>\d+)`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SeriesPattern(Regex);
+
+impl FromStr for SeriesPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SeriesPattern(Regex::new(s)?))
+    }
+}
+
+impl TryFrom<String> for SeriesPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// The series a book belongs to, and its place in the series if known.
+pub struct BookSeries {
+    pub name: String,
+    pub volume: Option<u32>,
+}
+
+impl Exporter {
+    /// The series a book belongs to, from a `series:` tag, or else the first series pattern its
+    /// title matches.
+    pub(crate) fn book_series(&self, book: &Book) -> Option<BookSeries> {
+        let tagged = book
+            .tags
+            .iter()
+            .filter_map(|tag| tag.name.strip_prefix(SERIES_TAG_PREFIX))
+            .map(str::trim)
+            .find(|name| !name.is_empty());
+
+        if let Some(name) = tagged {
+            let volume = self
+                .series_patterns
+                .iter()
+                .filter_map(|SeriesPattern(pattern)| pattern.captures(&book.title))
+                .find_map(|captures| captures.name("volume")?.as_str().parse().ok());
+
+            return Some(BookSeries {
+                name: name.to_string(),
+                volume,
+            });
+        }
+
+        self.series_patterns
+            .iter()
+            .find_map(|SeriesPattern(pattern)| {
+                let captures = pattern.captures(&book.title)?;
+                let name = captures
+                    .name("series")
+                    .unwrap_or_else(|| captures.get(0).unwrap())
+                    .as_str()
+                    .trim();
+
+                Some(BookSeries {
+                    name: name.to_string(),
+                    volume: captures
+                        .name("volume")
+                        .and_then(|v| v.as_str().parse().ok()),
+                })
+            })
+            .filter(|series| !series.name.is_empty())
+    }
+
+    /// Write a note for every series with an exported book into `folder` within the base folder,
+    /// linking to its books in order with their highlight counts rolled up. Regenerated on each
+    /// export.
+    pub(crate) fn write_series_notes(&mut self, folder: &str) -> anyhow::Result<()> {
+        if !self.templates.get_template_names().any(|n| n == "series") {
+            self.templates
+                .add_raw_template("series", DEFAULT_SERIES_TEMPLATE)?;
+        }
+
+        let root = self.export_root.join(folder);
+
+        let mut series: BTreeMap<String, Vec<(&Book, Option<u32>)>> = BTreeMap::new();
+        for book in &self.library.books {
+            if !self.exported_paths.contains_key(&book.id) {
+                continue;
+            }
+
+            if let Some(BookSeries { name, volume }) = self.book_series(book) {
+                series.entry(name).or_default().push((book, volume));
+            }
+        }
+
+        info!("Writing {} series notes into {:?}", series.len(), root);
+
+        let mut notes = vec![];
+        for (name, books) in series {
+            let books = books
+                .into_iter()
+                .sorted_by(|(a, x), (b, y)| {
+                    // Numbered books first, in order
+                    (x.is_none(), x, &a.title).cmp(&(y.is_none(), y, &b.title))
+                })
+                .map(|(book, volume)| {
+                    let highlights = self.highlights_for(book);
+                    json!({
+                        "id": book.id,
+                        "title": book.title,
+                        "author": book.author,
+                        "volume": volume,
+                        "highlights": highlights.len(),
+                        "last_highlight_at": highlights
+                            .iter()
+                            .filter_map(|h| h.highlighted_at.as_deref())
+                            .max(),
+                        "link": self
+                            .exported_paths
+                            .get(&book.id)
+                            .map(|path| self.wikilink_target(path)),
+                    })
+                })
+                .collect_vec();
+
+            let highlight_count = books
+                .iter()
+                .filter_map(|b| b["highlights"].as_u64())
+                .sum::<u64>();
+            let last_highlight_at = books
+                .iter()
+                .filter_map(|b| b["last_highlight_at"].as_str())
+                .max();
+            let authors = books
+                .iter()
+                .filter_map(|b| b["author"].as_str())
+                .unique()
+                .collect_vec();
+
+            let mut context = Context::new();
+            context.insert("series", &name);
+            context.insert("books", &books);
+            context.insert("book_count", &books.len());
+            context.insert("highlight_count", &highlight_count);
+            context.insert("last_highlight_at", &last_highlight_at);
+            context.insert("authors", &authors);
+
+            let contents = self.templates.render("series", &context)?;
+            let title = self.sanitize_title(&name, "series");
+            notes.push((name, root.join(title).with_extension("md"), contents));
+        }
+
+        for (name, path, contents) in notes {
+            if self.confine_record(&path, "series", &name, None) {
+                self.transaction.stage_file(&path, contents)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::readwise::Tag;
+    use crate::testing::{book, exporter, library, vault};
+
+    #[test]
+    fn series_come_from_tags_before_titles() {
+        let vault = vault("book-series");
+        let exporter = exporter(
+            &vault,
+            library(vec![], vec![]),
+            &[
+                "--series-pattern",
+                r"^(?P<series>.+?),? (?:Vol\.|Volume) (?P<volume>\d+)",
+            ],
+        );
+
+        let series = exporter.book_series(&book(1, "Saga, Volume 3")).unwrap();
+        assert_eq!((series.name.as_str(), series.volume), ("Saga", Some(3)));
+
+        let mut tagged = book(2, "The Hobbit, Vol. 1");
+        tagged.tags.push(Tag {
+            id: 1,
+            name: "series:Middle-earth".to_string(),
+        });
+        let series = exporter.book_series(&tagged).unwrap();
+        assert_eq!(
+            (series.name.as_str(), series.volume),
+            ("Middle-earth", Some(1))
+        );
+
+        assert!(exporter.book_series(&book(3, "Dune")).is_none());
+        std::fs::remove_dir_all(&vault).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+use crate::readwise::{Book, Highlight};
+use serde::Serialize;
+
+/// Average adult reading speed, used to estimate reading times.
+pub const WORDS_PER_MINUTE: f64 = 238.0;
+
+/// Aggregate figures for a book's exported highlights, available as `book.stats` to templates and
+/// metadata scripts.
+#[derive(Debug, Clone, Serialize)]
+pub struct BookStats {
+    pub highlights: usize,
+    pub favorites: usize,
+    /// Words across the text of the highlights
+    pub highlighted_words: usize,
+    /// Words across the notes made on the highlights
+    pub note_words: usize,
+    /// Estimated minutes to read the highlights, rounded up
+    pub reading_minutes: u64,
+}
+
+pub fn book_stats(highlights: &[&Highlight]) -> BookStats {
+    let highlighted_words = highlights
+        .iter()
+        .map(|h| h.text.split_whitespace().count())
+        .sum::<usize>();
+
+    BookStats {
+        highlights: highlights.len(),
+        favorites: highlights.iter().filter(|h| h.is_favorite).count(),
+        highlighted_words,
+        note_words: highlights
+            .iter()
+            .map(|h| h.note.split_whitespace().count())
+            .sum(),
+        reading_minutes: (highlighted_words as f64 / WORDS_PER_MINUTE).ceil() as u64,
+    }
+}
+
+/// A book as given to metadata scripts, with the stats of its highlights.
+pub fn book_with_stats(
+    book: &Book,
+    highlights: &[&Highlight],
+) -> serde_json::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(book)?;
+    value["stats"] = serde_json::to_value(book_stats(highlights))?;
+    Ok(value)
+}
@@ -0,0 +1,218 @@
+use crate::readwise::{parse_timestamp, Document};
+use crate::Exporter;
+use chrono::{DateTime, Datelike, Utc};
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use tera::Context;
+use tracing::info;
+
+const DEFAULT_SUMMARY_TEMPLATE: &str = r#"# Reading in {{ period }}
+
+- Highlights made: {{ highlight_count }}
+- Documents finished: {{ finished | length }}
+
+{% if finished %}## Finished
+{% for document in finished %}
+- [{{ document.title }}]({{ document.url }}){% if document.author %} by {{ document.author }}{% endif %}{% if document.word_count %} ({{ document.word_count }} words){% endif %}{% endfor %}
+
+{% endif %}{% if books %}## Highlighted
+{% for book in books %}
+- {% if book.link %}[[{{ book.link }}|{{ book.title }}]]{% else %}{{ book.title }}{% endif %}: {{ book.highlights }}{% endfor %}
+
+{% endif %}{% if top_tags %}## Top tags
+{% for tag in top_tags %}
+- #{{ tag.name | replace(from=" ", to="-") }} ({{ tag.count }}){% endfor %}
+
+{% endif %}{% if longest_reads %}## Longest reads
+{% for document in longest_reads %}
+- [{{ document.title }}]({{ document.url }}): {{ document.word_count }} words{% endfor %}
+{% endif %}"#;
+
+/// The length of time covered by a summary note.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum SummaryPeriod {
+    /// A note per month, named like `2024-03`
+    Month,
+
+    /// A note per year, named like `2024`
+    Year,
+}
+
+impl SummaryPeriod {
+    fn key(&self, timestamp: DateTime<Utc>) -> String {
+        match self {
+            SummaryPeriod::Month => format!("{}-{:02}", timestamp.year(), timestamp.month()),
+            SummaryPeriod::Year => timestamp.year().to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PeriodActivity<'a> {
+    highlight_count: usize,
+    books: HashMap<i32, usize>,
+    tags: HashMap<String, usize>,
+    finished: Vec<&'a Document>,
+}
+
+#[derive(Serialize)]
+struct FinishedDocument<'a> {
+    title: &'a str,
+    author: Option<&'a str>,
+    url: String,
+    word_count: Option<i64>,
+}
+
+impl<'a> From<&'a Document> for FinishedDocument<'a> {
+    fn from(document: &'a Document) -> Self {
+        FinishedDocument {
+            title: document.title.as_deref().unwrap_or(&document.url),
+            author: document.author.as_deref(),
+            url: format!("https://read.readwise.io/read/{}", document.id),
+            word_count: document.word_count,
+        }
+    }
+}
+
+/// When a finished document was finished, taking the last time it was opened, or when it was
+/// last moved (to the archive) if it was never opened in Reader.
+fn finished_at(document: &Document) -> Option<DateTime<Utc>> {
+    if document.parent_id.is_some() || document.reading_progress < 1.0 {
+        return None;
+    }
+
+    document
+        .last_opened_at
+        .as_deref()
+        .and_then(parse_timestamp)
+        .or_else(|| parse_timestamp(&document.last_moved_at))
+}
+
+impl Exporter {
+    /// Write a summary note for every month or year with reading activity into `folder` within
+    /// the base folder: the highlights made and on which books, their top tags, and the Reader
+    /// documents finished and longest among them. Regenerated on each export.
+    pub(crate) fn write_summaries(
+        &mut self,
+        folder: &str,
+        periods: &[SummaryPeriod],
+    ) -> anyhow::Result<()> {
+        if !self.templates.get_template_names().any(|n| n == "summary") {
+            self.templates
+                .add_raw_template("summary", DEFAULT_SUMMARY_TEMPLATE)?;
+        }
+
+        let root = self.export_root.join(folder);
+
+        for period in periods {
+            let mut activity: BTreeMap<String, PeriodActivity> = BTreeMap::new();
+
+            for highlight in self
+                .library
+                .highlights
+                .iter()
+                .filter(|h| self.exports_highlight(h))
+            {
+                let Some(at) = highlight
+                    .highlighted_at
+                    .as_deref()
+                    .and_then(parse_timestamp)
+                else {
+                    continue;
+                };
+
+                let entry = activity.entry(period.key(at)).or_default();
+                entry.highlight_count += 1;
+                *entry
+                    .books
+                    .entry(self.library.canonical_book_id(highlight.book_id))
+                    .or_default() += 1;
+                for tag in &highlight.tags {
+                    *entry.tags.entry(tag.name.clone()).or_default() += 1;
+                }
+            }
+
+            for document in &self.library.documents {
+                if let Some(at) = finished_at(document) {
+                    activity
+                        .entry(period.key(at))
+                        .or_default()
+                        .finished
+                        .push(document);
+                }
+            }
+
+            info!(
+                "Writing {} {:?} summaries into {:?}",
+                activity.len(),
+                period,
+                root
+            );
+
+            for (key, activity) in activity {
+                let books = activity
+                    .books
+                    .iter()
+                    .filter_map(|(id, count)| {
+                        let book = self.library.books.iter().find(|b| b.id == *id)?;
+                        Some(json!({
+                            "title": book.title,
+                            "highlights": count,
+                            "link": self
+                                .exported_paths
+                                .get(id)
+                                .map(|path| self.wikilink_target(path)),
+                        }))
+                    })
+                    .sorted_by_key(|b| {
+                        (
+                            std::cmp::Reverse(b["highlights"].as_u64()),
+                            b["title"].as_str().map(str::to_string),
+                        )
+                    })
+                    .collect_vec();
+
+                let top_tags = activity
+                    .tags
+                    .iter()
+                    .sorted_by(|(a, x), (b, y)| y.cmp(x).then(a.cmp(b)))
+                    .take(10)
+                    .map(|(name, count)| json!({ "name": name, "count": count }))
+                    .collect_vec();
+
+                let longest_reads = activity
+                    .finished
+                    .iter()
+                    .filter(|d| d.word_count.is_some())
+                    .sorted_by_key(|d| std::cmp::Reverse(d.word_count))
+                    .take(5)
+                    .map(|d| FinishedDocument::from(*d))
+                    .collect_vec();
+
+                let finished = activity
+                    .finished
+                    .iter()
+                    .map(|d| FinishedDocument::from(*d))
+                    .collect_vec();
+
+                let mut context = Context::new();
+                context.insert("period", &key);
+                context.insert("kind", &format!("{:?}", period).to_lowercase());
+                context.insert("highlight_count", &activity.highlight_count);
+                context.insert("books", &books);
+                context.insert("top_tags", &top_tags);
+                context.insert("finished", &finished);
+                context.insert("longest_reads", &longest_reads);
+
+                let contents = self.templates.render("summary", &context)?;
+                self.transaction
+                    .stage_file(&root.join(&key).with_extension("md"), contents)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use crate::readwise::Book;
+use crate::tags::obsidian_tag;
+use crate::Exporter;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// The tag added to the frontmatter of supplemental books' notes.
+const SUPPLEMENTAL_TAG: &str = "supplemental";
+
+/// What happens to the supplemental books Readwise adds of popular highlights.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum SupplementalsPolicy {
+    /// Export them like any other book, tagged as supplemental
+    Include,
+
+    /// Leave them out of the export
+    Exclude,
+
+    /// Export them to their own folder, whatever their category, tagged as supplemental
+    SeparateFolder,
+}
+
+impl Exporter {
+    /// Whether a book is one of Readwise's supplementals, judged by its category and source before
+    /// any `--category-map` relabels it.
+    pub(crate) fn is_supplemental(&self, book: &Book) -> bool {
+        let book = self
+            .library
+            .books
+            .iter()
+            .find(|b| b.id == book.id)
+            .unwrap_or(book);
+
+        let supplemental = |s: &str| s.to_lowercase().starts_with("supplemental");
+        supplemental(&book.category) || book.source.as_deref().is_some_and(supplemental)
+    }
+
+    /// Add the supplemental tag to the frontmatter of an included supplemental book's note.
+    pub(crate) fn tag_supplemental(&self, book: &Book, metadata: &mut serde_yml::Mapping) {
+        if !self.is_supplemental(book) {
+            return;
+        }
+
+        let Some(tag) = obsidian_tag(&self.tag_prefix, SUPPLEMENTAL_TAG) else {
+            return;
+        };
+
+        let tags = metadata
+            .entry(serde_yml::Value::from("tags"))
+            .or_insert_with(|| serde_yml::Value::Sequence(vec![]));
+
+        if let Some(tags) = tags.as_sequence_mut() {
+            let tag = serde_yml::Value::from(tag);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::Exporter;
+use chrono::Local;
+use tracing::info;
+
+const HEADER: &str = "| Run | New notes | Updated notes | Removed notes | Stranded | Failed |
+| --- | --- | --- | --- | --- | --- |";
+
+impl Exporter {
+    /// Append a row for this run to the sync log note at `file` within the base folder, with the
+    /// notes it creates, updates, and removes, and the stranded notes and failed books it leaves.
+    /// Must be called once every other change of the run has been staged.
+    pub(crate) fn append_sync_log(&mut self, file: &str, stranded: usize) -> anyhow::Result<()> {
+        let path = self.export_root.join(file).with_extension("md");
+        let preview = self.transaction.preview()?;
+
+        let row = format!(
+            "| {} | {} | {} | {} | {} | {} |",
+            Local::now().format("%Y-%m-%d %H:%M"),
+            preview.created.len(),
+            preview.updated.len(),
+            preview.trashed.len(),
+            stranded,
+            self.failures.len(),
+        );
+
+        info!("Adding this run to the sync log {:?}", path);
+
+        let existing = self.transaction.read_existing(&path)?.unwrap_or_default();
+        let contents = match existing.trim_end() {
+            "" => format!("# Readwise Sync Log\n\n{}\n{}\n", HEADER, row),
+            existing => format!("{}\n{}\n", existing, row),
+        };
+
+        self.transaction.stage_file(&path, contents)
+    }
+}
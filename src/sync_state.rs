@@ -0,0 +1,46 @@
+use crate::{Library, ReadwiseObjectKind};
+use clap::ValueEnum;
+use itertools::Itertools;
+use std::fmt::Write as _;
+use tracing::info;
+
+/// Describe when each kind of record, and the Reader documents with each tag, were last synced.
+pub fn show(library: &Library) -> anyhow::Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "Library last updated: {}",
+        library.updated_at.to_rfc3339()
+    )?;
+
+    for kind in ReadwiseObjectKind::value_variants() {
+        let name = kind.to_possible_value().unwrap();
+        match library.synced_at(*kind) {
+            Some(synced_at) => writeln!(out, "{}: {}", name.get_name(), synced_at.to_rfc3339())?,
+            None => writeln!(out, "{}: never, fetched in full next time", name.get_name())?,
+        }
+    }
+
+    for (tag, synced_at) in library.reader_tag_synced_at.iter().sorted() {
+        writeln!(out, "reader-tag {}: {}", tag, synced_at.to_rfc3339())?;
+    }
+
+    Ok(out)
+}
+
+/// Forget when these kinds, and the Reader documents with these tags, were last synced, so the
+/// next fetch gets them in full. With neither given, every kind and tag is reset.
+pub fn reset(library: &mut Library, kinds: &[ReadwiseObjectKind], reader_tags: &[String]) {
+    let everything = kinds.is_empty() && reader_tags.is_empty();
+
+    for kind in ReadwiseObjectKind::value_variants() {
+        if everything || kinds.contains(kind) {
+            info!("Resetting sync state of {:?}", kind);
+            library.kind_synced_at.insert(*kind, None);
+        }
+    }
+
+    library
+        .reader_tag_synced_at
+        .retain(|tag, _| !everything && !reader_tags.contains(tag));
+}
@@ -0,0 +1,226 @@
+use crate::readwise::{Book, Highlight, Tag};
+use crate::Exporter;
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use tera::Context;
+use tracing::info;
+
+const DEFAULT_TAG_TEMPLATE: &str = r#"# {{ tag }}
+
+- Books: {{ book_count }}
+- Highlights: {{ highlight_count }}
+{% if books %}
+## Books
+{% for book in books %}
+- [[{{ book.link }}|{{ book.title }}]]{% endfor %}
+{% endif %}{% if highlights %}
+## Highlights
+{% for highlight in highlights %}
+> {{ highlight.text | replace(from="
+", to="
+> ") }}
+
+— [[{{ highlight.link }}#^{{ highlight.id }}|{{ highlight.book_title }}]]
+{% endfor %}{% endif %}"#;
+
+const DEFAULT_TAG_INDEX_TEMPLATE: &str = r#"# Tags
+{% for tag in tags %}
+- [[{{ tag.link }}|{{ tag.tag }}]]: {{ tag.book_count }} books, {{ tag.highlight_count }} highlights{% endfor %}
+"#;
+
+/// The file name of the index note written alongside the tag notes.
+const TAG_INDEX_FILE: &str = "Tag Index.md";
+
+/// Where Readwise tags are written in exported notes.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum TagOutput {
+    /// As Obsidian tags in the `tags` frontmatter of book notes, from the book and its highlights
+    Frontmatter,
+
+    /// As `[[Tag Name]]` links, given to templates as `tag_links` for the book and
+    /// `highlight.tag_links` for each highlight
+    Links,
+}
+
+/// A Readwise tag as an Obsidian tag: whitespace becomes `-` and characters Obsidian doesn't allow
+/// in tags are dropped. `None` if nothing valid remains, or it would be purely numeric.
+pub(crate) fn obsidian_tag(prefix: &str, name: &str) -> Option<String> {
+    let name = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/'))
+        .collect::<String>();
+
+    let tag = format!("{}{}", prefix, name.trim_matches('/'));
+    let tag = tag.trim_matches('/');
+    if name.is_empty() || tag.chars().all(|c| c.is_ascii_digit() || c == '/') {
+        return None;
+    }
+
+    Some(tag.to_string())
+}
+
+/// Tags in order of their names, so notes don't change with the order Readwise gives them in.
+pub(crate) fn sorted_tags(tags: &[Tag]) -> Vec<Tag> {
+    tags.iter()
+        .cloned()
+        .sorted_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)))
+        .collect()
+}
+
+/// A Readwise tag as a wikilink, dropping the characters which can't appear in link targets.
+fn tag_link(prefix: &str, name: &str) -> String {
+    let name = name.replace(['[', ']', '|', '#', '^'], "");
+    format!("[[{}{}]]", prefix, name.trim())
+}
+
+impl Exporter {
+    fn tag_links(&self, tags: &[Tag]) -> Vec<String> {
+        tags.iter()
+            .map(|t| tag_link(&self.tag_prefix, &t.name))
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// Links to the book's tags, if tags are written as links.
+    pub(crate) fn book_tag_links(&self, book: &Book) -> Vec<String> {
+        match self.tag_output.contains(&TagOutput::Links) {
+            true => self.tag_links(&book.tags),
+            false => vec![],
+        }
+    }
+
+    /// Links to the highlight's tags, if tags are written as links.
+    pub(crate) fn highlight_tag_links(&self, highlight: &Highlight) -> Vec<String> {
+        match self.tag_output.contains(&TagOutput::Links) {
+            true => self.tag_links(&highlight.tags),
+            false => vec![],
+        }
+    }
+
+    /// The Obsidian tags for the frontmatter of a book note, from the tags of the book and its
+    /// highlights, or `None` if tags aren't written to the frontmatter.
+    pub(crate) fn frontmatter_tags(
+        &self,
+        book: &Book,
+        highlights: &[&Highlight],
+    ) -> Option<Vec<String>> {
+        if !self.tag_output.contains(&TagOutput::Frontmatter) {
+            return None;
+        }
+
+        Some(
+            book.tags
+                .iter()
+                .chain(highlights.iter().flat_map(|h| &h.tags))
+                .filter_map(|t| obsidian_tag(&self.tag_prefix, &t.name))
+                .sorted()
+                .dedup()
+                .collect(),
+        )
+    }
+
+    /// Write a note for every Readwise tag on an exported book or highlight into `folder` within
+    /// the base folder, listing the tagged books and highlights with links to their notes, and an
+    /// index of the tags with their counts. Tags differing only in case share a note. Regenerated
+    /// on each export.
+    pub(crate) fn write_tag_notes(&mut self, folder: &str) -> anyhow::Result<()> {
+        for (name, template) in [
+            ("tag", DEFAULT_TAG_TEMPLATE),
+            ("tag_index", DEFAULT_TAG_INDEX_TEMPLATE),
+        ] {
+            if !self.templates.get_template_names().any(|n| n == name) {
+                self.templates.add_raw_template(name, template)?;
+            }
+        }
+
+        let root = self.export_root.join(folder);
+
+        // By lowercased name, with the name as first seen
+        let mut tags: BTreeMap<String, (String, Vec<serde_json::Value>, Vec<serde_json::Value>)> =
+            BTreeMap::new();
+        let books = self
+            .library
+            .books
+            .iter()
+            .filter(|book| self.exported_paths.contains_key(&book.id))
+            .sorted_by(|a, b| a.title.cmp(&b.title));
+
+        for book in books {
+            let link = self.wikilink_target(&self.exported_paths[&book.id]);
+            for tag in book.tags.iter().unique_by(|t| t.name.to_lowercase()) {
+                tags.entry(tag.name.to_lowercase())
+                    .or_insert_with(|| (tag.name.clone(), vec![], vec![]))
+                    .1
+                    .push(json!({ "id": book.id, "title": book.title, "link": link }));
+            }
+
+            for highlight in self
+                .highlights_for(book)
+                .into_iter()
+                .sorted_by_key(|h| h.location)
+            {
+                for tag in highlight.tags.iter().unique_by(|t| t.name.to_lowercase()) {
+                    let mut value = self.highlight_value(book, highlight)?;
+                    value["book_title"] = serde_json::Value::from(book.title.as_str());
+                    value["link"] = serde_json::Value::from(link.as_str());
+
+                    tags.entry(tag.name.to_lowercase())
+                        .or_insert_with(|| (tag.name.clone(), vec![], vec![]))
+                        .2
+                        .push(value);
+                }
+            }
+        }
+
+        info!("Writing {} tag notes into {:?}", tags.len(), root);
+
+        let mut notes = vec![];
+        let mut index = vec![];
+        for (name, books, highlights) in tags.into_values() {
+            let path = root
+                .join(self.sanitize_title(&format!("{}{}", self.tag_prefix, name), "tag"))
+                .with_extension("md");
+
+            let mut context = Context::new();
+            context.insert("tag", &name);
+            context.insert("books", &books);
+            context.insert("highlights", &highlights);
+            context.insert("book_count", &books.len());
+            context.insert("highlight_count", &highlights.len());
+            notes.push((
+                name.clone(),
+                path.clone(),
+                self.templates.render("tag", &context)?,
+            ));
+
+            index.push(json!({
+                "tag": name,
+                "link": self.wikilink_target(&path),
+                "book_count": books.len(),
+                "highlight_count": highlights.len(),
+            }));
+        }
+
+        let mut context = Context::new();
+        context.insert("tags", &index);
+        notes.push((
+            "index".to_string(),
+            root.join(TAG_INDEX_FILE),
+            self.templates.render("tag_index", &context)?,
+        ));
+
+        for (name, path, contents) in notes {
+            if self.confine_record(&path, "tag", &name, None) {
+                self.transaction.stage_file(&path, contents)?;
+            }
+        }
+
+        Ok(())
+    }
+}
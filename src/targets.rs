@@ -0,0 +1,540 @@
+use anyhow::{anyhow, Context};
+use regex::Regex;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, StatusCode, Url};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+use tracing::{debug, info, warn};
+
+/// Where exported notes are written. Paths are relative to the vault root, so the same export can
+/// be pushed to a local vault or to one hosted elsewhere.
+pub trait OutputTarget {
+    fn exists(&self, path: &Path) -> anyhow::Result<bool>;
+
+    fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>>;
+
+    /// Write a file, creating its parent folders and replacing any existing file.
+    fn write(&self, path: &Path, contents: &[u8]) -> anyhow::Result<()>;
+
+    fn remove(&self, path: &Path) -> anyhow::Result<()>;
+
+    /// Remove a folder, failing if it isn't empty.
+    fn remove_folder(&self, path: &Path) -> anyhow::Result<()>;
+
+    /// Move a file, creating the destination's parent folders.
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()>;
+
+    /// The file's path on the local filesystem, if the target is local.
+    fn local_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Open the output target for a `--target` URL, or the local vault if none is given. The host key
+/// of an SFTP target is checked against `~/.ssh/known_hosts` unless `trust_unknown_host`, and
+/// credentials are only sent to a WebDAV target over plain http if `allow_http_credentials`.
+pub fn open_target(
+    vault_root: &Path,
+    target: Option<&str>,
+    password: Option<&str>,
+    key: Option<&Path>,
+    trust_unknown_host: bool,
+    allow_http_credentials: bool,
+) -> anyhow::Result<Box<dyn OutputTarget>> {
+    let Some(url) = target else {
+        return Ok(Box::new(LocalTarget {
+            root: vault_root.to_path_buf(),
+        }));
+    };
+
+    let url: Url = url.parse().context("Invalid target URL")?;
+    info!("Writing notes to remote target {}", url);
+    match url.scheme() {
+        "webdav" | "webdav+https" | "webdav+http" => Ok(Box::new(WebDavTarget::new(
+            &url,
+            password,
+            allow_http_credentials,
+        )?)),
+        "sftp" => Ok(Box::new(SftpTarget::connect(
+            &url,
+            password,
+            key,
+            trust_unknown_host,
+        )?)),
+        scheme => Err(anyhow!(
+            "Unsupported target scheme '{}', use webdav+https://, webdav+http://, or sftp://",
+            scheme
+        )),
+    }
+}
+
+/// A path relative to the target root as `/` separated segments, refusing to leave the root.
+fn segments(path: &Path) -> anyhow::Result<Vec<String>> {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(segment) => Ok(segment.to_string_lossy().to_string()),
+            _ => Err(anyhow!(
+                "Target path {:?} is not a plain relative path",
+                path
+            )),
+        })
+        .collect()
+}
+
+pub struct LocalTarget {
+    root: PathBuf,
+}
+
+impl OutputTarget for LocalTarget {
+    fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+        Ok(self.root.join(path).exists())
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        Ok(std::fs::read(self.root.join(path))?)
+    }
+
+    /// Writes by renaming a sibling temporary file over the target, so a file is never left half
+    /// written.
+    fn write(&self, path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, contents)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        Ok(std::fs::remove_file(self.root.join(path))?)
+    }
+
+    fn remove_folder(&self, path: &Path) -> anyhow::Result<()> {
+        Ok(std::fs::remove_dir(self.root.join(path))?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        let to = self.root.join(to);
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        Ok(std::fs::rename(self.root.join(from), to)?)
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(self.root.join(path))
+    }
+}
+
+/// Run a future to completion from the synchronous writer. Requires the multi-threaded runtime.
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+/// The end of an entry in a WebDAV folder listing, whatever the namespace prefix.
+static LISTING_ENTRY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)</(?:[a-z0-9]+:)?response>").unwrap());
+
+/// A vault on a WebDAV server such as Nextcloud, given as
+/// `webdav+https://user@host/remote.php/dav/files/user/Vault`.
+pub struct WebDavTarget {
+    root: Url,
+    username: String,
+    password: Option<String>,
+    client: reqwest::Client,
+}
+
+impl WebDavTarget {
+    fn new(
+        url: &Url,
+        password: Option<&str>,
+        allow_http_credentials: bool,
+    ) -> anyhow::Result<Self> {
+        let scheme = match url.scheme() {
+            "webdav+http" => "http",
+            _ => "https",
+        };
+
+        // Basic auth sends the password as it is, readable by anyone between here and the server
+        if scheme == "http" && !url.username().is_empty() {
+            if !allow_http_credentials {
+                return Err(anyhow!(
+                    "Refusing to send the credentials for WebDAV target {} over plain http. Use \
+                     webdav+https://, or pass --target-allow-http-credentials",
+                    url.host_str().unwrap_or_default()
+                ));
+            }
+
+            warn!(
+                "Sending the credentials for WebDAV target {} over plain http",
+                url.host_str().unwrap_or_default()
+            );
+        }
+
+        let mut root: Url = format!(
+            "{}://{}{}",
+            scheme,
+            url.host_str()
+                .ok_or_else(|| anyhow!("WebDAV target {} has no host", url))?,
+            url.port().map(|p| format!(":{}", p)).unwrap_or_default()
+        )
+        .parse()?;
+        root.set_path(url.path().trim_end_matches('/'));
+
+        Ok(WebDavTarget {
+            root,
+            username: url.username().to_string(),
+            password: password
+                .map(str::to_string)
+                .or_else(|| url.password().map(str::to_string)),
+            client: crate::readwise::http_client(),
+        })
+    }
+
+    fn url(&self, path: &Path) -> anyhow::Result<Url> {
+        let mut url = self.root.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("WebDAV target {} cannot be a base", self.root))?
+            .extend(segments(path)?);
+        Ok(url)
+    }
+
+    async fn request(&self, method: Method, url: Url) -> anyhow::Result<reqwest::Response> {
+        debug!("WebDAV {} {}", method, url);
+        let mut request = self.client.request(method, url);
+        if !self.username.is_empty() {
+            request = request.basic_auth(&self.username, self.password.as_ref());
+        }
+
+        Ok(request.send().await?)
+    }
+
+    /// Create each folder leading to `path`, ignoring those which already exist.
+    fn create_parents(&self, path: &Path) -> anyhow::Result<()> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+
+        let mut folder = PathBuf::new();
+        for segment in segments(parent)? {
+            folder.push(segment);
+            let response =
+                block_on(self.request(Method::from_bytes(b"MKCOL")?, self.url(&folder)?))?;
+
+            // 405 is returned for folders which already exist
+            if !response.status().is_success()
+                && response.status() != StatusCode::METHOD_NOT_ALLOWED
+            {
+                return Err(anyhow!(
+                    "Failed to create WebDAV folder {:?}: {}",
+                    folder,
+                    response.status()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl OutputTarget for WebDavTarget {
+    fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+        let response = block_on(self.request(Method::HEAD, self.url(path)?))?;
+        Ok(response.status().is_success())
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let response = block_on(self.request(Method::GET, self.url(path)?))?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to read {:?}: {}", path, response.status()));
+        }
+
+        Ok(block_on(response.bytes())?.to_vec())
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+        self.create_parents(path)?;
+
+        let mut request = self
+            .client
+            .put(self.url(path)?)
+            .header(CONTENT_TYPE, "text/markdown; charset=utf-8")
+            .body(contents.to_vec());
+        if !self.username.is_empty() {
+            request = request.basic_auth(&self.username, self.password.as_ref());
+        }
+
+        let response = block_on(request.send())?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to write {:?}: {}", path, response.status()));
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        let response = block_on(self.request(Method::DELETE, self.url(path)?))?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to remove {:?}: {}",
+                path,
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// A DELETE removes a collection along with everything in it, so the folder is listed first
+    /// and only removed if the listing has nothing besides the folder itself.
+    fn remove_folder(&self, path: &Path) -> anyhow::Result<()> {
+        let mut url = self.url(path)?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("WebDAV target {} cannot be a base", self.root))?
+            .push("");
+
+        let mut request = self
+            .client
+            .request(Method::from_bytes(b"PROPFIND")?, url.clone())
+            .header("Depth", "1");
+        if !self.username.is_empty() {
+            request = request.basic_auth(&self.username, self.password.as_ref());
+        }
+
+        let response = block_on(request.send())?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to list {:?}: {}", path, response.status()));
+        }
+
+        let listing = block_on(response.text())?;
+        if LISTING_ENTRY.find_iter(&listing).count() > 1 {
+            return Err(anyhow!("WebDAV folder {:?} isn't empty", path));
+        }
+
+        self.remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        self.create_parents(to)?;
+
+        let mut request = self
+            .client
+            .request(Method::from_bytes(b"MOVE")?, self.url(from)?)
+            .header("Destination", self.url(to)?.as_str());
+        if !self.username.is_empty() {
+            request = request.basic_auth(&self.username, self.password.as_ref());
+        }
+
+        let response = block_on(request.send())?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to move {:?} to {:?}: {}",
+                from,
+                to,
+                response.status()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// A vault on a host reachable over SSH, given as `sftp://user@host:port/path/to/vault`. Signs in
+/// with the key given, the password given, or otherwise the SSH agent.
+pub struct SftpTarget {
+    root: PathBuf,
+    sftp: ssh2::Sftp,
+    // Kept alive for the lifetime of the SFTP channel
+    _session: ssh2::Session,
+}
+
+impl SftpTarget {
+    fn connect(
+        url: &Url,
+        password: Option<&str>,
+        key: Option<&Path>,
+        trust_unknown_host: bool,
+    ) -> anyhow::Result<Self> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("SFTP target {} has no host", url))?;
+        let username = match url.username() {
+            "" => std::env::var("USER").context("SFTP target has no user")?,
+            username => username.to_string(),
+        };
+
+        let port = url.port().unwrap_or(22);
+        let tcp = TcpStream::connect((host, port))?;
+        let mut session = ssh2::Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        check_host_key(&session, host, port, trust_unknown_host)?;
+
+        let password = password.or(url.password());
+        match (key, password) {
+            (Some(key), passphrase) => {
+                session.userauth_pubkey_file(&username, None, key, passphrase)?
+            }
+            (None, Some(password)) => session.userauth_password(&username, password)?,
+            (None, None) => session.userauth_agent(&username)?,
+        }
+
+        if !session.authenticated() {
+            return Err(anyhow!(
+                "Failed to authenticate to {} as {}",
+                host,
+                username
+            ));
+        }
+
+        Ok(SftpTarget {
+            root: PathBuf::from(url.path()),
+            sftp: session.sftp()?,
+            _session: session,
+        })
+    }
+
+    fn path(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        Ok(segments(path)?
+            .into_iter()
+            .fold(self.root.clone(), |root, segment| root.join(segment)))
+    }
+
+    fn create_parents(&self, path: &Path) -> anyhow::Result<()> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+
+        let mut folder = self.root.clone();
+        for segment in segments(parent)? {
+            folder.push(segment);
+            if self.sftp.stat(&folder).is_err() {
+                self.sftp.mkdir(&folder, 0o755)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check the key a host presented against `~/.ssh/known_hosts` before signing in to it, so the
+/// credentials and the vault aren't handed to whoever is in the middle of the connection. A host
+/// which isn't known is only trusted if `trust_unknown_host`, a changed key never is.
+fn check_host_key(
+    session: &ssh2::Session,
+    host: &str,
+    port: u16,
+    trust_unknown_host: bool,
+) -> anyhow::Result<()> {
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| anyhow!("SFTP target {} presented no host key", host))?;
+
+    let mut known_hosts = session.known_hosts()?;
+    if let Some(home) = std::env::var_os("HOME") {
+        let file = Path::new(&home).join(".ssh/known_hosts");
+        if file.exists() {
+            known_hosts
+                .read_file(&file, ssh2::KnownHostFileKind::OpenSSH)
+                .with_context(|| format!("Failed to read {:?}", file))?;
+        }
+    }
+
+    match known_hosts.check_port(host, port, key) {
+        ssh2::CheckResult::Match => Ok(()),
+        ssh2::CheckResult::Mismatch => Err(anyhow!(
+            "The host key of SFTP target {} doesn't match the one in ~/.ssh/known_hosts, refusing \
+             to connect",
+            host
+        )),
+        ssh2::CheckResult::NotFound if trust_unknown_host => {
+            warn!("Trusting the unknown host key of SFTP target {}", host);
+            Ok(())
+        }
+        ssh2::CheckResult::NotFound => Err(anyhow!(
+            "SFTP target {} isn't in ~/.ssh/known_hosts. Connect with ssh once to add it, or pass \
+             --target-trust-unknown-host",
+            host
+        )),
+        ssh2::CheckResult::Failure => Err(anyhow!(
+            "Failed to check the host key of SFTP target {}",
+            host
+        )),
+    }
+}
+
+impl OutputTarget for SftpTarget {
+    fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+        Ok(self.sftp.stat(&self.path(path)?).is_ok())
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let mut contents = vec![];
+        self.sftp
+            .open(&self.path(path)?)?
+            .read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+        self.create_parents(path)?;
+
+        let target = self.path(path)?;
+        let mut tmp = target.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        self.sftp.create(&tmp)?.write_all(contents)?;
+        self.sftp.rename(
+            &tmp,
+            &target,
+            Some(ssh2::RenameFlags::OVERWRITE | ssh2::RenameFlags::ATOMIC),
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        Ok(self.sftp.unlink(&self.path(path)?)?)
+    }
+
+    fn remove_folder(&self, path: &Path) -> anyhow::Result<()> {
+        Ok(self.sftp.rmdir(&self.path(path)?)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        self.create_parents(to)?;
+        Ok(self.sftp.rename(&self.path(from)?, &self.path(to)?, None)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::open_target;
+    use std::path::Path;
+
+    #[test]
+    fn credentials_are_only_sent_over_http_when_allowed() {
+        let open = |target, allow_http_credentials| {
+            open_target(
+                Path::new("vault"),
+                Some(target),
+                Some("secret"),
+                None,
+                false,
+                allow_http_credentials,
+            )
+        };
+
+        assert!(open("webdav+http://me@example.com/vault", false).is_err());
+        assert!(open("webdav+http://me@example.com/vault", true).is_ok());
+        assert!(open("webdav+https://me@example.com/vault", false).is_ok());
+        assert!(open("webdav+http://example.com/vault", false).is_ok());
+    }
+}
@@ -0,0 +1,66 @@
+//! Logging, and with the `otel` feature, the export of spans to an OpenTelemetry collector over
+//! OTLP. Spans are only exported if `OTEL_EXPORTER_OTLP_ENDPOINT` is set, e.g. to
+//! `http://localhost:4318` for a local Jaeger or Grafana Alloy.
+
+#[cfg(feature = "otel")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::SdkTracerProvider;
+
+/// Keeps span export running, flushing the spans not yet exported when dropped.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Failed to export the remaining spans: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+pub fn init() -> anyhow::Result<Telemetry> {
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        tracing_subscriber::fmt::init();
+        return Ok(Telemetry { provider: None });
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME"))))
+        .init();
+
+    Ok(Telemetry {
+        provider: Some(provider),
+    })
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init() -> anyhow::Result<Telemetry> {
+    tracing_subscriber::fmt::init();
+    Ok(Telemetry {})
+}
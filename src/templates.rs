@@ -0,0 +1,189 @@
+use crate::readwise::Book;
+use crate::Exporter;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tera::Tera;
+use tracing::debug;
+
+/// The book template used when `--book-template` isn't given.
+pub const DEFAULT_BOOK_TEMPLATE: &str = include_str!("../templates/book.md.tera");
+
+/// The highlight template used when `--highlight-template` isn't given.
+pub const DEFAULT_HIGHLIGHT_TEMPLATE: &str = include_str!("../templates/highlight.md.tera");
+
+/// Prefix of a Readwise book tag which selects an alternative set of templates for that book.
+const TEMPLATE_TAG_PREFIX: &str = "template:";
+
+/// The highlights a highlight template rule applies to.
+#[derive(Debug, Clone)]
+pub enum HighlightSelector {
+    Color(String),
+    Tag(String),
+}
+
+/// A highlight template for the highlights of a color or with a tag, e.g.
+/// `color:red=task.md.tera` or `tag:question=qa.md.tera`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HighlightTemplateRule {
+    pub selector: HighlightSelector,
+    pub path: PathBuf,
+}
+
+impl HighlightTemplateRule {
+    /// The name the rule's template is registered as, by its position among the rules.
+    pub fn template_name(index: usize) -> String {
+        format!("highlight_rule_{}", index)
+    }
+
+    /// Whether a highlight, as given to templates, is selected by the rule.
+    fn matches(&self, highlight: &serde_json::Value) -> bool {
+        match &self.selector {
+            HighlightSelector::Color(color) => highlight["color"]
+                .as_str()
+                .is_some_and(|c| c.eq_ignore_ascii_case(color)),
+            HighlightSelector::Tag(tag) => highlight["tags"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|t| t["name"].as_str())
+                .any(|name| name.eq_ignore_ascii_case(tag)),
+        }
+    }
+}
+
+impl FromStr for HighlightTemplateRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (selector, path) = s
+            .split_once('=')
+            .and_then(|(selector, path)| Some((selector.split_once(':')?, path.trim())))
+            .filter(|((_, value), path)| !value.trim().is_empty() && !path.is_empty())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Expected color:<color>=<template> or tag:<tag>=<template>, got '{}'",
+                    s
+                )
+            })?;
+
+        let selector = match selector {
+            ("color", color) => HighlightSelector::Color(color.trim().to_string()),
+            ("tag", tag) => HighlightSelector::Tag(tag.trim().to_string()),
+            (other, _) => {
+                return Err(anyhow!(
+                    "Unknown highlight selector '{}', expected color or tag",
+                    other
+                ))
+            }
+        };
+
+        Ok(HighlightTemplateRule {
+            selector,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl TryFrom<String> for HighlightTemplateRule {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Register a template read from a file, recording its source.
+pub fn add_file(
+    tera: &mut Tera,
+    sources: &mut HashMap<String, String>,
+    name: &str,
+    path: &Path,
+) -> anyhow::Result<()> {
+    tera.add_template_file(path, Some(name))?;
+    sources.insert(name.to_string(), std::fs::read_to_string(path)?);
+    Ok(())
+}
+
+/// Register a template from its source, recording it.
+pub fn add_raw(
+    tera: &mut Tera,
+    sources: &mut HashMap<String, String>,
+    name: &str,
+    source: &str,
+) -> anyhow::Result<()> {
+    tera.add_raw_template(name, source)?;
+    sources.insert(name.to_string(), source.to_string());
+    Ok(())
+}
+
+/// Register every `.md.tera` file beneath `dir`, named by its path relative to `dir` without the
+/// extension. For example `poetry/book.md.tera` is registered as `poetry/book`.
+pub fn load_template_dir(
+    tera: &mut Tera,
+    sources: &mut HashMap<String, String>,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    fn visit(
+        tera: &mut Tera,
+        sources: &mut HashMap<String, String>,
+        root: &Path,
+        dir: &Path,
+    ) -> anyhow::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                visit(tera, sources, root, &path)?;
+                continue;
+            }
+
+            let relative = path
+                .strip_prefix(root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            if let Some(name) = relative.strip_suffix(".md.tera") {
+                debug!("Loading template {:?} as '{}'", path, name);
+                add_file(tera, sources, name, &path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    visit(tera, sources, dir, dir)
+}
+
+impl Exporter {
+    /// The name of the template of the given kind (`book` or `highlight`) to use for a book. A
+    /// `template:<name>` tag on the book selects `<name>/<kind>` from the template directory,
+    /// otherwise the set named after the book's category (e.g. `podcasts/highlight`) is used.
+    /// Each kind falls back separately, to the default template if no set includes one, so a
+    /// category can have its own highlight template while sharing the default book template.
+    pub(crate) fn template_for(&self, book: &Book, kind: &str) -> String {
+        book.tags
+            .iter()
+            .filter_map(|tag| tag.name.strip_prefix(TEMPLATE_TAG_PREFIX))
+            .map(|name| name.trim().to_string())
+            .chain(std::iter::once(book.category.to_lowercase()))
+            .map(|name| format!("{}/{}", name, kind))
+            .find(|name| self.templates.get_template_names().any(|n| n == name))
+            .unwrap_or_else(|| kind.to_string())
+    }
+
+    /// The name of the template to render a highlight with: that of the first highlight template
+    /// rule selecting it, otherwise the book's highlight template.
+    pub(crate) fn highlight_template_for(
+        &self,
+        book: &Book,
+        highlight: &serde_json::Value,
+    ) -> String {
+        self.highlight_template_rules
+            .iter()
+            .position(|rule| rule.matches(highlight))
+            .map(HighlightTemplateRule::template_name)
+            .unwrap_or_else(|| self.template_for(book, "highlight"))
+    }
+}
@@ -0,0 +1,113 @@
+//! Builders for the records tests work with, filling in the fields a test doesn't care about.
+
+use crate::readwise::{Book, Document, Highlight};
+use crate::{ExportCommand, Exporter, Library};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+pub fn book(id: i32, title: &str) -> Book {
+    Book {
+        id,
+        title: title.to_string(),
+        author: None,
+        category: "books".to_string(),
+        num_highlights: 0,
+        last_highlight_at: None,
+        updated: None,
+        cover_image_url: None,
+        highlights_url: None,
+        source_url: None,
+        asin: None,
+        tags: vec![],
+        readable_title: None,
+        document_note: None,
+        source: None,
+        unique_url: None,
+    }
+}
+
+pub fn highlight(id: i32, book_id: i32, text: &str) -> Highlight {
+    Highlight {
+        id,
+        text: text.to_string(),
+        note: String::new(),
+        location: id,
+        location_type: "location".to_string(),
+        highlighted_at: None,
+        url: None,
+        color: String::new(),
+        updated: "2024-01-01T00:00:00Z".to_string(),
+        book_id,
+        tags: vec![],
+        is_favorite: false,
+        is_discard: false,
+        end_location: None,
+        external_id: None,
+    }
+}
+
+/// A Reader document, or with `parent_id` a highlight made on one.
+pub fn document(id: &str, title: &str, category: &str, parent_id: Option<&str>) -> Document {
+    Document {
+        id: id.to_string(),
+        url: format!("https://read.readwise.io/read/{}", id),
+        title: Some(title.to_string()),
+        author: None,
+        source: None,
+        category: Some(category.to_string()),
+        location: None,
+        tags: None,
+        site_name: None,
+        word_count: None,
+        created_at: "2024-01-01T00:00:00Z".to_string(),
+        updated_at: "2024-01-01T00:00:00Z".to_string(),
+        published_date: None,
+        summary: None,
+        image_url: None,
+        content: Some(title.to_string()),
+        html_content: None,
+        source_url: None,
+        notes: None,
+        parent_id: parent_id.map(str::to_string),
+        reading_progress: 0.0,
+        first_opened_at: None,
+        last_opened_at: None,
+        saved_at: "2024-01-01T00:00:00Z".to_string(),
+        last_moved_at: "2024-01-01T00:00:00Z".to_string(),
+    }
+}
+
+/// A library holding just `books` and `highlights`.
+pub fn library(books: Vec<Book>, highlights: Vec<Highlight>) -> Library {
+    Library {
+        books,
+        highlights,
+        ..Library::empty()
+    }
+}
+
+/// An empty vault folder of its own for the test `name`.
+pub fn vault(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!(
+        "readwise-export-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&root).ok();
+    std::fs::create_dir_all(root.join(".obsidian")).unwrap();
+    root
+}
+
+/// An exporter of `library` into `vault`, with a base folder of `Readwise` and the export options
+/// in `args`.
+pub fn exporter(vault: &Path, library: Library, args: &[&str]) -> Exporter {
+    let vault = vault.to_string_lossy();
+    let cli = ExportCommand::try_parse_from(
+        ["export", "--vault", &vault, "--base-folder", "Readwise"]
+            .into_iter()
+            .chain(args.iter().copied()),
+    )
+    .unwrap();
+
+    Exporter::new(library, &cli).unwrap()
+}
@@ -0,0 +1,302 @@
+use anyhow::anyhow;
+use regex::Regex;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+/// A single cleanup applied to highlight text as it is rendered.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CleanupStep {
+    /// Replace curly quotes and apostrophes with straight ones
+    StraightQuotes,
+
+    /// Replace straight quotes and apostrophes with curly ones
+    CurlyQuotes,
+
+    /// Remove soft hyphens, and rejoin words hyphenated across a line break
+    Dehyphenate,
+
+    /// Join lines broken mid-paragraph, keeping blank lines between paragraphs
+    JoinLines,
+
+    /// Collapse runs of spaces and blank lines, and trim each line
+    Whitespace,
+
+    /// Escape the `==` and `%%` which Obsidian would read as highlight or comment markers
+    EscapeMarkdown,
+
+    /// Escape the `$` and backslashes which Obsidian would read as MathJax, so LaTeX from
+    /// math-heavy PDFs is shown as written
+    EscapeMath,
+}
+
+impl FromStr for CleanupStep {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "straight-quotes" => Ok(CleanupStep::StraightQuotes),
+            "curly-quotes" => Ok(CleanupStep::CurlyQuotes),
+            "dehyphenate" => Ok(CleanupStep::Dehyphenate),
+            "join-lines" => Ok(CleanupStep::JoinLines),
+            "whitespace" => Ok(CleanupStep::Whitespace),
+            "escape-markdown" => Ok(CleanupStep::EscapeMarkdown),
+            "escape-math" => Ok(CleanupStep::EscapeMath),
+            other => Err(anyhow!(
+                "Unknown text cleanup '{}', expected one of straight-quotes, curly-quotes, \
+                 dehyphenate, join-lines, whitespace, escape-markdown, escape-math",
+                other
+            )),
+        }
+    }
+}
+
+static HYPHEN_BREAK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\w)-[ \t]*\r?\n[ \t]*(\w)").unwrap());
+static SPACES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[ \t\u{00A0}]{2,}").unwrap());
+static BLANK_LINES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\n{3,}").unwrap());
+
+impl CleanupStep {
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            CleanupStep::StraightQuotes => text
+                .replace(['\u{2018}', '\u{2019}', '\u{201A}', '\u{2032}'], "'")
+                .replace(['\u{201C}', '\u{201D}', '\u{201E}', '\u{2033}'], "\""),
+
+            CleanupStep::CurlyQuotes => curl_quotes(text),
+
+            CleanupStep::Dehyphenate => HYPHEN_BREAK
+                .replace_all(&text.replace('\u{00AD}', ""), "$1$2")
+                .to_string(),
+
+            CleanupStep::JoinLines => join_lines(text),
+
+            CleanupStep::Whitespace => {
+                let lines = text
+                    .lines()
+                    .map(|line| SPACES.replace_all(line.trim(), " ").to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                BLANK_LINES.replace_all(lines.trim(), "\n\n").to_string()
+            }
+
+            CleanupStep::EscapeMarkdown => {
+                // Paired `==` are the author's own highlighting, an unpaired one runs on through
+                // the rest of the note
+                let text = match text.matches("==").count() % 2 {
+                    0 => text.to_string(),
+                    _ => text.replace("==", r"\=\="),
+                };
+
+                text.replace("%%", r"\%\%")
+            }
+
+            CleanupStep::EscapeMath => escape_math(text),
+        }
+    }
+}
+
+/// Escape dollar signs, and backslashes which would otherwise escape the character after them (as
+/// in `\{`), leaving commands like `\frac` readable.
+fn escape_math(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => escaped.push_str(r"\$"),
+            '\\' if chars.peek().is_some_and(|next| next.is_ascii_punctuation()) => {
+                escaped.push_str(r"\\")
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Join the lines of each paragraph with spaces, paragraphs being separated by blank lines.
+fn join_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .split(|line| line.is_empty())
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| paragraph.join(" "))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Replace straight quotes with curly ones, opening after whitespace or an opening bracket and
+/// closing elsewhere, so apostrophes within words curl as closing quotes.
+fn curl_quotes(text: &str) -> String {
+    let mut curled = String::with_capacity(text.len());
+    let mut previous: Option<char> = None;
+
+    for c in text.chars() {
+        let opening =
+            previous.is_none_or(|p| p.is_whitespace() || "([{\u{2014}\u{2013}".contains(p));
+
+        curled.push(match (c, opening) {
+            ('"', true) => '\u{201C}',
+            ('"', false) => '\u{201D}',
+            ('\'', true) => '\u{2018}',
+            ('\'', false) => '\u{2019}',
+            (c, _) => c,
+        });
+
+        previous = Some(c);
+    }
+
+    curled
+}
+
+/// The cleanup steps applied, in order, to the text of highlights in a category, in books with a
+/// tag, or in every category if neither is given, e.g. `books=dehyphenate+join-lines`,
+/// `tag:math=escape-math`, or `whitespace+straight-quotes`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TextCleanup {
+    pub category: Option<String>,
+    pub tag: Option<String>,
+    pub steps: Vec<CleanupStep>,
+}
+
+impl FromStr for TextCleanup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (category, tag, steps) = match s.split_once('=') {
+            Some((selector, steps)) => match selector.trim().strip_prefix("tag:") {
+                _ if selector.trim().is_empty() => {
+                    return Err(anyhow!("Empty category in text cleanup '{}'", s))
+                }
+                Some(tag) if tag.trim().is_empty() => {
+                    return Err(anyhow!("Empty tag in text cleanup '{}'", s))
+                }
+                Some(tag) => (None, Some(tag.trim().to_lowercase()), steps),
+                None => (Some(selector.trim().to_lowercase()), None, steps),
+            },
+            None => (None, None, s),
+        };
+
+        let steps = steps
+            .split('+')
+            .map(str::parse)
+            .collect::<anyhow::Result<Vec<CleanupStep>>>()?;
+
+        Ok(TextCleanup {
+            category,
+            tag,
+            steps,
+        })
+    }
+}
+
+impl TryFrom<String> for TextCleanup {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Apply the pipeline configured for a category, falling back to the one for every category, then
+/// the pipelines for any of the tags.
+pub fn clean(pipelines: &[TextCleanup], category: &str, tags: &[&str], text: &str) -> String {
+    let category = category.to_lowercase();
+    let general = pipelines
+        .iter()
+        .filter(|p| p.tag.is_none())
+        .find(|p| p.category.as_deref() == Some(category.as_str()))
+        .or_else(|| {
+            pipelines
+                .iter()
+                .find(|p| p.tag.is_none() && p.category.is_none())
+        });
+
+    let tagged = pipelines.iter().filter(|p| {
+        p.tag
+            .as_deref()
+            .is_some_and(|tag| tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+    });
+
+    general
+        .into_iter()
+        .chain(tagged)
+        .flat_map(|pipeline| &pipeline.steps)
+        .fold(text.to_string(), |text, step| step.apply(&text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clean, CleanupStep, TextCleanup};
+
+    #[test]
+    fn quotes_curl_open_and_closed_by_position() {
+        let straight = "\"It's here,\" she said";
+        let curled = "\u{201C}It\u{2019}s here,\u{201D} she said";
+
+        assert_eq!(CleanupStep::CurlyQuotes.apply(straight), curled);
+        assert_eq!(CleanupStep::StraightQuotes.apply(curled), straight);
+    }
+
+    #[test]
+    fn line_breaks_are_undone_within_paragraphs() {
+        assert_eq!(
+            CleanupStep::Dehyphenate.apply("extra-\n  ordinary soft\u{00AD}ware"),
+            "extraordinary software"
+        );
+        assert_eq!(
+            CleanupStep::JoinLines.apply("one\n two\n\n\nthree"),
+            "one two\n\nthree"
+        );
+        assert_eq!(
+            CleanupStep::Whitespace.apply("  a   b \n\n\n\nc "),
+            "a b\n\nc"
+        );
+    }
+
+    #[test]
+    fn only_unpaired_highlight_markers_are_escaped() {
+        assert_eq!(CleanupStep::EscapeMarkdown.apply("==kept=="), "==kept==");
+        assert_eq!(
+            CleanupStep::EscapeMarkdown.apply("a == b %% c"),
+            r"a \=\= b \%\% c"
+        );
+    }
+
+    #[test]
+    fn a_category_pipeline_replaces_the_general_one() {
+        let pipelines = ["whitespace", "Books=join-lines+straight-quotes"]
+            .iter()
+            .map(|p| p.parse::<TextCleanup>().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            clean(&pipelines, "books", &[], "\u{2018}a\u{2019}\n  b"),
+            "'a' b"
+        );
+        assert_eq!(clean(&pipelines, "articles", &[], "a\n  b"), "a\nb");
+        assert_eq!(clean(&[], "articles", &[], "a\n  b"), "a\n  b");
+    }
+
+    #[test]
+    fn tag_pipelines_run_after_the_category_one() {
+        let pipelines = ["whitespace", "tag:Poetry=join-lines"]
+            .iter()
+            .map(|p| p.parse::<TextCleanup>().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(clean(&pipelines, "books", &["poetry"], "a  b\n c"), "a b c");
+        assert_eq!(clean(&pipelines, "books", &["prose"], "a  b\n c"), "a b\nc");
+        assert!("tag:=whitespace".parse::<TextCleanup>().is_err());
+    }
+
+    #[test]
+    fn unknown_steps_and_empty_categories_are_rejected() {
+        assert!("whitespace+sparkle".parse::<TextCleanup>().is_err());
+        assert!("=whitespace".parse::<TextCleanup>().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::readwise::{parse_timestamp, Book, Highlight};
+use crate::Exporter;
+use chrono::Duration;
+use itertools::Itertools;
+use serde_json::Value;
+
+/// The longest gap between two tweets saved from the same thread. Readwise saves every tweet of a
+/// thread at once, so anything further apart was saved separately.
+const THREAD_GAP_MINUTES: i64 = 5;
+
+/// Whether `next` continues the thread ending with `previous`.
+fn continues_thread(previous: &Highlight, next: &Highlight) -> bool {
+    let saved_at = |h: &Highlight| h.highlighted_at.as_deref().and_then(parse_timestamp);
+
+    match (saved_at(previous), saved_at(next)) {
+        (Some(previous), Some(next)) => {
+            (next - previous).abs() <= Duration::minutes(THREAD_GAP_MINUTES)
+        }
+        _ => false,
+    }
+}
+
+impl Exporter {
+    /// The highlights of a book as they are rendered by the highlight template, in the order they
+    /// appear in the note. In thread categories, consecutive tweets saved together are stitched
+    /// into one highlight whose text is the whole thread followed by the author's handle, and whose
+    /// `thread` lists the original highlights.
+    pub(crate) fn highlight_blocks(
+        &self,
+        book: &Book,
+        highlights: &[&Highlight],
+    ) -> serde_json::Result<Vec<Value>> {
+        let threaded = self
+            .thread_categories
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(&book.category));
+
+        if !threaded {
+            return highlights
+                .iter()
+                .map(|highlight| self.highlight_value(book, highlight))
+                .collect();
+        }
+
+        let sorted = highlights
+            .iter()
+            .copied()
+            .sorted_by_key(|h| h.location)
+            .collect_vec();
+
+        let mut threads: Vec<Vec<&Highlight>> = vec![];
+        for highlight in sorted {
+            match threads.last_mut() {
+                Some(thread) if continues_thread(thread[thread.len() - 1], highlight) => {
+                    thread.push(highlight)
+                }
+                _ => threads.push(vec![highlight]),
+            }
+        }
+
+        threads
+            .into_iter()
+            .map(|thread| {
+                let mut value = self.highlight_value(book, thread[0])?;
+                if thread.len() == 1 {
+                    return Ok(value);
+                }
+
+                let parts = thread
+                    .iter()
+                    .map(|h| self.highlight_value(book, h))
+                    .collect::<serde_json::Result<Vec<_>>>()?;
+
+                let mut text = parts
+                    .iter()
+                    .filter_map(|p| p["text"].as_str())
+                    .map(str::trim)
+                    .join("\n\n");
+                if let Some(author) = &book.author {
+                    text.push_str(&format!("\n\n— {}", author));
+                }
+
+                value["text"] = Value::from(text);
+                value["note"] = Value::from(
+                    thread
+                        .iter()
+                        .map(|h| h.note.trim())
+                        .filter(|n| !n.is_empty())
+                        .join("\n\n"),
+                );
+                value["thread"] = Value::from(parts);
+                Ok(value)
+            })
+            .collect()
+    }
+}
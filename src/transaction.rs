@@ -0,0 +1,696 @@
+use crate::confinement::normalize;
+use crate::targets::{open_target, OutputTarget};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Folder in the vault root holding backups of the files being changed by an in-flight export.
+const BACKUP_DIR: &str = ".readwise-export-backup";
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Where Obsidian sends deleted files, per the vault's "Deleted files" setting.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TrashOption {
+    /// The operating system's trash
+    System,
+
+    /// The `.trash` folder in the vault root
+    Local,
+
+    /// Delete permanently
+    None,
+}
+
+#[derive(Deserialize)]
+struct AppConfig {
+    #[serde(rename = "trashOption", default = "default_trash_option")]
+    trash_option: TrashOption,
+}
+
+fn default_trash_option() -> TrashOption {
+    TrashOption::System
+}
+
+/// Read the vault's trash setting from `.obsidian/app.json`, defaulting as Obsidian does.
+fn trash_option(vault_root: &Path) -> TrashOption {
+    let config = vault_root.join(".obsidian").join("app.json");
+    std::fs::File::open(config)
+        .ok()
+        .and_then(|file| serde_json::from_reader::<_, AppConfig>(file).ok())
+        .map(|config| config.trash_option)
+        .unwrap_or_else(default_trash_option)
+}
+
+/// A change to the vault staged during an export.
+enum StagedChange {
+    Write {
+        path: PathBuf,
+        contents: String,
+        /// Whether this is a note for a book or document, rather than an index, log, or other file
+        /// written alongside them
+        note: bool,
+    },
+
+    /// A binary file, such as a downloaded image, which is not validated as a note
+    Attachment { path: PathBuf, contents: Vec<u8> },
+
+    /// Move a file to the trash, so that it can still be recovered from Obsidian
+    Trash { path: PathBuf },
+}
+
+impl StagedChange {
+    fn path(&self) -> &Path {
+        match self {
+            StagedChange::Write { path, .. }
+            | StagedChange::Attachment { path, .. }
+            | StagedChange::Trash { path } => path,
+        }
+    }
+}
+
+/// The state of a file before the transaction touched it, `backup` being `None` for files which
+/// did not exist yet, and what the transaction left elsewhere in the vault changing it. Paths are
+/// relative to the vault root.
+#[derive(Serialize, Deserialize)]
+struct BackupEntry {
+    path: PathBuf,
+    backup: Option<PathBuf>,
+
+    /// Where the file was moved to if it was trashed into the vault's `.trash` folder
+    #[serde(default)]
+    trashed_to: Option<PathBuf>,
+
+    /// The folders which didn't exist before, so were created to write or trash the file
+    #[serde(default)]
+    created_folders: Vec<PathBuf>,
+}
+
+/// The files changed by a committed transaction, relative to the vault root.
+#[derive(Debug, Default)]
+pub struct AppliedChanges {
+    pub created: Vec<PathBuf>,
+    pub updated: Vec<PathBuf>,
+    pub trashed: Vec<PathBuf>,
+}
+
+/// Collects every change an export makes to the vault so that they can be validated before any is
+/// applied, and rolled back if applying them fails partway. Backups are kept on disk until the
+/// transaction completes, so a run that crashed mid-apply is rolled back by the next one.
+pub struct VaultTransaction {
+    vault_root: PathBuf,
+    backup_root: PathBuf,
+    trash_option: TrashOption,
+    target: Box<dyn OutputTarget>,
+    changes: Vec<StagedChange>,
+}
+
+/// Render a note as it is written to disk, with its metadata as YAML frontmatter.
+pub(crate) fn render_note(metadata: &serde_yml::Value, contents: &str) -> anyhow::Result<String> {
+    Ok(format!(
+        "---\n{}---\n{}",
+        serde_yml::to_string(metadata)?,
+        contents
+    ))
+}
+
+impl VaultTransaction {
+    /// Start a transaction writing to `target`, first rolling back any left behind by a crashed
+    /// run. Backups are always kept in the local vault.
+    pub fn begin(vault_root: &Path, target: Box<dyn OutputTarget>) -> anyhow::Result<Self> {
+        let transaction = VaultTransaction {
+            vault_root: vault_root.to_path_buf(),
+            backup_root: vault_root.join(BACKUP_DIR),
+            trash_option: trash_option(vault_root),
+            target,
+            changes: vec![],
+        };
+
+        if transaction.backup_root.join(MANIFEST_FILE).exists() {
+            warn!("Found backups from an interrupted export, restoring them");
+            transaction.restore()?;
+        }
+
+        Ok(transaction)
+    }
+
+    /// A transaction over the local vault which is only looked through and never committed, so
+    /// nothing is restored from an interrupted export's backups.
+    pub fn inspect(vault_root: &Path) -> anyhow::Result<Self> {
+        Ok(VaultTransaction {
+            vault_root: vault_root.to_path_buf(),
+            backup_root: vault_root.join(BACKUP_DIR),
+            trash_option: trash_option(vault_root),
+            target: open_target(vault_root, None, None, None, false, false)?,
+            changes: vec![],
+        })
+    }
+
+    /// The path of a file in the vault relative to the vault root, with any `..` resolved so it
+    /// can't lead back out of the vault.
+    fn relative(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        normalize(path)
+            .strip_prefix(normalize(&self.vault_root))
+            .map(Path::to_path_buf)
+            .map_err(|_| anyhow!("Refusing to change {:?} outside of the vault", path))
+    }
+
+    /// Whether the file at `path` exists on the target.
+    pub fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+        self.target.exists(&self.relative(path)?)
+    }
+
+    /// The contents of the file at `path` as they are on the target, or `None` if it doesn't exist.
+    pub fn read_existing(&self, path: &Path) -> anyhow::Result<Option<String>> {
+        let path = self.relative(path)?;
+        if !self.target.exists(&path)? {
+            return Ok(None);
+        }
+
+        Ok(Some(String::from_utf8(self.target.read(&path)?)?))
+    }
+
+    /// Stage a note to be written to `path`.
+    pub fn stage_note(
+        &mut self,
+        path: &Path,
+        metadata: &serde_yml::Value,
+        contents: &str,
+    ) -> anyhow::Result<()> {
+        self.changes.push(StagedChange::Write {
+            path: self.relative(path)?,
+            contents: render_note(metadata, contents)?,
+            note: true,
+        });
+        Ok(())
+    }
+
+    /// Stage a file without frontmatter to be written to `path`.
+    pub fn stage_file(&mut self, path: &Path, contents: String) -> anyhow::Result<()> {
+        self.changes.push(StagedChange::Write {
+            path: self.relative(path)?,
+            contents,
+            note: false,
+        });
+        Ok(())
+    }
+
+    /// Stage a binary file to be written to `path`, unless it is already in the vault or staged.
+    pub fn stage_attachment(&mut self, path: &Path, contents: Vec<u8>) -> anyhow::Result<()> {
+        let path = self.relative(path)?;
+        if self.changes.iter().any(|c| c.path() == path) || self.target.exists(&path)? {
+            return Ok(());
+        }
+
+        self.changes
+            .push(StagedChange::Attachment { path, contents });
+        Ok(())
+    }
+
+    /// Stage moving the file at `path` to the trash. Staged before a write to the same path, this
+    /// keeps the replaced note recoverable.
+    pub fn stage_trash(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.changes.push(StagedChange::Trash {
+            path: self.relative(path)?,
+        });
+        Ok(())
+    }
+
+    /// Move the file of the manifest entry at `index` to the trash configured for the vault.
+    /// Remote targets have no system trash, so use the vault's `.trash` folder instead, recording
+    /// where the file is moved to before moving it so a rollback can take it back out.
+    fn trash(&self, manifest: &mut [BackupEntry], index: usize) -> anyhow::Result<()> {
+        let path = manifest[index].path.clone();
+        match (self.trash_option, self.target.local_path(&path)) {
+            (TrashOption::System, Some(local_path)) => trash::delete(local_path)?,
+            (TrashOption::System | TrashOption::Local, _) => {
+                let trash_root = PathBuf::from(".trash");
+
+                // Obsidian keeps the trash flat, numbering files whose names collide
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                let mut destination = trash_root.join(path.file_name().unwrap_or_default());
+                let mut n = 1;
+                while self.target.exists(&destination)? {
+                    destination = trash_root.join(format!("{} {}.{}", stem, n, extension));
+                    n += 1;
+                }
+
+                manifest[index].created_folders = self.missing_folders(&destination)?;
+                manifest[index].trashed_to = Some(destination.clone());
+                self.write_manifest(manifest)?;
+
+                self.target.rename(&path, &destination)?;
+            }
+            (TrashOption::None, _) => self.target.remove(&path)?,
+        }
+
+        Ok(())
+    }
+
+    /// The folders leading to `path` which don't exist yet, deepest first.
+    fn missing_folders(&self, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut missing = vec![];
+        for folder in path.ancestors().skip(1) {
+            if folder.as_os_str().is_empty() || self.target.exists(folder)? {
+                break;
+            }
+
+            missing.push(folder.to_path_buf());
+        }
+
+        Ok(missing)
+    }
+
+    /// Check every staged file has parseable frontmatter, and that no book or document note which
+    /// has content in the vault is replaced by one without any. Other files, such as an index with
+    /// nothing to list, may well be empty.
+    fn validate(&self) -> anyhow::Result<()> {
+        for change in &self.changes {
+            let StagedChange::Write {
+                path,
+                contents,
+                note,
+            } = change
+            else {
+                continue;
+            };
+
+            let body = match contents.strip_prefix("---\n") {
+                Some(rest) => {
+                    let (frontmatter, body) = rest.split_once("---\n").ok_or_else(|| {
+                        anyhow!("Staged note {:?} has unterminated frontmatter", path)
+                    })?;
+
+                    serde_yml::from_str::<serde_yml::Value>(frontmatter).with_context(|| {
+                        format!("Staged note {:?} has invalid frontmatter", path)
+                    })?;
+                    body
+                }
+                None => contents.as_str(),
+            };
+
+            if *note && body.trim().is_empty() && self.has_content(path)? {
+                return Err(anyhow!(
+                    "Staged note {:?} is empty, but has content in the vault",
+                    path
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the file at `path` exists on the target with something below its frontmatter.
+    fn has_content(&self, path: &Path) -> anyhow::Result<bool> {
+        if !self.target.exists(path)? {
+            return Ok(false);
+        }
+
+        let existing = String::from_utf8_lossy(&self.target.read(path)?).into_owned();
+        let body = existing
+            .strip_prefix("---\n")
+            .and_then(|rest| rest.split_once("---\n"))
+            .map_or(existing.as_str(), |(_, body)| body);
+
+        Ok(!body.trim().is_empty())
+    }
+
+    /// Validate the staged changes, then apply them all, restoring the vault to its prior state if
+    /// any of them fails.
+    pub fn commit(self) -> anyhow::Result<AppliedChanges> {
+        self.validate()?;
+
+        info!("Applying {} staged vault changes", self.changes.len());
+
+        let mut manifest = match self.back_up() {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                // Nothing has been changed yet, so the partial backups can simply be discarded
+                std::fs::remove_dir_all(&self.backup_root).ok();
+                return Err(err);
+            }
+        };
+
+        if let Err(err) = self.apply(&mut manifest) {
+            warn!("Failed to apply staged changes, rolling back: {}", err);
+            self.restore()?;
+            return Err(err);
+        }
+
+        std::fs::remove_dir_all(&self.backup_root)?;
+
+        Ok(self.summarize(
+            manifest
+                .into_iter()
+                .map(|entry| (entry.path, entry.backup.is_some())),
+        ))
+    }
+
+    /// The files the staged changes would create, update, and trash if committed now.
+    pub fn preview(&self) -> anyhow::Result<AppliedChanges> {
+        let existing = self
+            .changes
+            .iter()
+            .map(|change| {
+                Ok((
+                    change.path().to_path_buf(),
+                    self.target.exists(change.path())?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(self.summarize(existing.into_iter()))
+    }
+
+    /// The files the staged changes would actually change if committed now, leaving out those
+    /// rewritten with the contents they already have.
+    pub fn drift(&self) -> anyhow::Result<AppliedChanges> {
+        let mut drift = AppliedChanges::default();
+        for change in &self.changes {
+            let path = change.path();
+            let exists = self.target.exists(path)?;
+            let contents = match change {
+                StagedChange::Write { contents, .. } => Some(contents.as_bytes()),
+                StagedChange::Attachment { contents, .. } => Some(contents.as_slice()),
+                StagedChange::Trash { .. } => None,
+            };
+
+            match (contents, exists) {
+                (Some(_), false) => drift.created.push(path.to_path_buf()),
+                (Some(contents), true) if self.target.read(path)? != contents => {
+                    drift.updated.push(path.to_path_buf())
+                }
+                (None, true) => drift.trashed.push(path.to_path_buf()),
+                _ => {}
+            }
+        }
+
+        // A replaced note is trashed and then rewritten, so only changes if its contents do
+        let written = self
+            .changes
+            .iter()
+            .filter(|c| !matches!(c, StagedChange::Trash { .. }))
+            .map(StagedChange::path)
+            .collect::<Vec<_>>();
+        drift
+            .trashed
+            .retain(|path| !written.contains(&path.as_path()));
+        drift.created.sort();
+        drift.created.dedup();
+        drift.updated.sort();
+        drift.updated.dedup();
+
+        Ok(drift)
+    }
+
+    /// Sort the staged changes into the files created, updated, and trashed, given the path of
+    /// each and whether it existed beforehand.
+    fn summarize(&self, existing: impl Iterator<Item = (PathBuf, bool)>) -> AppliedChanges {
+        let mut applied = AppliedChanges::default();
+        for (change, (path, existed)) in self.changes.iter().zip(existing) {
+            match (change, existed) {
+                (StagedChange::Write { .. } | StagedChange::Attachment { .. }, false) => {
+                    applied.created.push(path)
+                }
+                (StagedChange::Write { .. } | StagedChange::Attachment { .. }, true) => {
+                    applied.updated.push(path)
+                }
+                (StagedChange::Trash { .. }, true) => applied.trashed.push(path),
+                (StagedChange::Trash { .. }, false) => {}
+            }
+        }
+
+        // A replaced note is trashed and then rewritten, which is an update of the same file
+        applied
+            .trashed
+            .retain(|path| !applied.updated.contains(path));
+        applied.created.sort();
+        applied.created.dedup();
+        applied.updated.sort();
+        applied.updated.dedup();
+
+        applied
+    }
+
+    /// Copy every file the staged changes touch into the backup folder, then record them in the
+    /// manifest which marks a transaction as in flight.
+    fn back_up(&self) -> anyhow::Result<Vec<BackupEntry>> {
+        std::fs::create_dir_all(&self.backup_root)?;
+        let mut manifest = Vec::with_capacity(self.changes.len());
+        for (index, change) in self.changes.iter().enumerate() {
+            let path = change.path();
+            let backup = if self.target.exists(path)? {
+                let backup = self.backup_root.join(index.to_string());
+                std::fs::write(&backup, self.target.read(path)?)?;
+                Some(backup)
+            } else {
+                None
+            };
+
+            let created_folders = match change {
+                StagedChange::Trash { .. } => vec![],
+                _ => self.missing_folders(path)?,
+            };
+
+            manifest.push(BackupEntry {
+                path: path.to_path_buf(),
+                backup,
+                trashed_to: None,
+                created_folders,
+            });
+        }
+
+        self.write_manifest(&manifest)?;
+        Ok(manifest)
+    }
+
+    fn write_manifest(&self, manifest: &[BackupEntry]) -> anyhow::Result<()> {
+        std::fs::write(
+            self.backup_root.join(MANIFEST_FILE),
+            serde_json::to_vec(manifest)?,
+        )?;
+        Ok(())
+    }
+
+    fn apply(&self, manifest: &mut [BackupEntry]) -> anyhow::Result<()> {
+        for (index, change) in self.changes.iter().enumerate() {
+            match change {
+                StagedChange::Write { path, contents, .. } => {
+                    debug!("Writing {:?}", path);
+                    self.target
+                        .write(path, contents.as_bytes())
+                        .with_context(|| format!("Failed to write {:?}", path))?;
+                }
+
+                StagedChange::Attachment { path, contents } => {
+                    debug!("Writing attachment {:?}", path);
+                    self.target
+                        .write(path, contents)
+                        .with_context(|| format!("Failed to write {:?}", path))?;
+                }
+
+                StagedChange::Trash { path } => {
+                    if self.target.exists(path)? {
+                        debug!("Moving {:?} to the {:?} trash", path, self.trash_option);
+                        self.trash(manifest, index)
+                            .with_context(|| format!("Failed to trash {:?}", path))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Put every file recorded in the backup manifest back as it was, taking trashed files back
+    /// out of the vault's `.trash` folder and removing the folders created for them, then discard
+    /// the backups. Files sent to the system trash stay there, their contents being restored.
+    fn restore(&self) -> anyhow::Result<()> {
+        let manifest: Vec<BackupEntry> =
+            serde_json::from_reader(std::fs::File::open(self.backup_root.join(MANIFEST_FILE))?)?;
+
+        for entry in &manifest {
+            if let Some(trashed_to) = &entry.trashed_to {
+                if self.target.exists(trashed_to)? {
+                    self.target.remove(trashed_to)?;
+                }
+            }
+
+            match &entry.backup {
+                Some(backup) => self.target.write(&entry.path, &std::fs::read(backup)?)?,
+                None if self.target.exists(&entry.path)? => self.target.remove(&entry.path)?,
+                None => {}
+            }
+        }
+
+        // Deepest first, so a folder's subfolders are gone before it is removed
+        let mut folders = manifest
+            .iter()
+            .flat_map(|entry| &entry.created_folders)
+            .collect::<Vec<_>>();
+        folders.sort_by(|a, b| (b.components().count(), b).cmp(&(a.components().count(), a)));
+        folders.dedup();
+
+        for folder in folders {
+            if !self.target.exists(folder)? {
+                continue;
+            }
+
+            // Something else may have been put in it since
+            if let Err(err) = self.target.remove_folder(folder) {
+                debug!("Leaving folder {:?}: {:#}", folder, err);
+            }
+        }
+
+        info!("Restored {} files from backup", manifest.len());
+        std::fs::remove_dir_all(&self.backup_root)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackupEntry, VaultTransaction, BACKUP_DIR, MANIFEST_FILE};
+    use crate::targets::open_target;
+    use crate::testing::vault;
+    use std::path::{Path, PathBuf};
+
+    fn begin(root: &Path) -> VaultTransaction {
+        VaultTransaction::begin(
+            root,
+            open_target(root, None, None, None, false, false).unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// A vault which trashes into its own `.trash` folder, as Obsidian can be configured to.
+    fn vault_with_local_trash(name: &str) -> PathBuf {
+        let root = vault(name);
+        std::fs::write(
+            root.join(".obsidian/app.json"),
+            r#"{ "trashOption": "local" }"#,
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn a_failed_commit_rolls_back_every_change() {
+        let root = vault_with_local_trash("rollback");
+        let note = root.join("Book.md");
+        std::fs::write(&note, "Before").unwrap();
+        std::fs::write(root.join("Old.md"), "Old").unwrap();
+        // A file where a folder is needed makes the last write fail
+        std::fs::write(root.join("Blocker"), "").unwrap();
+
+        let mut transaction = begin(&root);
+        transaction.stage_file(&note, "After".to_string()).unwrap();
+        transaction.stage_trash(&root.join("Old.md")).unwrap();
+        transaction
+            .stage_file(&root.join("New/Folder/Created.md"), "New".to_string())
+            .unwrap();
+        transaction
+            .stage_file(&root.join("Blocker/Note.md"), "Blocked".to_string())
+            .unwrap();
+
+        assert!(transaction.commit().is_err());
+        assert_eq!(std::fs::read_to_string(&note).unwrap(), "Before");
+        assert_eq!(std::fs::read_to_string(root.join("Old.md")).unwrap(), "Old");
+        assert!(!root.join(".trash").exists());
+        assert!(!root.join("New").exists());
+        assert!(!root.join(BACKUP_DIR).exists());
+
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn an_interrupted_commit_is_restored_by_the_next() {
+        let root = vault_with_local_trash("interrupted");
+        let note = root.join("Book.md");
+        let backup_root = root.join(BACKUP_DIR);
+        std::fs::create_dir_all(&backup_root).unwrap();
+        std::fs::write(backup_root.join("0"), "Before").unwrap();
+        std::fs::write(backup_root.join("1"), "Old").unwrap();
+
+        // A run which crashed after writing one note, trashing another, and creating a third
+        let manifest = vec![
+            BackupEntry {
+                path: PathBuf::from("Book.md"),
+                backup: Some(backup_root.join("0")),
+                trashed_to: None,
+                created_folders: vec![],
+            },
+            BackupEntry {
+                path: PathBuf::from("Old.md"),
+                backup: Some(backup_root.join("1")),
+                trashed_to: Some(PathBuf::from(".trash/Old.md")),
+                created_folders: vec![PathBuf::from(".trash")],
+            },
+            BackupEntry {
+                path: PathBuf::from("New/Created.md"),
+                backup: None,
+                trashed_to: None,
+                created_folders: vec![PathBuf::from("New")],
+            },
+        ];
+        std::fs::write(
+            backup_root.join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(&note, "After").unwrap();
+        std::fs::create_dir_all(root.join(".trash")).unwrap();
+        std::fs::write(root.join(".trash/Old.md"), "Old").unwrap();
+        std::fs::create_dir_all(root.join("New")).unwrap();
+        std::fs::write(root.join("New/Created.md"), "New").unwrap();
+
+        begin(&root);
+        assert_eq!(std::fs::read_to_string(&note).unwrap(), "Before");
+        assert_eq!(std::fs::read_to_string(root.join("Old.md")).unwrap(), "Old");
+        assert!(!root.join(".trash").exists());
+        assert!(!root.join("New").exists());
+        assert!(!backup_root.exists());
+
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    fn metadata() -> serde_yml::Value {
+        serde_yml::from_str("note-kind: readwise").unwrap()
+    }
+
+    #[test]
+    fn empty_files_besides_notes_are_written() {
+        let root = vault("empty-files");
+        let mut transaction = begin(&root);
+        transaction
+            .stage_file(&root.join("Quotes.md"), String::new())
+            .unwrap();
+        transaction
+            .stage_note(&root.join("New.md"), &metadata(), "\n")
+            .unwrap();
+
+        let applied = transaction.commit().unwrap();
+        assert_eq!(applied.created.len(), 2);
+
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn emptying_a_note_with_content_is_refused() {
+        let root = vault("emptying-note");
+        let note = root.join("Book.md");
+        std::fs::write(&note, "---\nnote-kind: readwise\n---\nHighlights\n").unwrap();
+
+        let mut transaction = begin(&root);
+        transaction.stage_note(&note, &metadata(), " \n").unwrap();
+
+        assert!(transaction.commit().is_err());
+        assert!(std::fs::read_to_string(&note)
+            .unwrap()
+            .contains("Highlights"));
+
+        std::fs::remove_dir_all(root).ok();
+    }
+}
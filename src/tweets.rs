@@ -0,0 +1,80 @@
+use crate::readwise::{Book, Highlight};
+use reqwest::Url;
+use serde_json::Value;
+
+fn is_twitter(url: &Url) -> bool {
+    url.host_str().is_some_and(|host| {
+        let host = host.strip_prefix("www.").unwrap_or(host);
+        let host = host.strip_prefix("mobile.").unwrap_or(host);
+        host == "twitter.com" || host == "x.com"
+    })
+}
+
+/// A handle as written in a name or title, e.g. the `user` of `Tweets From @user`.
+fn handle_in(text: &str) -> Option<String> {
+    let (_, rest) = text.split_once('@')?;
+    let handle = rest
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .collect::<String>();
+
+    (!handle.is_empty()).then_some(handle)
+}
+
+/// The handle, without the `@`, of the account a book of tweets is from. Taken from the author
+/// Readwise gives (`@user`), the profile the book links to, or its title.
+pub fn handle(book: &Book) -> Option<String> {
+    let source_url = book
+        .source_url
+        .as_deref()
+        .and_then(|url| Url::parse(url).ok());
+    let from_twitter = book.category.eq_ignore_ascii_case("tweets")
+        || book.source.as_deref() == Some("twitter")
+        || source_url.as_ref().is_some_and(is_twitter);
+
+    if !from_twitter {
+        return None;
+    }
+
+    book.author
+        .as_deref()
+        .filter(|author| author.trim_start().starts_with('@'))
+        .and_then(handle_in)
+        .or_else(|| {
+            source_url
+                .filter(is_twitter)
+                .and_then(|url| Some(url.path_segments()?.next()?.to_string()))
+                .filter(|segment| !segment.is_empty() && segment != "i")
+        })
+        .or_else(|| handle_in(&book.title))
+}
+
+/// Add `handle` and `profile_url` to the template value of a book of tweets.
+pub fn add_book_fields(book: &Book, value: &mut Value) {
+    if let Some(handle) = handle(book) {
+        value["profile_url"] = Value::from(format!("https://x.com/{}", handle));
+        value["handle"] = Value::from(handle);
+    }
+}
+
+/// Add `tweet_url` to the template value of a highlighted tweet: the highlight's url if it is a
+/// tweet, or else one built from the book's handle and the tweet's id.
+pub fn add_tweet_fields(book: &Book, highlight: &Highlight, value: &mut Value) {
+    let url = highlight
+        .url
+        .as_deref()
+        .and_then(|url| Url::parse(url).ok())
+        .filter(|url| is_twitter(url) && url.path().contains("/status/"))
+        .map(|url| url.to_string())
+        .or_else(|| {
+            let id = highlight
+                .external_id
+                .as_deref()
+                .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))?;
+            Some(format!("https://x.com/{}/status/{}", handle(book)?, id))
+        });
+
+    if let Some(url) = url {
+        value["tweet_url"] = Value::from(url);
+    }
+}
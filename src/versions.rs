@@ -0,0 +1,70 @@
+use crate::articles;
+use crate::readwise::Highlight;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The text and note of a highlight before it was edited in Readwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighlightVersion {
+    pub text: String,
+    pub note: String,
+    /// When this version was last updated in Readwise
+    pub updated: String,
+    /// When a fetch found it had been replaced by an edit
+    pub replaced_at: DateTime<Utc>,
+}
+
+/// The versions of highlights in `previous` whose text or note differ in `current`, keyed by
+/// highlight id.
+pub fn edited(previous: &[Highlight], current: &[Highlight]) -> Vec<(i32, HighlightVersion)> {
+    let previous = previous
+        .iter()
+        .map(|h| (h.id, h))
+        .collect::<HashMap<_, _>>();
+    let replaced_at = Utc::now();
+
+    current
+        .iter()
+        .filter_map(|highlight| {
+            let old = previous.get(&highlight.id)?;
+            if old.text == highlight.text && old.note == highlight.note {
+                return None;
+            }
+
+            Some((
+                highlight.id,
+                HighlightVersion {
+                    text: old.text.clone(),
+                    note: old.note.clone(),
+                    updated: old.updated.clone(),
+                    replaced_at,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// A collapsed callout for each previous version of a highlight, the most recent first.
+pub fn previous_version_callouts(versions: &[HighlightVersion]) -> String {
+    versions
+        .iter()
+        .rev()
+        .map(|version| {
+            let mut body = version.text.trim().to_string();
+            if !version.note.trim().is_empty() {
+                body = format!("{}\n\n---\n{}", body, version.note.trim());
+            }
+
+            articles::collapsed_callout(
+                "quote",
+                &format!(
+                    "Previous version, replaced {}",
+                    version.replaced_at.format("%Y-%m-%d")
+                ),
+                &body,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
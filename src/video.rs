@@ -0,0 +1,80 @@
+use crate::podcasts::offset_seconds;
+use crate::readwise::{Book, Highlight};
+use reqwest::Url;
+use serde_json::{json, Value};
+
+fn is_youtube(url: &Url) -> bool {
+    url.host_str().is_some_and(|host| {
+        host == "youtu.be" || host == "youtube.com" || host.ends_with(".youtube.com")
+    })
+}
+
+/// Seconds into the video from a `t` parameter such as `t=95`, `t=95s`, or `t=1m35s`.
+fn time_parameter(url: &Url) -> Option<u32> {
+    let (_, t) = url.query_pairs().find(|(key, _)| key == "t")?;
+
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in t.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' | 'm' | 's' => {
+                let unit = match c {
+                    'h' => 3600,
+                    'm' => 60,
+                    _ => 1,
+                };
+                seconds += number.parse::<u32>().ok()? * unit;
+                number.clear();
+            }
+            _ => return None,
+        }
+    }
+
+    if !number.is_empty() {
+        seconds += number.parse::<u32>().ok()?;
+    }
+
+    Some(seconds)
+}
+
+/// Add `video_url_at_time` to the template value of a highlight on a YouTube video: the video's
+/// url with a `t` parameter for the moment of the highlight, taken from the highlight's time
+/// offset, its url, or a timestamp in its text.
+pub fn add_video_fields(book: &Book, highlight: &Highlight, value: &mut Value) {
+    let Some(mut video_url) = book
+        .source_url
+        .as_deref()
+        .and_then(|url| Url::parse(url).ok())
+        .filter(is_youtube)
+    else {
+        return;
+    };
+
+    let seconds = highlight
+        .url
+        .as_deref()
+        .and_then(|url| Url::parse(url).ok())
+        .filter(is_youtube)
+        .and_then(|url| time_parameter(&url))
+        .or_else(|| offset_seconds(highlight));
+
+    let Some(seconds) = seconds else {
+        return;
+    };
+
+    let query = video_url
+        .query_pairs()
+        .filter(|(key, _)| key != "t")
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+    video_url
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("t", &format!("{}s", seconds));
+
+    if let Some(object) = value.as_object_mut() {
+        object.insert("video_url_at_time".to_string(), json!(video_url.as_str()));
+    }
+}
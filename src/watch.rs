@@ -0,0 +1,252 @@
+use crate::adopt::{linked_note, read_note};
+use crate::note_exports::{content_hash, NoteExport};
+use crate::transaction::{render_note, VaultTransaction};
+use crate::{targets, ExportCommand, Exporter, Library};
+use chrono::Utc;
+use clap::ValueEnum;
+use notify::{Event, RecursiveMode, Watcher};
+use obsidian_rust_interface::joining::strategies::TypeAndKey;
+use obsidian_rust_interface::Vault;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How long the vault has to be quiet before a burst of changes is checked, so a note being moved
+/// isn't mistaken for one being deleted halfway through.
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// What happens to a book whose note is removed by hand.
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+pub enum RemovalPolicy {
+    /// Write a deleted note again, and restore the frontmatter of a note it was removed from
+    Recreate,
+
+    /// Stop exporting the book, recording it as excluded in the library cache, and leave any note
+    /// whose frontmatter was removed as the user's own
+    Exclude,
+}
+
+/// How a book's note was removed by hand.
+enum Removal {
+    /// The note was deleted, and not moved elsewhere in the vault
+    Deleted,
+
+    /// The note is still there, but no longer has the frontmatter linking it to the book
+    Unlinked(PathBuf),
+}
+
+impl Removal {
+    fn describe(&self) -> &'static str {
+        match self {
+            Removal::Deleted => "deleted",
+            Removal::Unlinked(_) => "unlinked from it",
+        }
+    }
+}
+
+/// Check the recorded notes among the changed paths, following any which were moved and returning
+/// the books whose notes were removed.
+fn find_removals(
+    library: &mut Library,
+    vault_root: &Path,
+    changed: &HashSet<PathBuf>,
+) -> anyhow::Result<Vec<(i32, Removal)>> {
+    let mut removals = vec![];
+    let mut missing = vec![];
+
+    for (book_id, export) in &library.note_exports {
+        let path = vault_root.join(&export.path);
+        if !changed.contains(&path) {
+            continue;
+        }
+
+        if !path.is_file() {
+            missing.push(*book_id);
+            continue;
+        }
+
+        match read_note(&path) {
+            Ok((metadata, _)) => {
+                let linked = metadata
+                    .get("__readwise_fk")
+                    .and_then(|fk| fk.as_i64())
+                    .is_some_and(|fk| fk == *book_id as i64);
+
+                if !linked {
+                    removals.push((*book_id, Removal::Unlinked(path)));
+                }
+            }
+
+            // Most likely saved halfway through an edit, it will be checked again when fixed
+            Err(err) => warn!("Couldn't read {:?}, leaving it for now: {:#}", path, err),
+        }
+    }
+
+    if missing.is_empty() {
+        return Ok(removals);
+    }
+
+    // A note which was moved or renamed is still the book's note
+    let notes = obsidian_rust_interface::joining::find_by::<_, i32>(
+        &Vault::open(vault_root),
+        &TypeAndKey {
+            type_key: "note-kind".to_string(),
+            note_type: "readwise".to_string(),
+            id_key: "__readwise_fk".to_string(),
+        },
+    );
+
+    for book_id in missing {
+        match notes.get(&book_id) {
+            Some(note) => {
+                let path = note.to_path_buf();
+                info!("Note for book {} was moved to {:?}", book_id, path);
+                if let Some(export) = library.note_exports.get_mut(&book_id) {
+                    export.path = path.strip_prefix(vault_root)?.to_path_buf();
+                }
+            }
+
+            None => removals.push((book_id, Removal::Deleted)),
+        }
+    }
+
+    Ok(removals)
+}
+
+/// Recreate or exclude the books whose notes were removed, as the policy says.
+fn apply(
+    mut library: Library,
+    cmd: &ExportCommand,
+    policy: RemovalPolicy,
+    removals: Vec<(i32, Removal)>,
+) -> anyhow::Result<Library> {
+    let mut transaction = VaultTransaction::begin(
+        &cmd.vault,
+        targets::open_target(&cmd.vault, None, None, None, false, false)?,
+    )?;
+    let mut deleted = false;
+
+    for (book_id, removal) in removals {
+        match (policy, removal) {
+            (RemovalPolicy::Exclude, removal) => {
+                info!(
+                    "Excluding book {} as its note was {}",
+                    book_id,
+                    removal.describe()
+                );
+                library.note_exports.remove(&book_id);
+                library.excluded_books.insert(book_id, Utc::now());
+            }
+
+            (RemovalPolicy::Recreate, Removal::Unlinked(path)) => {
+                info!(
+                    "Restoring the frontmatter of {:?} for book {}",
+                    path, book_id
+                );
+                let (metadata, contents) = linked_note(&path, book_id)?;
+                transaction.stage_note(&path, &metadata, &contents)?;
+                library.note_exports.insert(
+                    book_id,
+                    NoteExport {
+                        path: path.strip_prefix(&cmd.vault)?.to_path_buf(),
+                        hash: content_hash(&render_note(&metadata, &contents)?),
+                        exported_at: Utc::now(),
+                    },
+                );
+            }
+
+            (RemovalPolicy::Recreate, Removal::Deleted) => {
+                info!("Recreating the deleted note for book {}", book_id);
+                library.note_exports.remove(&book_id);
+                deleted = true;
+            }
+        }
+    }
+
+    transaction.commit()?;
+
+    if !deleted {
+        return Ok(library);
+    }
+
+    let mut exporter = Exporter::new(library, cmd)?;
+    exporter.export()?;
+    exporter.transaction.commit()?;
+
+    for failure in &exporter.failures {
+        warn!(
+            "Failed to export book {} ({}): {}",
+            failure.id,
+            failure.title.as_deref().unwrap_or_default(),
+            failure.reason
+        );
+    }
+
+    Ok(exporter.library)
+}
+
+fn record_paths(event: notify::Result<Event>, changed: &mut HashSet<PathBuf>) {
+    match event {
+        Ok(event) => changed.extend(event.paths),
+        Err(err) => warn!("Error watching the vault: {}", err),
+    }
+}
+
+/// Recreate or exclude the books whose notes were removed by `changed`, and record the notes which
+/// were moved, saving the library if anything changed.
+fn handle_changes(
+    library_path: &Path,
+    durable: bool,
+    cmd: &ExportCommand,
+    policy: RemovalPolicy,
+    changed: &HashSet<PathBuf>,
+) -> anyhow::Result<()> {
+    let mut library: Library = serde_json::from_reader(std::fs::File::open(library_path)?)?;
+    let recorded = library.note_exports.clone();
+    let removals = find_removals(&mut library, &cmd.vault, changed)?;
+
+    let moved = library
+        .note_exports
+        .iter()
+        .any(|(id, export)| recorded.get(id).is_some_and(|r| r.path != export.path));
+
+    if removals.is_empty() && !moved {
+        return Ok(());
+    }
+
+    let library = apply(library, cmd, policy, removals)?;
+    library.save(library_path, durable)
+}
+
+/// Watch the base folder until interrupted, recreating or excluding the books whose notes are
+/// removed by hand. A batch of changes which fails to be handled is logged, and watching carries
+/// on with the next.
+pub fn watch(
+    library_path: &Path,
+    durable: bool,
+    cmd: &ExportCommand,
+    policy: RemovalPolicy,
+) -> anyhow::Result<()> {
+    let root = cmd.vault.join(&cmd.base_folder);
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+    info!("Watching {:?} for notes removed by hand", root);
+
+    loop {
+        let mut changed = HashSet::new();
+        record_paths(rx.recv()?, &mut changed);
+        while let Ok(event) = rx.recv_timeout(SETTLE) {
+            record_paths(event, &mut changed);
+        }
+
+        debug!("{} paths changed", changed.len());
+
+        if let Err(err) = handle_changes(library_path, durable, cmd, policy, &changed) {
+            warn!("Failed to handle changes to the vault: {:#}", err);
+        }
+    }
+}